@@ -15,7 +15,7 @@ pub fn benchmark(c: &mut Criterion) {
     let filepath = Path::new("benches/data/coalesce-bench-single-3848571113.json");
     let grid_entries = load_simple_grids_from_json(&filepath).unwrap();
     let store_single_rc = Rc::new(create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
         entries: grid_entries,
     }]));
 
@@ -26,11 +26,14 @@ pub fn benchmark(c: &mut Criterion) {
             weight: 1.,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 1,
+                lang_set: LangSet::from(1),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 14,
             mask: 1 << 0,
+            radius_override: None,
         };
         let stack = vec![subquery.clone()];
         let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
@@ -45,11 +48,14 @@ pub fn benchmark(c: &mut Criterion) {
             weight: 1.,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 1,
+                lang_set: LangSet::from(1),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 14,
             mask: 1 << 0,
+            radius_override: None,
         };
         let stack = vec![subquery.clone()];
         let match_opts = MatchOpts {
@@ -68,11 +74,14 @@ pub fn benchmark(c: &mut Criterion) {
             weight: 1.,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 1,
+                lang_set: LangSet::from(1),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 14,
             mask: 1 << 0,
+            radius_override: None,
         };
         let stack = vec![subquery.clone()];
         let match_opts =
@@ -85,14 +94,14 @@ pub fn benchmark(c: &mut Criterion) {
     let filepath = Path::new("benches/data/coalesce-bench-multi-1965155344.json");
     let grid_entries = load_simple_grids_from_json(&filepath).unwrap();
     let store_multi1_rc = Rc::new(create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
         entries: grid_entries,
     }]));
 
     let filepath = Path::new("benches/data/coalesce-bench-multi-3848571113.json");
     let grid_entries = load_simple_grids_from_json(&filepath).unwrap();
     let store_multi2_rc = Rc::new(create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 2, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
         entries: grid_entries,
     }]));
 
@@ -105,22 +114,28 @@ pub fn benchmark(c: &mut Criterion) {
                 weight: 0.25,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 0,
                 zoom: 12,
                 mask: 1 << 0,
+                radius_override: None,
             },
             PhrasematchSubquery {
                 store: store_multi2.borrow(),
                 weight: 0.75,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 1,
                 zoom: 12,
                 mask: 1 << 1,
+                radius_override: None,
             },
         ];
 
@@ -137,22 +152,28 @@ pub fn benchmark(c: &mut Criterion) {
                 weight: 0.25,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 0,
                 zoom: 12,
                 mask: 1 << 0,
+                radius_override: None,
             },
             PhrasematchSubquery {
                 store: store_multi2.borrow(),
                 weight: 0.75,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 1,
                 zoom: 12,
                 mask: 1 << 1,
+                radius_override: None,
             },
         ];
 
@@ -173,22 +194,28 @@ pub fn benchmark(c: &mut Criterion) {
                 weight: 0.25,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 0,
                 zoom: 12,
                 mask: 1 << 0,
+                radius_override: None,
             },
             PhrasematchSubquery {
                 store: store_multi2.borrow(),
                 weight: 0.75,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 1,
                 zoom: 12,
                 mask: 1 << 1,
+                radius_override: None,
             },
         ];
 
@@ -206,22 +233,28 @@ pub fn benchmark(c: &mut Criterion) {
                 weight: 0.25,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 1,
                 zoom: 12,
                 mask: 1 << 0,
+                radius_override: None,
             },
             PhrasematchSubquery {
                 store: store_multi2.borrow(),
                 weight: 0.75,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 2,
                 zoom: 14,
                 mask: 1 << 1,
+                radius_override: None,
             },
         ];
 
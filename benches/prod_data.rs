@@ -1,3 +1,4 @@
+use std::env;
 use std::fs::File;
 use std::io::{self, BufRead};
 
@@ -57,6 +58,21 @@ pub fn benchmark(c: &mut Criterion) {
         })
     }));
 
+    // any of the ljson.lz4 stack dumps that `prepare_coalesce_stacks` knows how to fetch
+    if let Ok(fixture) = env::var("COALESCE_BENCH_FIXTURE") {
+        to_bench.push(Fun::new("coalesce_fixture", move |b: &mut Bencher, _i| {
+            let stacks = prepare_coalesce_stacks(&fixture);
+            assert!(!stacks.is_empty(), "fixture {} contains no stacks", fixture);
+
+            let mut cycle = stacks.iter().cycle();
+
+            b.iter(|| {
+                let (stack, opts) = cycle.next().unwrap();
+                coalesce(stack.clone(), opts).unwrap();
+            })
+        }));
+    }
+
     let eur_records = Lazy::new(|| {
         let dl_path =
             ensure_downloaded("europen-place-both-740ed51f45-d775d2eb65.gridstore.dat.lz4");
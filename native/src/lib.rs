@@ -1,14 +1,14 @@
 use carmen_core::gridstore::coalesce;
 use carmen_core::gridstore::PhrasematchSubquery;
 use carmen_core::gridstore::{
-    CoalesceContext, GridEntry, GridKey, GridStore, GridStoreBuilder, MatchOpts, MatchKey,
+    CoalesceContext, GridEntry, GridKey, GridStore, GridStoreBuilder, GridStoreError, LangSet,
+    MatchOpts, MatchKey,
 };
 
 use neon::prelude::*;
 use neon::{class_definition, declare_types, impl_managed, register_module};
 use neon_serde::errors::Result as LibResult;
 use owning_ref::OwningHandle;
-use failure::Error;
 
 use std::sync::Arc;
 
@@ -44,7 +44,7 @@ impl Task for CoalesceTask {
     }
 }
 
-type KeyIterator = OwningHandle<ArcGridStore, Box<dyn Iterator<Item=Result<GridKey, Error>>>>;
+type KeyIterator = OwningHandle<ArcGridStore, Box<dyn Iterator<Item=Result<GridKey, GridStoreError>>>>;
 
 declare_types! {
     pub class JsGridStoreBuilder as JsGridStoreBuilder for Option<GridStoreBuilder> {
@@ -110,20 +110,21 @@ declare_types! {
 
         method compactAppend(mut cx) {
             let grid_key = cx.argument::<JsObject>(0)?;
-            let phrase_id: u32 = grid_key
+            let js_phrase_id = grid_key
                 .get(&mut cx, "phrase_id")?
                 .downcast::<JsNumber>()
-                .or_throw(&mut cx)?
-                .value() as u32;
+                .or_throw(&mut cx)?;
+            let phrase_id = js_number_to_u32(&mut cx, js_phrase_id, "phrase_id")?;
 
             let js_lang_set = grid_key.get(&mut cx, "lang_set")?;
-            let lang_set: u128 = langarray_to_langset(&mut cx, js_lang_set)?;
+            let lang_set: LangSet = langarray_to_langset(&mut cx, js_lang_set)?;
 
             let key = GridKey { phrase_id, lang_set };
 
             let relev = cx.argument::<JsNumber>(1)?.value() as f64;
             let score = cx.argument::<JsNumber>(2)?.value() as u8;
-            let id = cx.argument::<JsNumber>(3)?.value() as u32;
+            let js_id = cx.argument::<JsNumber>(3)?;
+            let id = js_number_to_u32(&mut cx, js_id, "id")?;
             let source_phrase_hash = cx.argument::<JsNumber>(4)?.value() as u8;
             let js_coords = cx.argument::<JsValue>(5)?;
             let coords: Vec<(u16, u16)> = neon_serde::from_value(&mut cx, js_coords)?;
@@ -250,14 +251,14 @@ declare_types! {
         method get(mut cx) {
             let grid_key = cx.argument::<JsObject>(0)?;
 
-            let phrase_id: u32 = grid_key
+            let js_phrase_id = grid_key
                 .get(&mut cx, "phrase_id")?
                 .downcast::<JsNumber>()
-                .or_throw(&mut cx)?
-                .value() as u32;
+                .or_throw(&mut cx)?;
+            let phrase_id = js_number_to_u32(&mut cx, js_phrase_id, "phrase_id")?;
 
             let js_lang_set = grid_key.get(&mut cx, "lang_set")?;
-            let lang_set: u128 = langarray_to_langset(&mut cx, js_lang_set)?;
+            let lang_set: LangSet = langarray_to_langset(&mut cx, js_lang_set)?;
 
             let key = GridKey { phrase_id, lang_set };
 
@@ -292,7 +293,7 @@ declare_types! {
                 // this is per the OwningHandle docs -- the handle keeps both the arc and the
                 // iterator, so the former is guaranteed to be around as long as the latter
                 let gridstore = unsafe { &*gs };
-                let iter: Box<dyn Iterator<Item=Result<GridKey, Error>>> = Box::new(gridstore.keys());
+                let iter: Box<dyn Iterator<Item=Result<GridKey, GridStoreError>>> = Box::new(gridstore.keys());
                 iter
             }))
         }
@@ -324,7 +325,7 @@ declare_types! {
                     js_gk.set(&mut cx, phrase_id_label, phrase_id_value)?;
 
                     let lang_set_label = JsString::new(&mut cx, "lang_set");
-                    let lang_set_value = langset_to_langarray(&mut cx, gk.lang_set);
+                    let lang_set_value = langset_to_langarray(&mut cx, gk.lang_set)?;
                     js_gk.set(&mut cx, lang_set_label, lang_set_value)?;
 
                     Ok(out.upcast())
@@ -344,43 +345,61 @@ declare_types! {
     }
 }
 
-fn langarray_to_langset<'j, C>(cx: &mut C, maybe_lang_array: Handle<'j, JsValue>) -> Result<u128, neon_serde::errors::Error>
+fn langarray_to_langset<'j, C>(cx: &mut C, maybe_lang_array: Handle<'j, JsValue>) -> Result<LangSet, neon_serde::errors::Error>
 where
     C: Context<'j>,
 {
     if let Ok(lang_array) = maybe_lang_array.downcast::<JsArray>() {
-        let mut out = 0u128;
+        let mut langs: Vec<u32> = Vec::with_capacity(lang_array.len() as usize);
         for i in 0..lang_array.len() {
-            let converted_lang_array = lang_array.get(cx, i)?.downcast::<JsNumber>().or_throw(cx)?.value() as usize;
-            if  converted_lang_array >= 128 {
-                continue;
-            } else {
-            out = out
-                | (1 << converted_lang_array);
-            }
+            let js_lang = lang_array.get(cx, i)?.downcast::<JsNumber>().or_throw(cx)?;
+            langs.push(js_number_to_u32(cx, js_lang, "language id")?);
         }
+        let out = match carmen_core::gridstore::langarray_to_langset(&langs) {
+            Ok(out) => out,
+            Err(e) => cx.throw_type_error(e.to_string())?,
+        };
         Ok(out)
     } else if let Ok(_) = maybe_lang_array.downcast::<JsNull>() {
-        Ok(std::u128::MAX)
+        Ok(LangSet::all())
     } else if let Ok(_) = maybe_lang_array.downcast::<JsUndefined>() {
-        Ok(std::u128::MAX)
+        Ok(LangSet::all())
     } else {
         cx.throw_type_error("Expected array, undefined, or null for lang_set")?
     }
 }
 
-fn langset_to_langarray<'j, C: Context<'j>>(cx: &mut C, lang_set: u128) -> Handle<'j, JsArray> {
+/// Reads a JS number that has to be a whole number in `u32` range, throwing a TypeError naming
+/// `what` otherwise rather than letting `as` truncate or saturate it
+fn js_number_to_u32<'j, C: Context<'j>>(
+    cx: &mut C,
+    number: Handle<'j, JsNumber>,
+    what: &str,
+) -> NeonResult<u32> {
+    let value = number.value();
+    if value.fract() == 0. && value >= 0. && value <= f64::from(std::u32::MAX) {
+        Ok(value as u32)
+    } else {
+        cx.throw_type_error(format!(
+            "{} must be a whole number from 0 to {}, got {}",
+            what,
+            std::u32::MAX,
+            value
+        ))
+    }
+}
+
+fn langset_to_langarray<'j, C: Context<'j>>(cx: &mut C, lang_set: LangSet) -> JsResult<'j, JsArray> {
     let out = JsArray::new(cx, 0);
     let mut i = 0;
-    for j in 0..128 {
-        let bit = 1u128 << j;
-        if lang_set & bit != 0 {
+    for j in 0..128u8 {
+        if lang_set.contains(j) {
             let num = JsNumber::new(cx, j);
-            out.set(cx, i, num).expect("failed to set array slot");
+            out.set(cx, i, num)?;
             i += 1;
         }
     }
-    out
+    Ok(out)
 }
 
 pub fn js_coalesce(mut cx: FunctionContext) -> JsResult<JsUndefined> {
@@ -421,20 +440,27 @@ where
         let idx = js_phrasematch.get(cx, "idx")?;
         let zoom = js_phrasematch.get(cx, "zoom")?;
         let mask = js_phrasematch.get(cx, "mask")?;
+        let radius_override = js_phrasematch.get(cx, "radius_override")?;
 
         let match_key = js_phrasematch.get(cx, "match_key")?.downcast::<JsObject>().or_throw(cx)?;
         let match_phrase = match_key.get(cx, "match_phrase")?;
 
         let js_lang_set = match_key.get(cx, "lang_set")?;
-        let lang_set: u128 = langarray_to_langset(cx, js_lang_set)?;
+        let lang_set: LangSet = langarray_to_langset(cx, js_lang_set)?;
 
         let subq = PhrasematchSubquery {
             store: gridstore,
             weight: neon_serde::from_value(cx, weight)?,
-            match_key: MatchKey { match_phrase: neon_serde::from_value(cx, match_phrase)?, lang_set },
+            match_key: MatchKey {
+                match_phrase: neon_serde::from_value(cx, match_phrase)?,
+                lang_set,
+                lang_fallbacks: vec![],
+                ignore_language: false,
+            },
             idx: neon_serde::from_value(cx, idx)?,
             zoom: neon_serde::from_value(cx, zoom)?,
             mask: neon_serde::from_value(cx, mask)?,
+            radius_override: neon_serde::from_value(cx, radius_override)?,
         };
         phrasematches.push(subq);
     }
@@ -446,14 +472,11 @@ fn prep_for_insert<'j, T: neon::object::This>(cx: &mut CallContext<'j, T>) -> Re
     let grid_key = cx.argument::<JsObject>(0)?;
     let grid_entry = cx.argument::<JsValue>(1)?;
     let values: Vec<GridEntry> = neon_serde::from_value(cx, grid_entry)?;
-    let phrase_id: u32 = grid_key
-        .get(cx, "phrase_id")?
-        .downcast::<JsNumber>()
-        .or_throw(cx)?
-        .value() as u32;
+    let js_phrase_id = grid_key.get(cx, "phrase_id")?.downcast::<JsNumber>().or_throw(cx)?;
+    let phrase_id = js_number_to_u32(cx, js_phrase_id, "phrase_id")?;
 
     let js_lang_set = grid_key.get(cx, "lang_set")?;
-    let lang_set: u128 = langarray_to_langset(cx, js_lang_set)?;
+    let lang_set: LangSet = langarray_to_langset(cx, js_lang_set)?;
 
     let key = GridKey { phrase_id, lang_set };
 
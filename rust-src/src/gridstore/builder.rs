@@ -1,11 +1,16 @@
 use std::collections::hash_map::Entry as HmEntry;
 use std::collections::{btree_map::Entry, BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::JoinHandle;
 
 use failure::{Error, Fail};
+use integer_encoding::VarInt;
 use itertools::Itertools;
 use morton::interleave_morton;
-use rocksdb::{Options, DB};
+#[cfg(test)]
+use rocksdb::DBCompressionType;
+use rocksdb::{Options, WriteBatch, WriteOptions, DB};
 use smallvec::{smallvec, SmallVec};
 
 use crate::gridstore::common::*;
@@ -17,8 +22,21 @@ pub struct GridStoreBuilder {
     path: PathBuf,
     data: BTreeMap<GridKey, BuilderEntry>,
     bin_boundaries: Vec<u32>,
+    entries_per_phrase: usize,
+    durable_flush_interval: Option<usize>,
+    key_ordering: KeyOrdering,
 }
 
+/// Key under which a durable build records how many records it has committed so far; it's
+/// removed in the same write as the last records, so it only exists in partial builds
+const PROGRESS_KEY: &[u8] = b"~PROGRESS";
+
+/// How many records `finish_resumable` commits at a time if durable writes weren't configured
+const DEFAULT_DURABLE_FLUSH_INTERVAL: usize = 10_000;
+
+/// How many records can be waiting in an `insert_sink` channel before senders block
+const INSERT_SINK_CAPACITY: usize = 1024;
+
 /// Extends a BuildEntry with the given values.
 fn extend_entries(builder_entry: &mut BuilderEntry, values: Vec<GridEntry>) -> () {
     for (rs, rs_values) in somewhat_eager_groupby(values.into_iter(), |value| {
@@ -53,10 +71,10 @@ fn copy_entries(source_entry: &BuilderEntry, destination_entry: &mut BuilderEntr
     }
 }
 
-fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
+fn get_encoded_value(value: &BuilderEntry) -> Result<Vec<u8>, Error> {
     let mut builder = gridstore_format::Writer::new();
 
-    let mut items: Vec<(_, _)> = value.into_iter().collect();
+    let mut items: Vec<(_, _)> = value.iter().collect();
     items.sort_by(|(relevance_score_a, _), (relevance_score_b, _)| {
         relevance_score_b.cmp(&relevance_score_a)
     });
@@ -66,12 +84,13 @@ fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
     let mut id_lists: HashMap<_, gridstore_format::FixedVecOffset<u32>> = HashMap::new();
 
     for (relevance_score, coord_group) in items.into_iter() {
-        let mut inner_items: Vec<(_, _)> = coord_group.into_iter().collect();
+        let mut inner_items: Vec<(_, _)> = coord_group.iter().collect();
         inner_items.sort_by(|(coord_a, _), (coord_b, _)| coord_b.cmp(&coord_a));
 
         let mut coords: Vec<_> = Vec::with_capacity(inner_items.len());
 
-        for (coord, mut ids) in inner_items.into_iter() {
+        for (coord, ids) in inner_items.into_iter() {
+            let mut ids = ids.clone();
             // reverse sort
             ids.sort_by(|id_a, id_b| id_b.cmp(id_a));
             ids.dedup();
@@ -79,12 +98,12 @@ fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
             let encoded_ids =
                 id_lists.entry(ids.clone()).or_insert_with(|| builder.write_fixed_vec(&ids));
 
-            let encoded_coord = gridstore_format::Coord { coord, ids: encoded_ids.clone() };
+            let encoded_coord = gridstore_format::Coord { coord: *coord, ids: encoded_ids.clone() };
             coords.push(encoded_coord);
         }
         let encoded_coords = builder.write_uniform_vec(&coords);
         let encoded_relevance_score =
-            gridstore_format::RelevScore { relev_score: relevance_score, coords: encoded_coords };
+            gridstore_format::RelevScore { relev_score: *relevance_score, coords: encoded_coords };
         relevance_scores.push(encoded_relevance_score);
     }
 
@@ -98,24 +117,39 @@ fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
 
 impl GridStoreBuilder {
     /// Makes a new GridStoreBuilder with a particular filename.
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, GridStoreError> {
         Ok(GridStoreBuilder {
             path: path.as_ref().to_owned(),
             data: BTreeMap::new(),
             bin_boundaries: Vec::new(),
+            entries_per_phrase: 0,
+            durable_flush_interval: None,
+            key_ordering: KeyOrdering::default(),
         })
     }
 
-    /// Inserts a new GridStore entry with the given values.
-    pub fn insert(&mut self, key: &GridKey, values: Vec<GridEntry>) -> Result<(), Error> {
+    /// Hints that roughly `phrases` keys holding `total_entries` grids in total are about to be
+    /// added with `compact_append`, which sizes the coordinate maps it creates for the average
+    /// entry count up front. `insert` and `append` don't use the hint.
+    pub fn reserve(&mut self, phrases: usize, total_entries: usize) {
+        if phrases > 0 {
+            self.entries_per_phrase = (total_entries + phrases - 1) / phrases;
+        }
+    }
+
+    /// Inserts a new GridStore entry with the given values, replacing any already held for `key`.
+    /// Grids that share an id, source phrase hash, coordinates, relev and score are stored once.
+    pub fn insert(&mut self, key: &GridKey, values: Vec<GridEntry>) -> Result<(), GridStoreError> {
         let mut to_insert = BuilderEntry::new();
         extend_entries(&mut to_insert, values);
         self.data.insert(key.to_owned(), to_insert);
         Ok(())
     }
 
-    ///  Appends a values to and existing GridStore entry.
-    pub fn append(&mut self, key: &GridKey, values: Vec<GridEntry>) -> Result<(), Error> {
+    /// Adds values to the entry for `key`, creating it if there isn't one yet. The finished record
+    /// is the same as if all of the key's values had been passed to a single `insert`: they're
+    /// sorted the same way, and grids already held for the key aren't stored twice.
+    pub fn append(&mut self, key: &GridKey, values: Vec<GridEntry>) -> Result<(), GridStoreError> {
         let mut to_append = self.data.entry(key.to_owned()).or_insert_with(|| BuilderEntry::new());
         extend_entries(&mut to_append, values);
         Ok(())
@@ -135,8 +169,9 @@ impl GridStoreBuilder {
 
         let relev_score = (relev_float_to_int(relev) << 4) | score;
         let id_hash = smallvec![(id << 8) | (source_phrase_hash as u32)];
+        let capacity = coords.len().max(self.entries_per_phrase);
         let relevance_score_entry =
-            to_append.entry(relev_score).or_insert_with(|| HashMap::with_capacity(coords.len()));
+            to_append.entry(relev_score).or_insert_with(|| HashMap::with_capacity(capacity));
         for pair in coords {
             let zcoord = interleave_morton(pair.0, pair.1);
             match relevance_score_entry.entry(zcoord) {
@@ -150,94 +185,331 @@ impl GridStoreBuilder {
         }
     }
 
-    /// In situations under which data has been inserted using temporary phrase IDs, renumber
-    /// the data in the index to use final phrase IDs, given a temporary-to-final-ID mapping
-    pub fn renumber(&mut self, tmp_phrase_ids_to_ids: &[u32]) -> Result<(), Error> {
+    /// Renumbers the phrase IDs of everything inserted so far, replacing each ID with its
+    /// position-indexed target in `tmp_phrase_ids_to_ids`. The map needs an entry for every
+    /// inserted phrase ID, and no two IDs may map to the same target.
+    pub fn renumber(&mut self, tmp_phrase_ids_to_ids: &[u32]) -> Result<(), GridStoreError> {
+        self.renumber_with_options(tmp_phrase_ids_to_ids, false)
+    }
+
+    /// Like `renumber`, but if `allow_collisions` is set, several IDs may map to the same target,
+    /// and the entries of any keys that end up the same are merged. The map is validated before
+    /// anything is changed, so on error the builder is left as it was.
+    pub fn renumber_with_options(
+        &mut self,
+        tmp_phrase_ids_to_ids: &[u32],
+        allow_collisions: bool,
+    ) -> Result<(), GridStoreError> {
+        if let Some(max_key) = self.data.keys().next_back() {
+            if max_key.phrase_id as usize >= tmp_phrase_ids_to_ids.len() {
+                return Err(Error::from(BuildError::OutOfBoundsRenumberEntry {
+                    tmp_id: max_key.phrase_id,
+                    map_len: tmp_phrase_ids_to_ids.len(),
+                })
+                .into());
+            }
+        }
+        if !allow_collisions {
+            let mut targets: HashMap<u32, u32> =
+                HashMap::with_capacity(tmp_phrase_ids_to_ids.len());
+            for (tmp_id, target_id) in tmp_phrase_ids_to_ids.iter().enumerate() {
+                if let Some(other_tmp_id) = targets.insert(*target_id, tmp_id as u32) {
+                    return Err(Error::from(BuildError::DuplicateRenumberEntry {
+                        target_id: *target_id,
+                        first_tmp_id: other_tmp_id,
+                        second_tmp_id: tmp_id as u32,
+                    })
+                    .into());
+                }
+            }
+        }
+
         let mut old_data: BTreeMap<GridKey, BuilderEntry> = BTreeMap::new();
         std::mem::swap(&mut old_data, &mut self.data);
 
         for (key, value) in old_data.into_iter() {
-            let new_phrase_id = tmp_phrase_ids_to_ids
-                .get(key.phrase_id as usize)
-                .ok_or_else(|| BuildError::OutOfBoundsRenumberEntry { tmp_id: key.phrase_id })?;
-            let new_key = GridKey { phrase_id: *new_phrase_id, ..key };
+            let new_phrase_id = tmp_phrase_ids_to_ids[key.phrase_id as usize];
+            let new_key = GridKey { phrase_id: new_phrase_id, ..key };
             match self.data.entry(new_key) {
                 Entry::Vacant(v) => {
                     v.insert(value);
                 }
-                Entry::Occupied(_) => {
-                    return Err(Error::from(BuildError::DuplicateRenumberEntry {
-                        target_id: *new_phrase_id,
-                    }))
+                Entry::Occupied(mut o) => {
+                    copy_entries(&value, o.get_mut());
                 }
             };
         }
         Ok(())
     }
 
-    pub fn load_bin_boundaries(&mut self, bin_boundaries: Vec<u32>) -> Result<(), Error> {
+    pub fn load_bin_boundaries(&mut self, bin_boundaries: Vec<u32>) -> Result<(), GridStoreError> {
         self.bin_boundaries = bin_boundaries;
         Ok(())
     }
 
+    /// Like `load_bin_boundaries`, but takes boundaries in the binary form written by
+    /// `encode_bin_boundaries`
+    pub fn load_bin_boundaries_binary(&mut self, data: &[u8]) -> Result<(), GridStoreError> {
+        let bin_boundaries = decode_bin_boundaries(data)?;
+        self.load_bin_boundaries(bin_boundaries)
+    }
+
+    /// Estimates how many bytes `finish` will write, by encoding everything accumulated so far
+    /// without touching rocksdb. Only the encoded keys and values are counted, not any rocksdb
+    /// overhead or compression, so treat it as a ballpark figure.
+    pub fn estimate_size(&self) -> Result<usize, GridStoreError> {
+        let mut size = 0;
+        write_records(self.data.iter(), &self.bin_boundaries, |db_key, db_data| {
+            size += db_key.len() + db_data.len();
+            Ok(())
+        })?;
+        Ok(size)
+    }
+
+    /// Moves the builder onto a background thread that `append`s every record sent to the
+    /// returned sender, then `finish`es the store once every clone of the sender has been
+    /// dropped. A key sent more than once keeps the grids from every send. The channel is bounded, so producers block rather than pile up records faster
+    /// than they can be inserted. The handle yields the result of the whole build; if an insert
+    /// fails, the thread stops taking records and later sends fail.
+    pub fn insert_sink(
+        mut self,
+    ) -> (SyncSender<(GridKey, Vec<GridEntry>)>, JoinHandle<Result<(), GridStoreError>>) {
+        let (sender, receiver) = sync_channel::<(GridKey, Vec<GridEntry>)>(INSERT_SINK_CAPACITY);
+        let handle = std::thread::spawn(move || {
+            for (key, values) in receiver {
+                self.append(&key, values)?;
+            }
+            self.finish()
+        });
+        (sender, handle)
+    }
+
+    /// Makes `finish` write through rocksdb's write-ahead log in synced batches of
+    /// `flush_interval` records, flushing after each one and recording how far it got, so a build
+    /// that dies partway through can be picked up again with `finish_resumable`. This is much
+    /// slower than the default bulk write, so it's only worth it for very large builds.
+    pub fn enable_durable_writes(&mut self, flush_interval: usize) {
+        self.durable_flush_interval = Some(flush_interval.max(1));
+    }
+
+    /// Sorts the finished store's keys with `key_ordering` rather than by phrase first. The store
+    /// then has to be opened with the same ordering in its `GridStoreOpenOptions`.
+    pub fn set_key_ordering(&mut self, key_ordering: KeyOrdering) {
+        self.key_ordering = key_ordering;
+    }
+
     /// Writes data to disk.
-    pub fn finish(self) -> Result<(), Error> {
+    pub fn finish(self) -> Result<(), GridStoreError> {
         let mut opts = Options::default();
         opts.set_disable_auto_compactions(true);
         opts.create_if_missing(true);
+        self.key_ordering.configure(&mut opts);
 
         let db = DB::open(&opts, &self.path)?;
-        let mut db_key: Vec<u8> = Vec::with_capacity(MAX_KEY_LENGTH);
-
-        let mut bin_seq = self.bin_boundaries.iter().cloned().peekable();
-        let mut current_bin = None;
-        let mut next_boundary = 0u32;
-        let grouped = somewhat_eager_groupby(self.data.into_iter(), |(key, _value)| {
-            while key.phrase_id >= next_boundary {
-                current_bin = bin_seq.next();
-                next_boundary = *(bin_seq.peek().unwrap_or(&std::u32::MAX));
+        match self.durable_flush_interval {
+            Some(flush_interval) => {
+                write_durably(&db, &self.data, &self.bin_boundaries, flush_interval, 0)?;
+            }
+            None => {
+                write_records(self.data.iter(), &self.bin_boundaries, |db_key, db_data| {
+                    db.put(db_key, db_data)?;
+                    Ok(())
+                })?;
             }
+        }
 
-            current_bin
-        });
+        db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        drop(db);
+        Ok(())
+    }
 
-        for (group_id, group_value) in grouped {
-            let mut lang_set_map: HashMap<u128, BuilderEntry> = HashMap::new();
+    /// Like `finish` with durable writes enabled, but if the directory holds a partial durable
+    /// build, picks up after the last records it committed instead of starting over. The builder
+    /// must hold the same data and bin boundaries as the interrupted one did. If the directory
+    /// already holds a finished store, nothing is written.
+    pub fn finish_resumable(self) -> Result<(), GridStoreError> {
+        let mut opts = Options::default();
+        opts.set_disable_auto_compactions(true);
+        opts.create_if_missing(true);
+        self.key_ordering.configure(&mut opts);
 
-            for (grid_key, value) in group_value.into_iter() {
-                // figure out the key
-                db_key.clear();
-                grid_key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
-
-                let mut grouped_entry =
-                    lang_set_map.entry(grid_key.lang_set).or_insert_with(|| BuilderEntry::new());
-                copy_entries(&value, &mut grouped_entry);
-                // figure out the value
-                let db_data = get_encoded_value(value)?;
-                db.put(&db_key, &db_data)?;
-            }
-            if let Some(group_id) = group_id {
-                for (lang_set, builder_entry) in lang_set_map.into_iter() {
-                    db_key.clear();
-                    let group_key = GridKey { phrase_id: group_id, lang_set };
-                    group_key.write_to(TypeMarker::PrefixBin, &mut db_key)?;
-                    let grouped_db_data = get_encoded_value(builder_entry)?;
-                    db.put(&db_key, &grouped_db_data)?;
-                }
+        let db = DB::open(&opts, &self.path)?;
+        let committed = match db.get(PROGRESS_KEY)? {
+            Some(progress) => decode_progress(&progress)?,
+            None if db.get("~BOUNDS")?.is_some() => return Ok(()),
+            None => 0,
+        };
+        let flush_interval = self.durable_flush_interval.unwrap_or(DEFAULT_DURABLE_FLUSH_INTERVAL);
+        write_durably(&db, &self.data, &self.bin_boundaries, flush_interval, committed)?;
+
+        db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        drop(db);
+        Ok(())
+    }
+}
+
+/// Writes the records for `data` in synced batches of `flush_interval`, skipping the first
+/// `committed` of them, which an earlier attempt already wrote. Each batch also records the
+/// number of records written so far, and the last one clears that marker.
+fn write_durably(
+    db: &DB,
+    data: &BTreeMap<GridKey, BuilderEntry>,
+    bin_boundaries: &[u32],
+    flush_interval: usize,
+    committed: u64,
+) -> Result<(), Error> {
+    let mut write_opts = WriteOptions::default();
+    write_opts.set_sync(true);
+
+    let mut written = 0u64;
+    let mut batch = WriteBatch::default();
+    let mut batch_len = 0;
+    write_records(data.iter(), bin_boundaries, |db_key, db_data| {
+        written += 1;
+        if written <= committed {
+            return Ok(());
+        }
+        batch.put(db_key, db_data)?;
+        batch_len += 1;
+        if batch_len >= flush_interval {
+            batch.put(PROGRESS_KEY, &written.to_le_bytes())?;
+            db.write_opt(std::mem::replace(&mut batch, WriteBatch::default()), &write_opts)?;
+            db.flush()?;
+            batch_len = 0;
+        }
+        Ok(())
+    })?;
+
+    batch.delete(PROGRESS_KEY)?;
+    db.write_opt(batch, &write_opts)?;
+    db.flush()?;
+    Ok(())
+}
+
+fn decode_progress(data: &[u8]) -> Result<u64, GridStoreError> {
+    let bytes: [u8; 8] = match data {
+        [a, b, c, d, e, f, g, h] => [*a, *b, *c, *d, *e, *f, *g, *h],
+        _ => {
+            return Err(GridStoreError::Decode {
+                what: "build progress marker",
+                reason: format!("expected 8 bytes, found {}", data.len()),
+            })
+        }
+    };
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// First byte of binary-encoded bin boundaries; JSON can't start with it, so loaders can use it
+/// to tell the two formats apart
+pub const BINARY_BOUNDARIES_MAGIC: u8 = 0xB0;
+
+/// Encodes ascending bin boundaries compactly: the magic byte followed by the difference between
+/// each boundary and the one before it (starting from 0) as a varint.
+pub fn encode_bin_boundaries(bin_boundaries: &[u32]) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(1 + bin_boundaries.len() * 2);
+    out.push(BINARY_BOUNDARIES_MAGIC);
+    let mut previous = 0u32;
+    let mut buf = [0u8; 10];
+    for boundary in bin_boundaries {
+        if *boundary < previous {
+            return Err(BuildError::DescendingBinBoundary { boundary: *boundary, previous }.into());
+        }
+        let len = (boundary - previous).encode_var(&mut buf);
+        out.extend_from_slice(&buf[..len]);
+        previous = *boundary;
+    }
+    Ok(out)
+}
+
+/// Decodes bin boundaries written by `encode_bin_boundaries`
+pub fn decode_bin_boundaries(data: &[u8]) -> Result<Vec<u32>, Error> {
+    let malformed = |reason| Error::from(BuildError::MalformedBinBoundaries { reason });
+    if data.first() != Some(&BINARY_BOUNDARIES_MAGIC) {
+        return Err(malformed("missing magic byte"));
+    }
+    let mut bin_boundaries = Vec::new();
+    let mut previous = 0u32;
+    let mut offset = 1;
+    while offset < data.len() {
+        // a varint ends with a byte without its high bit set, and a u32 takes at most 5 bytes
+        let mut delta = 0u64;
+        for shift in (0..5).map(|i| i * 7) {
+            let byte = *data.get(offset).ok_or_else(|| malformed("truncated varint"))?;
+            offset += 1;
+            delta |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            } else if shift == 28 {
+                return Err(malformed("varint longer than 5 bytes"));
             }
         }
+        if delta > u64::from(std::u32::MAX) {
+            return Err(malformed("oversized varint"));
+        }
+        previous = previous
+            .checked_add(delta as u32)
+            .ok_or_else(|| malformed("boundary overflows u32"))?;
+        bin_boundaries.push(previous);
+    }
+    Ok(bin_boundaries)
+}
 
-        // bake the prefix boundaries
-        let mut encoded_boundaries: Vec<u8> = Vec::with_capacity(self.bin_boundaries.len() * 4);
-        for boundary in self.bin_boundaries {
-            encoded_boundaries.extend_from_slice(&boundary.to_le_bytes());
+/// Encodes the given records, along with the prefix bin records and bin boundaries, passing each
+/// resulting db key and value to `write` in the order they should be written.
+fn write_records<'a, I, F>(data: I, bin_boundaries: &[u32], mut write: F) -> Result<(), Error>
+where
+    I: Iterator<Item = (&'a GridKey, &'a BuilderEntry)>,
+    F: FnMut(&[u8], &[u8]) -> Result<(), Error>,
+{
+    let mut db_key: Vec<u8> = Vec::with_capacity(MAX_KEY_LENGTH);
+
+    let mut bin_seq = bin_boundaries.iter().cloned().peekable();
+    let mut current_bin = None;
+    let mut next_boundary = 0u32;
+    let grouped = somewhat_eager_groupby(data, |(key, _value)| {
+        while key.phrase_id >= next_boundary {
+            current_bin = bin_seq.next();
+            next_boundary = *(bin_seq.peek().unwrap_or(&std::u32::MAX));
         }
-        db.put("~BOUNDS", &encoded_boundaries)?;
 
-        db.compact_range(None::<&[u8]>, None::<&[u8]>);
-        drop(db);
-        Ok(())
+        current_bin
+    });
+
+    for (group_id, group_value) in grouped {
+        // a BTreeMap, so the prefix bins are written in the same order on every run; resumed
+        // durable builds skip records by count
+        let mut lang_set_map: BTreeMap<LangSet, BuilderEntry> = BTreeMap::new();
+
+        for (grid_key, value) in group_value.into_iter() {
+            // figure out the key
+            db_key.clear();
+            grid_key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
+
+            let mut grouped_entry =
+                lang_set_map.entry(grid_key.lang_set).or_insert_with(|| BuilderEntry::new());
+            copy_entries(value, &mut grouped_entry);
+            // figure out the value
+            let db_data = get_encoded_value(value)?;
+            write(&db_key, &db_data)?;
+        }
+        if let Some(group_id) = group_id {
+            for (lang_set, builder_entry) in lang_set_map.into_iter() {
+                db_key.clear();
+                let group_key = GridKey { phrase_id: group_id, lang_set };
+                group_key.write_to(TypeMarker::PrefixBin, &mut db_key)?;
+                let grouped_db_data = get_encoded_value(&builder_entry)?;
+                write(&db_key, &grouped_db_data)?;
+            }
+        }
+    }
+
+    // bake the prefix boundaries
+    let mut encoded_boundaries: Vec<u8> = Vec::with_capacity(bin_boundaries.len() * 4);
+    for boundary in bin_boundaries {
+        encoded_boundaries.extend_from_slice(&boundary.to_le_bytes());
     }
+    write(b"~BOUNDS", &encoded_boundaries)?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -268,7 +540,7 @@ fn insert_test() {
     let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
     let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
     builder
         .insert(
@@ -296,7 +568,7 @@ fn append_test() {
     let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
     let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
     builder
         .insert(
@@ -325,12 +597,61 @@ fn append_test() {
     builder.finish().unwrap();
 }
 
+#[test]
+fn append_matches_combined_insert_test() {
+    let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+    let grid = |id, x, relev, score, source_phrase_hash| GridEntry {
+        id,
+        x,
+        y: 1,
+        relev,
+        score,
+        source_phrase_hash,
+    };
+    let first = vec![grid(1, 1, 1., 7, 0), grid(2, 2, 0.8, 3, 0), grid(3, 1, 1., 7, 1)];
+    // repeats two of the first grids exactly, and one with a different score
+    let second = vec![
+        grid(2, 2, 0.8, 3, 0),
+        grid(1, 1, 1., 7, 0),
+        grid(1, 1, 1., 5, 0),
+        grid(4, 3, 0.6, 3, 2),
+    ];
+
+    let appended_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut builder = GridStoreBuilder::new(appended_dir.path()).unwrap();
+    builder.insert(&key, first.clone()).unwrap();
+    builder.append(&key, second.clone()).unwrap();
+    builder.finish().unwrap();
+
+    let combined_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut builder = GridStoreBuilder::new(combined_dir.path()).unwrap();
+    builder.insert(&key, first.into_iter().chain(second.into_iter()).collect()).unwrap();
+    builder.finish().unwrap();
+
+    let read = |path: &Path| -> Vec<GridEntry> {
+        crate::gridstore::GridStore::new(path).unwrap().get(&key).unwrap().unwrap().collect()
+    };
+    let appended = read(appended_dir.path());
+    assert_eq!(appended, read(combined_dir.path()));
+    assert_eq!(
+        appended,
+        vec![
+            grid(3, 1, 1., 7, 1),
+            grid(1, 1, 1., 7, 0),
+            grid(1, 1, 1., 5, 0),
+            grid(2, 2, 0.8, 3, 0),
+            grid(4, 3, 0.6, 3, 2),
+        ],
+        "exact duplicates are stored once"
+    );
+}
+
 #[test]
 fn compact_append_test() {
     let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
     let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
     builder
         .insert(
@@ -346,10 +667,314 @@ fn compact_append_test() {
     builder.finish().unwrap();
 }
 
+#[test]
+fn reserve_test() {
+    let reserved_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let plain_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut reserved = GridStoreBuilder::new(reserved_dir.path()).unwrap();
+    let mut plain = GridStoreBuilder::new(plain_dir.path()).unwrap();
+
+    reserved.reserve(10, 100);
+    reserved.reserve(0, 100);
+    for builder in [&mut reserved, &mut plain].iter_mut() {
+        for phrase_id in 0..10u32 {
+            let key = GridKey { phrase_id, lang_set: LangSet::from(1) };
+            let coords: Vec<(u16, u16)> = (0..10u16).map(|i| (i, phrase_id as u16)).collect();
+            builder.compact_append(&key, 1., 3, phrase_id, 0, &coords);
+        }
+    }
+
+    assert_eq!(reserved.data, plain.data, "Reserving doesn't change what gets built");
+    reserved.finish().unwrap();
+    plain.finish().unwrap();
+
+    let reserved = crate::gridstore::GridStore::new(reserved_dir.path()).unwrap();
+    let plain = crate::gridstore::GridStore::new(plain_dir.path()).unwrap();
+    let reserved_records: Vec<_> = reserved.iter().map(|r| r.unwrap()).collect();
+    let plain_records: Vec<_> = plain.iter().map(|r| r.unwrap()).collect();
+    assert_eq!(reserved_records.len(), 10);
+    assert_eq!(reserved_records, plain_records, "Both stores hold the same records");
+}
+
+#[test]
+fn estimate_size_test() {
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+    assert!(builder.estimate_size().unwrap() > 0, "Even an empty store has its boundaries");
+
+    for phrase_id in 0..500u32 {
+        let key = GridKey { phrase_id, lang_set: LangSet::from(1) };
+        let entries = (0..20u32)
+            .map(|i| {
+                let n = phrase_id * 20 + i;
+                GridEntry {
+                    id: n.wrapping_mul(2_654_435_761) >> 8,
+                    x: (n.wrapping_mul(40_503) % 65_536) as u16,
+                    y: (n.wrapping_mul(7_919) % 65_536) as u16,
+                    relev: 1.,
+                    score: (n % 8) as u8,
+                    source_phrase_hash: (n % 256) as u8,
+                }
+            })
+            .collect();
+        builder.insert(&key, entries).expect("Unable to insert record");
+    }
+    builder.load_bin_boundaries(vec![0, 250, 500]).unwrap();
+
+    let estimate = builder.estimate_size().unwrap();
+    assert_eq!(estimate, builder.estimate_size().unwrap(), "Estimating doesn't change the data");
+
+    // write the same records the way `finish` does, but uncompressed, so the comparison doesn't
+    // depend on how well rocksdb compresses them
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.set_disable_auto_compactions(true);
+    opts.set_compression_type(DBCompressionType::None);
+    let db = DB::open(&opts, directory.path()).unwrap();
+    write_records(builder.data.iter(), &builder.bin_boundaries, |db_key, db_data| {
+        db.put(db_key, db_data)?;
+        Ok(())
+    })
+    .unwrap();
+    db.compact_range(None::<&[u8]>, None::<&[u8]>);
+    let actual = db.property_int_value("rocksdb.total-sst-files-size").unwrap().unwrap();
+    let ratio = estimate as f64 / actual as f64;
+    assert!(
+        ratio > 0.67 && ratio < 1.5,
+        "estimate {} is close to actual size {}",
+        estimate,
+        actual
+    );
+}
+
+#[test]
+fn binary_bin_boundaries_test() {
+    // a large set of ascending boundaries, with gaps of all sizes
+    let mut boundaries: Vec<u32> = Vec::new();
+    let mut boundary = 0u32;
+    for i in 0..5000u32 {
+        boundaries.push(boundary);
+        boundary += (i * i) % 70_000;
+    }
+    boundaries.push(std::u32::MAX);
+
+    let encoded = encode_bin_boundaries(&boundaries).unwrap();
+    assert_eq!(encoded[0], BINARY_BOUNDARIES_MAGIC);
+    assert!(encoded.len() < serde_json::to_vec(&boundaries).unwrap().len() / 2);
+    assert_eq!(decode_bin_boundaries(&encoded).unwrap(), boundaries);
+
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut from_json = GridStoreBuilder::new(directory.path()).unwrap();
+    let mut from_binary = GridStoreBuilder::new(directory.path()).unwrap();
+    from_json
+        .load_bin_boundaries(
+            serde_json::from_slice(&serde_json::to_vec(&boundaries).unwrap()).unwrap(),
+        )
+        .unwrap();
+    from_binary.load_bin_boundaries_binary(&encoded).unwrap();
+    assert_eq!(from_binary.bin_boundaries, from_json.bin_boundaries);
+
+    assert_eq!(decode_bin_boundaries(&[BINARY_BOUNDARIES_MAGIC]).unwrap(), Vec::<u32>::new());
+    assert!(encode_bin_boundaries(&[5, 3]).is_err(), "descending boundaries are rejected");
+    assert!(decode_bin_boundaries(b"[1,2]").is_err(), "JSON isn't mistaken for binary");
+    assert!(decode_bin_boundaries(&encoded[..encoded.len() - 1]).is_err(), "truncated data fails");
+    let mut overlong = vec![BINARY_BOUNDARIES_MAGIC];
+    overlong.extend_from_slice(&[0xff; 12]);
+    assert!(decode_bin_boundaries(&overlong).is_err(), "overlong varints fail without panicking");
+    let oversized = [BINARY_BOUNDARIES_MAGIC, 0xff, 0xff, 0xff, 0xff, 0x7f];
+    assert!(decode_bin_boundaries(&oversized).is_err(), "varints past u32::MAX fail");
+}
+
+#[test]
+fn renumber_validation_test() {
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+    for phrase_id in 0..3 {
+        let key = GridKey { phrase_id, lang_set: LangSet::from(1) };
+        let entries = vec![GridEntry {
+            id: phrase_id,
+            x: 1,
+            y: 1,
+            relev: 1.,
+            score: 7,
+            source_phrase_hash: 0,
+        }];
+        builder.insert(&key, entries).expect("Unable to insert record");
+    }
+
+    let err = builder.renumber(&[2, 1]).unwrap_err();
+    assert_eq!(err.to_string(), "out of bounds: 2 is past the end of the 2-entry map");
+
+    let err = builder.renumber(&[2, 1, 2]).unwrap_err();
+    assert_eq!(err.to_string(), "duplicate rename entry: 2 is the target of both 0 and 2");
+
+    // failed renumbers leave the builder untouched
+    let phrase_ids: Vec<_> = builder.data.keys().map(|key| key.phrase_id).collect();
+    assert_eq!(phrase_ids, [0, 1, 2]);
+
+    // collisions can be explicitly allowed, in which case the entries are merged
+    builder.renumber_with_options(&[2, 1, 2], true).unwrap();
+    let phrase_ids: Vec<_> = builder.data.keys().map(|key| key.phrase_id).collect();
+    assert_eq!(phrase_ids, [1, 2]);
+    let merged = builder.data.get(&GridKey { phrase_id: 2, lang_set: LangSet::from(1) }).unwrap();
+    let merged_ids: Vec<_> = merged[&0x37].values().flat_map(|ids| ids.iter()).collect();
+    assert_eq!(merged_ids.len(), 2);
+    builder.finish().unwrap();
+}
+
+#[test]
+fn insert_sink_test() {
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let builder = GridStoreBuilder::new(directory.path()).unwrap();
+    let (sender, handle) = builder.insert_sink();
+
+    let producer = std::thread::spawn(move || {
+        for phrase_id in 0..2000u32 {
+            let key = GridKey { phrase_id, lang_set: LangSet::from(1) };
+            let entries = vec![GridEntry {
+                id: phrase_id,
+                x: 1,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            sender.send((key, entries)).unwrap();
+        }
+        // sending a key again adds to its grids rather than replacing them
+        let key = GridKey { phrase_id: 0, lang_set: LangSet::from(1) };
+        let entries =
+            vec![GridEntry { id: 2000, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }];
+        sender.send((key, entries)).unwrap();
+    });
+    producer.join().unwrap();
+    handle.join().unwrap().unwrap();
+
+    let store = crate::gridstore::GridStore::new(directory.path()).unwrap();
+    let records: Vec<_> = store.iter().map(|r| r.unwrap()).collect();
+    assert_eq!(records.len(), 2000);
+    for (phrase_id, (key, entries)) in records.iter().enumerate() {
+        assert_eq!(key, &GridKey { phrase_id: phrase_id as u32, lang_set: LangSet::from(1) });
+        let mut ids: Vec<_> = entries.iter().map(|entry| entry.id).collect();
+        ids.sort();
+        if phrase_id == 0 {
+            assert_eq!(ids, [0, 2000]);
+        } else {
+            assert_eq!(ids, [phrase_id as u32]);
+        }
+    }
+}
+
+#[test]
+fn finish_resumable_test() {
+    let make_builder = |path: &Path| {
+        let mut builder = GridStoreBuilder::new(path).unwrap();
+        // several lang sets per bin, so each bin has several prefix bin records
+        for phrase_id in 0..20u32 {
+            let key = GridKey { phrase_id, lang_set: LangSet::from(1 << (phrase_id % 3)) };
+            let entries = vec![GridEntry {
+                id: phrase_id,
+                x: phrase_id as u16,
+                y: 1,
+                relev: 1.,
+                score: 3,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&key, entries).expect("Unable to insert record");
+        }
+        builder.load_bin_boundaries(vec![0, 10, 20]).unwrap();
+        builder
+    };
+    let records = |path: &Path| {
+        let store = crate::gridstore::GridStore::new(path).unwrap();
+        let records: Vec<_> = store.iter().map(|r| r.unwrap()).collect();
+        // a range over whole bins is read from the prefix bin records
+        let range_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 0, end: 20 },
+            lang_set: LangSet::all(),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let mut range_ids: Vec<u32> = store
+            .streaming_get_matching(&range_key, &MatchOpts::default(), MAX_CONTEXTS)
+            .unwrap()
+            .map(|entry| entry.grid_entry.id)
+            .collect();
+        range_ids.sort();
+        (records, store.bin_boundaries(), range_ids)
+    };
+
+    let plain_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    make_builder(plain_dir.path()).finish().unwrap();
+    let expected = records(plain_dir.path());
+    assert_eq!(expected.0.len(), 20);
+    assert_eq!(expected.2, (0..20).collect::<Vec<_>>());
+
+    let durable_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut durable = make_builder(durable_dir.path());
+    durable.enable_durable_writes(3);
+    durable.finish().unwrap();
+    assert_eq!(records(durable_dir.path()), expected, "durable writes build the same store");
+
+    // simulate a build that died after committing the first of a bin's prefix bin records
+    let resumed_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    {
+        let builder = make_builder(resumed_dir.path());
+        let mut all_records = Vec::new();
+        write_records(builder.data.iter(), &builder.bin_boundaries, |db_key, db_data| {
+            all_records.push((db_key.to_vec(), db_data.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        let cut =
+            all_records.iter().position(|(db_key, _)| TypeMarker::PrefixBin.marks(db_key)).unwrap()
+                + 1;
+
+        let db = DB::open_default(resumed_dir.path()).unwrap();
+        for (db_key, db_data) in &all_records[..cut] {
+            db.put(db_key, db_data).unwrap();
+        }
+        db.put(PROGRESS_KEY, &(cut as u64).to_le_bytes()).unwrap();
+    }
+    assert!(
+        crate::gridstore::GridStore::new(resumed_dir.path()).unwrap().iter().count() < 20,
+        "the partial build is missing records"
+    );
+
+    make_builder(resumed_dir.path()).finish_resumable().unwrap();
+    assert_eq!(records(resumed_dir.path()), expected, "resuming finishes the build");
+    {
+        let db = DB::open_default(resumed_dir.path()).unwrap();
+        assert!(db.get(PROGRESS_KEY).unwrap().is_none(), "a finished build has no progress marker");
+    }
+
+    // a finished store is left alone
+    make_builder(resumed_dir.path()).finish_resumable().unwrap();
+    assert_eq!(records(resumed_dir.path()), expected);
+
+    {
+        let db = DB::open_default(resumed_dir.path()).unwrap();
+        db.put(PROGRESS_KEY, b"bad").unwrap();
+    }
+    match make_builder(resumed_dir.path()).finish_resumable() {
+        Err(GridStoreError::Decode { what, .. }) => assert_eq!(what, "build progress marker"),
+        other => panic!("expected a decode error, got {:?}", other),
+    }
+}
+
 #[derive(Debug, Fail)]
 enum BuildError {
-    #[fail(display = "duplicate rename entry: {}", target_id)]
-    DuplicateRenumberEntry { target_id: u32 },
-    #[fail(display = "out of bounds: {}", tmp_id)]
-    OutOfBoundsRenumberEntry { tmp_id: u32 },
+    #[fail(
+        display = "duplicate rename entry: {} is the target of both {} and {}",
+        target_id, first_tmp_id, second_tmp_id
+    )]
+    DuplicateRenumberEntry { target_id: u32, first_tmp_id: u32, second_tmp_id: u32 },
+    #[fail(display = "out of bounds: {} is past the end of the {}-entry map", tmp_id, map_len)]
+    OutOfBoundsRenumberEntry { tmp_id: u32, map_len: usize },
+    #[fail(display = "bin boundary {} is below the one before it, {}", boundary, previous)]
+    DescendingBinBoundary { boundary: u32, previous: u32 },
+    #[fail(display = "malformed binary bin boundaries: {}", reason)]
+    MalformedBinBoundaries { reason: &'static str },
 }
@@ -1,12 +1,9 @@
-use std::borrow::Borrow;
-use std::cmp::Reverse;
+use std::borrow::{Borrow, Cow};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 
 use failure::Error;
-use itertools::Itertools;
-use ordered_float::OrderedFloat;
 
 use crate::gridstore::common::*;
 use crate::gridstore::store::GridStore;
@@ -17,16 +14,82 @@ pub fn coalesce<T: Borrow<GridStore> + Clone + Debug>(
     stack: Vec<PhrasematchSubquery<T>>,
     match_opts: &MatchOpts,
 ) -> Result<Vec<CoalesceContext>, Error> {
+    Ok(coalesce_with_stats(stack, match_opts)?.0)
+}
+
+/// Like `coalesce`, but also reports whether any subquery's matches were cut off at
+/// `MAX_GRIDS_PER_PHRASE`, in which case some relevant grids may be missing from the results
+pub fn coalesce_with_stats<T: Borrow<GridStore> + Clone + Debug>(
+    stack: Vec<PhrasematchSubquery<T>>,
+    match_opts: &MatchOpts,
+) -> Result<(Vec<CoalesceContext>, CoalesceStats), Error> {
+    coalesce_tracked(stack, match_opts, &mut None)
+}
+
+/// Like `coalesce`, but also returns a histogram of the relevance of every candidate context
+/// considered, including the ones cut for falling too far below the best, as
+/// `(bucket lower bound, count)` pairs for the non-empty `HISTOGRAM_BUCKET_SIZE`-wide buckets in
+/// ascending order. With several subqueries, the candidates are the contexts built for the last
+/// subquery and the partial stacks left over from earlier ones. Useful for tuning relevance
+/// cutoffs.
+pub fn coalesce_with_histogram<T: Borrow<GridStore> + Clone + Debug>(
+    stack: Vec<PhrasematchSubquery<T>>,
+    match_opts: &MatchOpts,
+) -> Result<(Vec<CoalesceContext>, Vec<(f64, usize)>), Error> {
+    let mut histogram = Some(RelevanceHistogram::default());
+    let (contexts, _) = coalesce_tracked(stack, match_opts, &mut histogram)?;
+    Ok((contexts, histogram.map(RelevanceHistogram::into_buckets).unwrap_or_default()))
+}
+
+/// Width of the relevance buckets reported by `coalesce_with_histogram`
+pub const HISTOGRAM_BUCKET_SIZE: f64 = 0.04;
+
+#[derive(Default)]
+struct RelevanceHistogram {
+    counts: Vec<usize>,
+}
+
+impl RelevanceHistogram {
+    fn into_buckets(self) -> Vec<(f64, usize)> {
+        self.counts
+            .into_iter()
+            .enumerate()
+            .filter(|(_, count)| *count > 0)
+            .map(|(bucket, count)| (bucket as f64 * HISTOGRAM_BUCKET_SIZE, count))
+            .collect()
+    }
+}
+
+/// Counts a candidate's relevance in the histogram, if one is being kept
+fn record_relevance(histogram: &mut Option<RelevanceHistogram>, relev: f64) {
+    if let Some(histogram) = histogram {
+        // the small offset keeps values on a bucket boundary from being floored into the one below
+        let bucket = ((relev.max(0.) + 1e-9) / HISTOGRAM_BUCKET_SIZE) as usize;
+        if bucket >= histogram.counts.len() {
+            histogram.counts.resize(bucket + 1, 0);
+        }
+        histogram.counts[bucket] += 1;
+    }
+}
+
+fn coalesce_tracked<T: Borrow<GridStore> + Clone + Debug>(
+    stack: Vec<PhrasematchSubquery<T>>,
+    match_opts: &MatchOpts,
+    histogram: &mut Option<RelevanceHistogram>,
+) -> Result<(Vec<CoalesceContext>, CoalesceStats), Error> {
+    let mut stats = CoalesceStats::default();
+    let dedup_by_source_phrase = match_opts.dedup_by_source_phrase && stack.len() <= 1;
     let contexts = if stack.len() <= 1 {
-        coalesce_single(&stack[0], match_opts)?
+        coalesce_single(&stack[0], match_opts, histogram)?
     } else {
-        coalesce_multi(stack, match_opts)?
+        coalesce_multi(stack, match_opts, &mut stats, histogram)?
     };
 
     let mut out = Vec::with_capacity(MAX_CONTEXTS);
     if !contexts.is_empty() {
         let max_relevance = contexts[0].relev;
         let mut sets: HashSet<u64> = HashSet::new();
+        let mut feature_counts: HashMap<u32, usize> = HashMap::new();
         for context in contexts {
             if out.len() >= MAX_CONTEXTS {
                 break;
@@ -35,13 +98,56 @@ pub fn coalesce<T: Borrow<GridStore> + Clone + Debug>(
             if max_relevance - context.relev >= 0.25 {
                 break;
             }
-            let inserted = sets.insert(context.entries[0].tmp_id.into());
+            if match_opts.min_relev.map_or(false, |min_relev| context.relev < min_relev) {
+                break;
+            }
+            // contexts are sorted by relevance, so the first one seen for a key is the best
+            let dedup_key = if match_opts.dedup_by_feature {
+                context.entries[0].grid_entry.id
+            } else {
+                context.entries[0].tmp_id
+            };
+            let dedup_key = if dedup_by_source_phrase {
+                (u64::from(dedup_key) << 8)
+                    | u64::from(context.entries[0].grid_entry.source_phrase_hash)
+            } else {
+                u64::from(dedup_key)
+            };
+            let feature_count = feature_counts.entry(context.entries[0].grid_entry.id).or_insert(0);
+            if match_opts.max_per_feature.map_or(false, |max| *feature_count >= max) {
+                continue;
+            }
+            let inserted = sets.insert(dedup_key);
             if inserted {
+                *feature_count += 1;
                 out.push(context);
             }
         }
     }
-    Ok(out)
+    Ok((out, stats))
+}
+
+/// Like `coalesce`, but hands back the contexts as an iterator in the same final order, for
+/// callers that consume them one at a time. Results are currently still computed up front.
+pub fn coalesce_iter<T: Borrow<GridStore> + Clone + Debug>(
+    stack: Vec<PhrasematchSubquery<T>>,
+    match_opts: &MatchOpts,
+) -> Result<impl Iterator<Item = CoalesceContext>, Error> {
+    Ok(coalesce(stack, match_opts)?.into_iter())
+}
+
+/// Like `coalesce`, but partitions the contexts by their mask, i.e. by which subqueries they
+/// stack. Each group keeps the order the contexts had in the full output, so the first context in
+/// a group is the best one for that mask.
+pub fn coalesce_grouped<T: Borrow<GridStore> + Clone + Debug>(
+    stack: Vec<PhrasematchSubquery<T>>,
+    match_opts: &MatchOpts,
+) -> Result<HashMap<u32, Vec<CoalesceContext>>, Error> {
+    let mut groups: HashMap<u32, Vec<CoalesceContext>> = HashMap::new();
+    for context in coalesce(stack, match_opts)? {
+        groups.entry(context.mask).or_insert_with(Vec::new).push(context);
+    }
+    Ok(groups)
 }
 
 fn grid_to_coalesce_entry<T: Borrow<GridStore> + Clone>(
@@ -57,18 +163,35 @@ fn grid_to_coalesce_entry<T: Borrow<GridStore> + Clone>(
         grid_entry: GridEntry { relev: relevance, ..grid.grid_entry },
         matches_language: grid.matches_language,
         idx: subquery.idx,
-        tmp_id: ((subquery.idx as u32) << 25) + grid.grid_entry.id,
+        tmp_id: match_opts.id_layout.encode(subquery.idx, grid.grid_entry.id),
         mask: subquery.mask,
         distance: grid.distance,
         scoredist: grid.scoredist,
     }
 }
 
+/// Applies a subquery's radius override, if it has one, to the proximity settings in the match
+/// options it'll be run with.
+fn subquery_match_opts<'a, T: Borrow<GridStore> + Clone>(
+    subquery: &PhrasematchSubquery<T>,
+    match_opts: &'a MatchOpts,
+) -> Cow<'a, MatchOpts> {
+    match (subquery.radius_override, &match_opts.proximity) {
+        (Some(radius), Some(proximity)) => Cow::Owned(MatchOpts {
+            proximity: Some(Proximity { radius, ..proximity.clone() }),
+            ..match_opts.clone()
+        }),
+        _ => Cow::Borrowed(match_opts),
+    }
+}
+
 fn coalesce_single<T: Borrow<GridStore> + Clone>(
     subquery: &PhrasematchSubquery<T>,
     match_opts: &MatchOpts,
+    histogram: &mut Option<RelevanceHistogram>,
 ) -> Result<Vec<CoalesceContext>, Error> {
     let bigger_max = 2 * MAX_CONTEXTS;
+    let match_opts = &*subquery_match_opts(subquery, match_opts);
 
     let grids = subquery.store.borrow().streaming_get_matching(
         &subquery.match_key,
@@ -76,19 +199,26 @@ fn coalesce_single<T: Borrow<GridStore> + Clone>(
         bigger_max,
     )?;
     let mut max_relevance: f64 = 0.;
-    let mut previous_id: u32 = 0;
+    let mut previous_id: (u32, u8) = (0, 0);
     let mut previous_relevance: f64 = 0.;
     let mut previous_scoredist: f64 = 0.;
     let mut min_scoredist = std::f64::MAX;
     let mut feature_count: usize = 0;
 
-    let mut coalesced: HashMap<u32, CoalesceEntry> = HashMap::new();
+    // grids are keyed on their feature id, and also on their source phrase if those are to be
+    // kept apart
+    let mut coalesced: HashMap<(u32, u8), CoalesceEntry> = HashMap::new();
+    let coalesce_key = |entry: &CoalesceEntry| {
+        let source_phrase_hash =
+            if match_opts.dedup_by_source_phrase { entry.grid_entry.source_phrase_hash } else { 0 };
+        (entry.grid_entry.id, source_phrase_hash)
+    };
 
     for grid in grids {
         let coalesce_entry = grid_to_coalesce_entry(&grid, subquery, match_opts);
 
         // If it's the same feature as the last one, but a lower scoredist don't add it
-        if previous_id == coalesce_entry.grid_entry.id
+        if previous_id == coalesce_key(&coalesce_entry)
             && coalesce_entry.scoredist <= previous_scoredist
         {
             continue;
@@ -104,6 +234,7 @@ fn coalesce_single<T: Borrow<GridStore> + Clone>(
             }
         }
 
+        record_relevance(histogram, coalesce_entry.grid_entry.relev);
         if max_relevance - coalesce_entry.grid_entry.relev >= 0.25 {
             break;
         }
@@ -112,7 +243,7 @@ fn coalesce_single<T: Borrow<GridStore> + Clone>(
         }
 
         // Save current values before mocing into coalesced
-        let current_id = coalesce_entry.grid_entry.id;
+        let current_id = coalesce_key(&coalesce_entry);
         let current_relev = coalesce_entry.grid_entry.relev;
         let current_scoredist = coalesce_entry.scoredist;
 
@@ -153,15 +284,7 @@ fn coalesce_single<T: Borrow<GridStore> + Clone>(
         })
         .collect();
 
-    contexts.sort_by_key(|context| {
-        Reverse((
-            OrderedFloat(context.relev),
-            OrderedFloat(context.entries[0].scoredist),
-            context.entries[0].grid_entry.x,
-            context.entries[0].grid_entry.y,
-            context.entries[0].grid_entry.id,
-        ))
-    });
+    contexts.sort_by_key(|context| context.sort_key_for(match_opts));
 
     contexts.truncate(MAX_CONTEXTS);
     Ok(contexts)
@@ -170,6 +293,8 @@ fn coalesce_single<T: Borrow<GridStore> + Clone>(
 fn coalesce_multi<T: Borrow<GridStore> + Clone>(
     mut stack: Vec<PhrasematchSubquery<T>>,
     match_opts: &MatchOpts,
+    stats: &mut CoalesceStats,
+    histogram: &mut Option<RelevanceHistogram>,
 ) -> Result<Vec<CoalesceContext>, Error> {
     stack.sort_by_key(|subquery| (subquery.zoom, subquery.idx));
 
@@ -180,34 +305,56 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
 
     let mut zoom_adjusted_match_options = match_opts.clone();
 
+    // A subquery can stack on any zoom up to its own that has a subquery with a different idx.
+    // The stack is sorted by (zoom, idx), so the smallest and largest idx at each zoom are
+    // enough to tell whether a zoom only has the subquery's own idx, without comparing every
+    // pair of subqueries.
+    let mut zoom_idx_bounds: Vec<(u16, u16, u16)> = Vec::new();
+    for subquery in stack.iter() {
+        match zoom_idx_bounds.last_mut() {
+            Some((zoom, _, max_idx)) if *zoom == subquery.zoom => *max_idx = subquery.idx,
+            _ => zoom_idx_bounds.push((subquery.zoom, subquery.idx, subquery.idx)),
+        }
+    }
+    let compatible_zooms_per_subquery: Vec<Vec<u16>> = stack
+        .iter()
+        .map(|subquery| {
+            zoom_idx_bounds
+                .iter()
+                .take_while(|(zoom, _, _)| *zoom <= subquery.zoom)
+                .filter(|(_, min_idx, max_idx)| {
+                    *min_idx != subquery.idx || *max_idx != subquery.idx
+                })
+                .map(|(zoom, _, _)| *zoom)
+                .collect()
+        })
+        .collect();
+
     for (i, subquery) in stack.iter().enumerate() {
         let mut to_add_to_coalesced: HashMap<(u16, u16, u16), Vec<CoalesceContext>> =
             HashMap::new();
-        let compatible_zooms: Vec<u16> = stack
-            .iter()
-            .filter_map(|subquery_b| {
-                if subquery.idx == subquery_b.idx || subquery.zoom < subquery_b.zoom {
-                    None
-                } else {
-                    Some(subquery_b.zoom)
-                }
-            })
-            .dedup()
-            .collect();
+        let compatible_zooms = &compatible_zooms_per_subquery[i];
 
         if zoom_adjusted_match_options.zoom != subquery.zoom {
             zoom_adjusted_match_options = match_opts.adjust_to_zoom(subquery.zoom);
         }
 
+        let subquery_opts = subquery_match_opts(subquery, &zoom_adjusted_match_options);
+
         let grids = subquery.store.borrow().streaming_get_matching(
             &subquery.match_key,
-            &zoom_adjusted_match_options,
+            &subquery_opts,
             MAX_GRIDS_PER_PHRASE,
         )?;
 
-        for grid in grids.take(MAX_GRIDS_PER_PHRASE) {
-            let coalesce_entry =
-                grid_to_coalesce_entry(&grid, subquery, &zoom_adjusted_match_options);
+        // Grids come out best first -- by relevance, then scoredist, so the nearest ones first
+        // when there's a proximity point -- so the cap drops the least useful ones.
+        for (grid_count, grid) in grids.enumerate() {
+            if grid_count == MAX_GRIDS_PER_PHRASE {
+                stats.truncated = true;
+                break;
+            }
+            let coalesce_entry = grid_to_coalesce_entry(&grid, subquery, &subquery_opts);
 
             let zxy = (subquery.zoom, grid.grid_entry.x, grid.grid_entry.y);
 
@@ -261,14 +408,14 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
             }
 
             if i == (stack.len() - 1) {
-                if entries.len() == 1 {
-                    // Slightly penalize contexts that have no stacking
+                let penalized = entries.len() == 1 || entries[0].mask > entries[1].mask;
+                if match_opts.apply_stack_penalties && penalized {
+                    // Slightly penalize contexts that have no stacking, or that are in
+                    // ascending order
                     context_relevance -= 0.01;
-                } else if entries[0].mask > entries[1].mask {
-                    // Slightly penalize contexts in ascending order
-                    context_relevance -= 0.01
                 }
 
+                record_relevance(histogram, context_relevance);
                 if max_relevance - context_relevance < 0.25 {
                     contexts.push(CoalesceContext {
                         entries,
@@ -306,22 +453,14 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
 
     for (_, matched) in coalesced {
         for context in matched {
+            record_relevance(histogram, context.relev);
             if max_relevance - context.relev < 0.25 {
                 contexts.push(context);
             }
         }
     }
 
-    contexts.sort_by_key(|context| {
-        (
-            Reverse(OrderedFloat(context.relev)),
-            Reverse(OrderedFloat(context.entries[0].scoredist)),
-            context.entries[0].idx,
-            Reverse(context.entries[0].grid_entry.x),
-            Reverse(context.entries[0].grid_entry.y),
-            Reverse(context.entries[0].grid_entry.id),
-        )
-    });
+    contexts.sort_by_key(|context| context.sort_key_for(match_opts));
 
     Ok(contexts)
 }
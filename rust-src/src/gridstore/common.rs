@@ -1,83 +1,303 @@
 use std::borrow::Borrow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Arc;
 
+use crate::gridstore::spatial::{self, DistanceUnit, ProximityCombine, ProximityCurve};
 use crate::gridstore::store::GridStore;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use failure::Error;
-use serde::{Deserialize, Serialize, Serializer};
+use failure::{Error, Fail};
+use morton::interleave_morton;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum TypeMarker {
     SinglePhrase = 0,
     PrefixBin = 1,
 }
 
+impl TypeMarker {
+    /// Whether `db_key` is in this marker's section of the store. Keys are sorted by their
+    /// marker byte first, so a scan reaches the end of its section when this turns false.
+    pub fn marks(self, db_key: &[u8]) -> bool {
+        db_key.first() == Some(&(self as u8))
+    }
+}
+
+/// How a store's keys are ordered on disk, which decides the order `GridStore::keys`,
+/// `GridStore::iter` and the like walk them in. It's picked when the store is built, and the
+/// store has to be opened with the same one: rocksdb records the comparator's name and refuses
+/// to open a store with a different one.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyOrdering {
+    /// By phrase ID, then language set; this is rocksdb's default byte order over the keys
+    PhraseFirst,
+    /// By language set, then phrase ID, so each language set's phrases are kept together. Phrase
+    /// range lookups have to seek once per language set in the store, so they're slower.
+    LanguageFirst,
+}
+
+impl Default for KeyOrdering {
+    fn default() -> Self {
+        KeyOrdering::PhraseFirst
+    }
+}
+
+/// Name rocksdb knows the `LanguageFirst` comparator by
+const LANGUAGE_FIRST_COMPARATOR: &str = "carmen.LanguageFirst";
+
+impl KeyOrdering {
+    /// Compares two db keys the way a store with this ordering sorts them
+    pub fn compare(self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            KeyOrdering::PhraseFirst => a.cmp(b),
+            KeyOrdering::LanguageFirst => compare_language_first(a, b),
+        }
+    }
+
+    /// A byte string for `db_key` whose byte order is this ordering, for sorting keys from
+    /// several stores together
+    pub(crate) fn sort_bytes(self, db_key: &[u8]) -> Vec<u8> {
+        match (self, language_first_parts(db_key)) {
+            (KeyOrdering::LanguageFirst, Some((type_marker, lang_set, phrase_id))) => {
+                let mut out = Vec::with_capacity(21);
+                out.push(type_marker);
+                out.extend_from_slice(&lang_set.to_be_bytes());
+                out.extend_from_slice(&phrase_id.to_be_bytes());
+                out
+            }
+            _ => db_key.to_vec(),
+        }
+    }
+
+    /// Sets rocksdb up to sort keys this way
+    pub(crate) fn configure(self, opts: &mut rocksdb::Options) {
+        if self == KeyOrdering::LanguageFirst {
+            opts.set_comparator(LANGUAGE_FIRST_COMPARATOR, compare_language_first);
+        }
+    }
+}
+
+/// The type marker, language set bits and phrase ID of a phrase or prefix bin key, or `None` for
+/// any other key
+fn language_first_parts(db_key: &[u8]) -> Option<(u8, u128, u32)> {
+    if db_key.len() < 5 || db_key.len() > 5 + 16 || db_key[0] > TypeMarker::PrefixBin as u8 {
+        return None;
+    }
+    let phrase_id = (&db_key[1..]).read_u32::<BigEndian>().ok()?;
+    let key_lang_partial = &db_key[5..];
+    // the all-languages key has no language suffix, and sorts after every other language set
+    let lang_set = if key_lang_partial.is_empty() {
+        std::u128::MAX
+    } else {
+        let mut key_lang_full = [0u8; 16];
+        key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
+        u128::from_be_bytes(key_lang_full)
+    };
+    Some((db_key[0], lang_set, phrase_id))
+}
+
+/// Orders phrase and prefix bin keys by type marker, language set and then phrase ID. Other
+/// keys, like the bin boundaries, are compared as bytes, which keeps them after the phrase keys.
+fn compare_language_first(a: &[u8], b: &[u8]) -> Ordering {
+    match (language_first_parts(a), language_first_parts(b)) {
+        (Some(a_parts), Some(b_parts)) => a_parts.cmp(&b_parts),
+        _ => a.cmp(b),
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialOrd, Ord, PartialEq, Eq, Clone)]
 pub struct GridKey {
     pub phrase_id: u32,
-    pub lang_set: u128,
+    pub lang_set: LangSet,
 }
 
+/// A set of language IDs from 0 to 127, stored as a bitfield with bit `n` set for language `n`.
+/// A set with every bit set stands for all languages, including ones no ID has been given to.
+/// It's serialized as the raw bitfield.
+#[derive(
+    Serialize, Deserialize, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Default,
+)]
+#[serde(transparent)]
+pub struct LangSet(u128);
+
+impl LangSet {
+    /// The set that matches every language
+    pub const fn all() -> LangSet {
+        LangSet(std::u128::MAX)
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.0 == std::u128::MAX
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether `lang` is in the set. IDs past 127 never are, unless the set is `all()`.
+    pub fn contains(&self, lang: u8) -> bool {
+        self.is_all() || (lang < 128 && self.0 & (1 << lang) != 0)
+    }
+
+    /// Panics if `lang` is past 127
+    pub fn insert(&mut self, lang: u8) {
+        assert!(lang < 128, "language ID {} doesn't fit in a language set", lang);
+        self.0 |= 1 << lang;
+    }
+
+    pub fn union(&self, other: LangSet) -> LangSet {
+        LangSet(self.0 | other.0)
+    }
+
+    pub fn intersection(&self, other: LangSet) -> LangSet {
+        LangSet(self.0 & other.0)
+    }
+
+    /// Whether the two sets have any language in common
+    pub fn intersects(&self, other: LangSet) -> bool {
+        !self.intersection(other).is_empty()
+    }
+
+    /// The raw bitfield
+    pub fn bits(&self) -> u128 {
+        self.0
+    }
+}
+
+impl From<u128> for LangSet {
+    fn from(bits: u128) -> Self {
+        LangSet(bits)
+    }
+}
+
+impl From<LangSet> for u128 {
+    fn from(lang_set: LangSet) -> Self {
+        lang_set.0
+    }
+}
+
+impl From<&[u32]> for LangSet {
+    /// Panics on IDs past 127; `langarray_to_langset` reports them as an error instead
+    fn from(lang_array: &[u32]) -> Self {
+        let mut lang_set = LangSet::default();
+        for lang in lang_array {
+            assert!(*lang < 128, "language ID {} doesn't fit in a language set", lang);
+            lang_set.insert(*lang as u8);
+        }
+        lang_set
+    }
+}
+
+/// The language set that matches every language. Grids stored under it are stored without a
+/// language suffix on their key.
+pub const ALL_LANGUAGES: LangSet = LangSet::all();
+
 impl GridKey {
+    /// Makes a key for grids of `phrase_id` that apply to every language
+    pub fn all_languages(phrase_id: u32) -> GridKey {
+        GridKey { phrase_id, lang_set: ALL_LANGUAGES }
+    }
+
     pub fn write_to(&self, type_marker: TypeMarker, db_key: &mut Vec<u8>) -> Result<(), Error> {
         db_key.push(type_marker as u8);
         // next goes the ID
         db_key.write_u32::<BigEndian>(self.phrase_id)?;
         // now the language ID
-        match self.lang_set {
-            std::u128::MAX => { /* do nothing -- this is the all-languages marker */ }
-            0 => {
-                db_key.push(0);
+        if self.lang_set.is_all() {
+            // do nothing -- this is the all-languages marker
+        } else if self.lang_set.is_empty() {
+            db_key.push(0);
+        } else {
+            let lang_set = self.lang_set.bits().to_be_bytes();
+            let iter = lang_set.iter().skip_while(|byte| **byte == 0u8);
+            db_key.extend(iter);
+        }
+        Ok(())
+    }
+
+    /// Decodes a db key (as written by `write_to`) back into a `GridKey`. Malformed keys are
+    /// reported as a `KeyError` that includes the offending bytes.
+    pub fn from_bytes(db_key: &[u8]) -> Result<GridKey, Error> {
+        if db_key.len() < 5 {
+            return Err(KeyError::Malformed {
+                key: db_key.to_vec(),
+                reason: "too short to hold a type marker and phrase ID",
             }
-            _ => {
-                let lang_set = self.lang_set.to_be_bytes();
-                let iter = lang_set.iter().skip_while(|byte| **byte == 0u8);
-                db_key.extend(iter);
+            .into());
+        }
+        if db_key.len() > 5 + 16 {
+            return Err(KeyError::Malformed {
+                key: db_key.to_vec(),
+                reason: "language set is longer than 16 bytes",
             }
+            .into());
         }
-        Ok(())
+        let phrase_id = (&db_key[1..]).read_u32::<BigEndian>()?;
+
+        let key_lang_partial = &db_key[5..];
+        let lang_set = if key_lang_partial.len() == 0 {
+            // 0-length language array is the shorthand for "matches everything"
+            ALL_LANGUAGES
+        } else {
+            let mut key_lang_full = [0u8; 16];
+            key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
+
+            LangSet::from((&key_lang_full[..]).read_u128::<BigEndian>()?)
+        };
+
+        Ok(GridKey { phrase_id, lang_set })
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, Ord, PartialEq, Eq, Clone)]
 pub enum MatchPhrase {
     Exact(u32),
-    Range { start: u32, end: u32 },
+    Range {
+        start: u32,
+        end: u32,
+    },
+    /// Any of a set of phrase IDs that needn't be contiguous; an empty set matches nothing
+    Set(Vec<u32>),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, Ord, PartialEq, Eq, Clone)]
 pub struct MatchKey {
     pub match_phrase: MatchPhrase,
-    pub lang_set: u128,
+    pub lang_set: LangSet,
+    /// Language sets to fall back to, in order of preference, for grids stored under languages
+    /// outside `lang_set`. Grids matching an earlier fallback are penalized less than ones
+    /// matching a later one, and all of them less than grids that match no language at all. An
+    /// all-languages set can go last to catch every remaining grid.
+    #[serde(default)]
+    pub lang_fallbacks: Vec<LangSet>,
+    /// Match grids regardless of language: every key matches, with no cross-language penalty
+    /// and no fallbacks applied. Unlike an all-languages `lang_set`, this also matches grids
+    /// stored under an empty language set.
+    #[serde(default)]
+    pub ignore_language: bool,
 }
 
-impl MatchKey {
-    pub fn write_start_to(
-        &self,
-        type_marker: TypeMarker,
-        db_key: &mut Vec<u8>,
-    ) -> Result<(), Error> {
-        db_key.push(type_marker as u8);
-        // next goes the ID
-        let start = match self.match_phrase {
-            MatchPhrase::Exact(phrase_id) => phrase_id,
-            MatchPhrase::Range { start, .. } => start,
-        };
-        db_key.write_u32::<BigEndian>(start)?;
-        Ok(())
-    }
+/// Relevance multiplier for grids that don't match the requested languages
+pub const LANGUAGE_MISMATCH_MULTIPLIER: f64 = 0.96;
 
-    pub fn matches_key(&self, type_marker: TypeMarker, db_key: &[u8]) -> Result<bool, Error> {
-        let key_phrase = (&db_key[1..]).read_u32::<BigEndian>()?;
-        if db_key[0] != (type_marker as u8) {
-            return Ok(false);
+impl MatchKey {
+    /// Makes a key that matches `match_phrase` in every language, with no fallbacks
+    pub fn all_languages(match_phrase: MatchPhrase) -> MatchKey {
+        MatchKey {
+            match_phrase,
+            lang_set: ALL_LANGUAGES,
+            lang_fallbacks: vec![],
+            ignore_language: false,
         }
-        Ok(match self.match_phrase {
-            MatchPhrase::Exact(phrase_id) => phrase_id == key_phrase,
-            MatchPhrase::Range { start, end } => start <= key_phrase && key_phrase < end,
-        })
     }
 
     pub fn matches_language(&self, db_key: &[u8]) -> Result<bool, Error> {
+        if self.ignore_language {
+            return Ok(true);
+        }
         let key_lang_partial = &db_key[5..];
         if key_lang_partial.len() == 0 {
             // 0-length language array is the shorthand for "matches everything"
@@ -87,9 +307,28 @@ impl MatchKey {
         let mut key_lang_full = [0u8; 16];
         key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
 
-        let key_lang_set: u128 = (&key_lang_full[..]).read_u128::<BigEndian>()?;
+        let key_lang_set = LangSet::from((&key_lang_full[..]).read_u128::<BigEndian>()?);
 
-        Ok(self.lang_set & key_lang_set != 0)
+        Ok(self.lang_set.intersects(key_lang_set))
+    }
+
+    /// Works out whether a db key matches `lang_set` or one of `lang_fallbacks`, and the
+    /// multiplier to apply to the relevance of its grids: 1 for `lang_set`, stepping down towards
+    /// `LANGUAGE_MISMATCH_MULTIPLIER` through the fallbacks, and that multiplier for no match.
+    pub fn language_match(&self, db_key: &[u8]) -> Result<(bool, f64), Error> {
+        if self.matches_language(db_key)? {
+            return Ok((true, 1.));
+        }
+        if !self.lang_fallbacks.is_empty() {
+            let key_lang_set = GridKey::from_bytes(db_key)?.lang_set;
+            let step = (1. - LANGUAGE_MISMATCH_MULTIPLIER) / (self.lang_fallbacks.len() + 1) as f64;
+            for (i, fallback) in self.lang_fallbacks.iter().enumerate() {
+                if fallback.intersects(key_lang_set) {
+                    return Ok((true, 1. - step * (i + 1) as f64));
+                }
+            }
+        }
+        Ok((false, LANGUAGE_MISMATCH_MULTIPLIER))
     }
 }
 
@@ -99,55 +338,247 @@ pub struct Proximity {
     pub radius: f64,
 }
 
+/// A bbox as callers may send it: whole tiles, or fractional tile coordinates
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BboxInput {
+    Tiles([u16; 4]),
+    Fractional([f64; 4]),
+}
+
+/// Reads a bbox given either as tiles or as fractional tile coordinates. Fractional bboxes are
+/// widened to the tiles that cover them, flooring the minimums and ceiling the maximums.
+fn deserialize_bbox<'de, D: Deserializer<'de>>(d: D) -> Result<Option<[u16; 4]>, D::Error> {
+    Ok(Option::<BboxInput>::deserialize(d)?.map(|bbox| match bbox {
+        BboxInput::Tiles(bbox) => bbox,
+        BboxInput::Fractional([min_x, min_y, max_x, max_y]) => {
+            [min_x.floor() as u16, min_y.floor() as u16, max_x.ceil() as u16, max_y.ceil() as u16]
+        }
+    }))
+}
+
+/// How coalesce results that tie on relevance and scoredist are ordered
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ScoreTiebreak {
+    /// By subquery index, then x, y and feature id, as `CoalesceContext::sort_key` does
+    ByPosition,
+    /// By score, highest first, and then as for `ByPosition`
+    ByScore,
+}
+
+impl Default for ScoreTiebreak {
+    fn default() -> Self {
+        ScoreTiebreak::ByPosition
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct MatchOpts {
+    #[serde(default, deserialize_with = "deserialize_bbox")]
     pub bbox: Option<[u16; 4]>,
     pub proximity: Option<Proximity>,
     pub zoom: u16,
+    #[serde(default)]
+    pub proximity_curve: ProximityCurve,
+    /// Several weighted points to score proximity against instead of the single `proximity`
+    /// point. The radius still comes from `proximity`, which needs to be set for these to be used.
+    #[serde(default)]
+    pub proximity_points: Option<Vec<([u16; 2], f64)>>,
+    #[serde(default)]
+    pub proximity_combine: ProximityCombine,
+    /// Dedup coalesce results on the raw feature id rather than the per-index tmp_id, so the
+    /// same feature indexed in several stores only comes back once. This assumes ids are
+    /// unique across all of the indexes being coalesced.
+    #[serde(default)]
+    pub dedup_by_feature: bool,
+    /// Caps how many coalesce results can share a feature id, so one feature can't crowd out
+    /// the rest. Results are already deduped per index, so this limits how often a feature that's
+    /// in several indexes comes back.
+    #[serde(default)]
+    pub max_per_feature: Option<usize>,
+    /// The unit `proximity`'s radius (and any subquery radius override) is given in
+    #[serde(default)]
+    pub distance_unit: DistanceUnit,
+    /// Only match grids whose feature id is in this set; an empty set matches nothing. It's
+    /// shared rather than copied when the options are cloned for each subquery.
+    #[serde(default)]
+    pub id_filter: Option<Arc<HashSet<u32>>>,
+    /// Only match grids with at least this score. Scores descend within each relevance level of a
+    /// record, so decoding stops at the first level below it rather than filtering afterward
+    #[serde(default)]
+    pub min_score: Option<u8>,
+    /// Drop coalesce results whose relevance is below this floor, on top of the usual cutoff for
+    /// results too far below the best one
+    #[serde(default)]
+    pub min_relev: Option<f64>,
+    /// Widen the bbox by this many tiles on every side when picking candidates, so results just
+    /// outside it (e.g. near a proximity point past its edge) can still be returned. Whether each
+    /// result was inside the original bbox is reported in `MatchEntry::within_bbox`
+    #[serde(default)]
+    pub bbox_soft_margin: Option<u16>,
+    /// Return at most this many grids per language, so one language can't crowd out the rest.
+    /// Each grid counts toward the lowest language of its key that's in the query's `lang_set`
+    /// (or the lowest of its key's languages, if none are); all-languages grids aren't capped
+    #[serde(default)]
+    pub max_per_language: Option<usize>,
+    /// Keep grids of the same feature that came from different source phrases apart when
+    /// coalescing a single subquery, rather than collapsing them into one result per feature.
+    /// Ignored when there's more than one subquery
+    #[serde(default)]
+    pub dedup_by_source_phrase: bool,
+    /// Knock 0.01 off the relevance of multi-subquery results that didn't stack, or that stacked
+    /// in ascending mask order. On by default; it can be turned off to compare results without it
+    #[serde(default = "default_true")]
+    pub apply_stack_penalties: bool,
+    /// How coalesce packs subquery indexes and feature ids into `tmp_id`s; the default
+    /// allows up to 128 subqueries
+    #[serde(default)]
+    pub id_layout: IdLayout,
+    /// Multipliers for the relevance of grids by their `source_phrase_hash`, applied during
+    /// matching; grids whose hash has no entry keep their relevance. Boosts above 1 can push a
+    /// grid's relevance past 1.0, which lets it outrank full-relevance grids.
+    #[serde(default)]
+    pub source_phrase_boosts: Option<HashMap<u8, f64>>,
+    /// Apply the cross-language relevance penalty to grids inside the proximity radius too. Off
+    /// by default, so nearby grids in another language rank as if they matched the query's language
+    #[serde(default)]
+    pub language_penalty_inside_radius: bool,
+    /// XORed into the feature id when ordering coalesce results, so results that tie on
+    /// everything else come out in a different but reproducible order; for fuzz testing
+    #[serde(default)]
+    pub tiebreak_seed: Option<u64>,
+    /// With a proximity point, keep at most this many grids from inside the proximity radius;
+    /// grids outside the radius are still returned after them
+    #[serde(default)]
+    pub proximity_max_within_radius: Option<usize>,
+    /// How coalesce orders results that tie on relevance and scoredist
+    #[serde(default)]
+    pub scoredist_tiebreak: ScoreTiebreak,
 }
 
 impl Default for MatchOpts {
     fn default() -> Self {
-        MatchOpts { bbox: None, proximity: None, zoom: 16 }
+        MatchOpts {
+            bbox: None,
+            proximity: None,
+            zoom: 16,
+            proximity_curve: ProximityCurve::Exponential,
+            proximity_points: None,
+            proximity_combine: ProximityCombine::Max,
+            dedup_by_feature: false,
+            max_per_feature: None,
+            distance_unit: DistanceUnit::Miles,
+            id_filter: None,
+            min_score: None,
+            min_relev: None,
+            bbox_soft_margin: None,
+            max_per_language: None,
+            dedup_by_source_phrase: false,
+            apply_stack_penalties: true,
+            id_layout: IdLayout::default(),
+            source_phrase_boosts: None,
+            language_penalty_inside_radius: false,
+            tiebreak_seed: None,
+            proximity_max_within_radius: None,
+            scoredist_tiebreak: ScoreTiebreak::ByPosition,
+        }
+    }
+}
+
+/// Scales a tile point at `zoom` to the equivalent point at `target_z`
+fn adjust_point_to_zoom(point: [u16; 2], zoom: u16, target_z: u16) -> [u16; 2] {
+    if target_z < zoom {
+        // If this is a zoom out, divide by 2 for every level of zooming out.
+        let zoom_levels = zoom - target_z;
+        // Shifting to the right by a number is the same as dividing by 2 that number of times.
+        [point[0] >> zoom_levels, point[1] >> zoom_levels]
+    } else {
+        // If this is a zoom in, choose the closest to the middle of the possible tiles at the higher zoom level.
+        // The scale of the coordinates for zooming in is 2^(difference in zs).
+        let scale_multiplier = 1 << (target_z - zoom);
+        // Pick a coordinate halfway between the possible higher zoom tiles,
+        // subtracting one to pick the one on the top left of the four middle tiles for consistency.
+        let mid_coord_adjuster = scale_multiplier / 2 - 1;
+        [
+            point[0] * scale_multiplier + mid_coord_adjuster,
+            point[1] * scale_multiplier + mid_coord_adjuster,
+        ]
     }
 }
 
 impl MatchOpts {
+    /// Default options with `bbox` set to the tiles at `zoom` that cover the given box of
+    /// longitudes and latitudes, in degrees, and `zoom` set to match. Boxes that cross the
+    /// antimeridian (`west > east`) can't be expressed as one tile bbox and are rejected, as are
+    /// zooms past 16
+    pub fn from_lnglat_bbox(
+        west: f64,
+        south: f64,
+        east: f64,
+        north: f64,
+        zoom: u16,
+    ) -> Result<MatchOpts, Error> {
+        if west > east {
+            return Err(LngLatError::CrossesAntimeridian { west, east }.into());
+        }
+        // tile rows count down from the north
+        let [min_x, min_y] = spatial::lnglat_to_tile(west, north, zoom)?;
+        let [max_x, max_y] = spatial::lnglat_to_tile(east, south, zoom)?;
+        Ok(MatchOpts { bbox: Some([min_x, min_y, max_x, max_y]), zoom, ..MatchOpts::default() })
+    }
+
+    /// The point the proximity search is ordered around: the highest-weight one of
+    /// `proximity_points` if there are any, otherwise the `proximity` point
+    pub fn proximity_seek_point(&self) -> Option<[u16; 2]> {
+        let proximity = self.proximity.as_ref()?;
+        match &self.proximity_points {
+            Some(points) if !points.is_empty() => points
+                .iter()
+                .max_by(|(_, weight_a), (_, weight_b)| {
+                    weight_a.partial_cmp(weight_b).unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .map(|(point, _)| *point),
+            _ => Some(proximity.point),
+        }
+    }
+
+    /// Returns a copy of these options with the proximity radius converted to miles, the unit
+    /// the spatial calculations work in
+    pub fn with_radius_in_miles(&self) -> MatchOpts {
+        match &self.proximity {
+            Some(proximity) if self.distance_unit != DistanceUnit::Miles => MatchOpts {
+                proximity: Some(Proximity {
+                    radius: self.distance_unit.to_miles(proximity.radius),
+                    ..proximity.clone()
+                }),
+                distance_unit: DistanceUnit::Miles,
+                ..self.clone()
+            },
+            _ => self.clone(),
+        }
+    }
+
     pub fn adjust_to_zoom(&self, target_z: u16) -> MatchOpts {
         if self.zoom == target_z {
             self.clone()
         } else {
             let adjusted_proximity = match &self.proximity {
-                Some(orig_proximity) => {
-                    if target_z < self.zoom {
-                        // If this is a zoom out, divide by 2 for every level of zooming out.
-                        let zoom_levels = self.zoom - target_z;
-                        Some(Proximity {
-                            // Shifting to the right by a number is the same as dividing by 2 that number of times.
-                            point: [
-                                orig_proximity.point[0] >> zoom_levels,
-                                orig_proximity.point[1] >> zoom_levels,
-                            ],
-                            radius: orig_proximity.radius,
-                        })
-                    } else {
-                        // If this is a zoom in, choose the closest to the middle of the possible tiles at the higher zoom level.
-                        // The scale of the coordinates for zooming in is 2^(difference in zs).
-                        let scale_multiplier = 1 << (target_z - self.zoom);
-                        // Pick a coordinate halfway between the possible higher zoom tiles,
-                        // subtracting one to pick the one on the top left of the four middle tiles for consistency.
-                        let mid_coord_adjuster = scale_multiplier / 2 - 1;
-                        let adjusted_x =
-                            orig_proximity.point[0] * scale_multiplier + mid_coord_adjuster;
-                        let adjusted_y =
-                            orig_proximity.point[1] * scale_multiplier + mid_coord_adjuster;
-
-                        Some(Proximity {
-                            point: [adjusted_x, adjusted_y],
-                            radius: orig_proximity.radius,
+                Some(orig_proximity) => Some(Proximity {
+                    point: adjust_point_to_zoom(orig_proximity.point, self.zoom, target_z),
+                    radius: orig_proximity.radius,
+                }),
+                None => None,
+            };
+
+            let adjusted_proximity_points = match &self.proximity_points {
+                Some(points) => Some(
+                    points
+                        .iter()
+                        .map(|(point, weight)| {
+                            (adjust_point_to_zoom(*point, self.zoom, target_z), *weight)
                         })
-                    }
-                }
+                        .collect(),
+                ),
                 None => None,
             };
 
@@ -183,16 +614,346 @@ impl MatchOpts {
                 None => None,
             };
 
-            MatchOpts { zoom: target_z, proximity: adjusted_proximity, bbox: adjusted_bbox }
+            MatchOpts {
+                zoom: target_z,
+                proximity: adjusted_proximity,
+                bbox: adjusted_bbox,
+                proximity_points: adjusted_proximity_points,
+                ..self.clone()
+            }
         }
     }
 }
 
+/// Builds `MatchOpts` a field at a time, starting from the defaults, so callers don't need to
+/// change when new options are added.
+#[derive(Debug, Default, Clone)]
+pub struct MatchOptsBuilder {
+    opts: MatchOpts,
+}
+
+impl MatchOptsBuilder {
+    pub fn new() -> Self {
+        MatchOptsBuilder::default()
+    }
+
+    pub fn zoom(mut self, zoom: u16) -> Self {
+        self.opts.zoom = zoom;
+        self
+    }
+
+    pub fn bbox(mut self, bbox: [u16; 4]) -> Self {
+        self.opts.bbox = Some(bbox);
+        self
+    }
+
+    pub fn proximity(mut self, point: [u16; 2], radius: f64) -> Self {
+        self.opts.proximity = Some(Proximity { point, radius });
+        self
+    }
+
+    pub fn proximity_curve(mut self, proximity_curve: ProximityCurve) -> Self {
+        self.opts.proximity_curve = proximity_curve;
+        self
+    }
+
+    pub fn proximity_points(
+        mut self,
+        points: Vec<([u16; 2], f64)>,
+        combine: ProximityCombine,
+    ) -> Self {
+        self.opts.proximity_points = Some(points);
+        self.opts.proximity_combine = combine;
+        self
+    }
+
+    pub fn dedup_by_feature(mut self, dedup_by_feature: bool) -> Self {
+        self.opts.dedup_by_feature = dedup_by_feature;
+        self
+    }
+
+    pub fn max_per_feature(mut self, max_per_feature: usize) -> Self {
+        self.opts.max_per_feature = Some(max_per_feature);
+        self
+    }
+
+    pub fn distance_unit(mut self, distance_unit: DistanceUnit) -> Self {
+        self.opts.distance_unit = distance_unit;
+        self
+    }
+
+    pub fn id_filter(mut self, id_filter: HashSet<u32>) -> Self {
+        self.opts.id_filter = Some(Arc::new(id_filter));
+        self
+    }
+
+    pub fn min_score(mut self, min_score: u8) -> Self {
+        self.opts.min_score = Some(min_score);
+        self
+    }
+
+    pub fn min_relev(mut self, min_relev: f64) -> Self {
+        self.opts.min_relev = Some(min_relev);
+        self
+    }
+
+    pub fn bbox_soft_margin(mut self, margin: u16) -> Self {
+        self.opts.bbox_soft_margin = Some(margin);
+        self
+    }
+
+    pub fn max_per_language(mut self, max_per_language: usize) -> Self {
+        self.opts.max_per_language = Some(max_per_language);
+        self
+    }
+
+    pub fn dedup_by_source_phrase(mut self, dedup_by_source_phrase: bool) -> Self {
+        self.opts.dedup_by_source_phrase = dedup_by_source_phrase;
+        self
+    }
+
+    pub fn apply_stack_penalties(mut self, apply_stack_penalties: bool) -> Self {
+        self.opts.apply_stack_penalties = apply_stack_penalties;
+        self
+    }
+
+    pub fn id_layout(mut self, id_layout: IdLayout) -> Self {
+        self.opts.id_layout = id_layout;
+        self
+    }
+
+    pub fn source_phrase_boosts(mut self, source_phrase_boosts: HashMap<u8, f64>) -> Self {
+        self.opts.source_phrase_boosts = Some(source_phrase_boosts);
+        self
+    }
+
+    pub fn language_penalty_inside_radius(mut self, language_penalty_inside_radius: bool) -> Self {
+        self.opts.language_penalty_inside_radius = language_penalty_inside_radius;
+        self
+    }
+
+    pub fn tiebreak_seed(mut self, tiebreak_seed: u64) -> Self {
+        self.opts.tiebreak_seed = Some(tiebreak_seed);
+        self
+    }
+
+    pub fn proximity_max_within_radius(mut self, proximity_max_within_radius: usize) -> Self {
+        self.opts.proximity_max_within_radius = Some(proximity_max_within_radius);
+        self
+    }
+
+    pub fn scoredist_tiebreak(mut self, scoredist_tiebreak: ScoreTiebreak) -> Self {
+        self.opts.scoredist_tiebreak = scoredist_tiebreak;
+        self
+    }
+
+    pub fn build(self) -> MatchOpts {
+        self.opts
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use once_cell::sync::Lazy;
 
+    #[test]
+    fn tmp_id_round_trip_test() {
+        assert_eq!(encode_tmp_id(0, 0), 0);
+        assert_eq!(encode_tmp_id(1, 1), (1 << 25) + 1);
+        for &(idx, id) in &[(0, 1), (1, 12), (3, (1 << 24) - 1), (127, 0), (127, (1 << 24) - 1)] {
+            assert_eq!(decode_tmp_id(encode_tmp_id(idx, id)), (idx, id));
+        }
+
+        let wide = IdLayout::new(8);
+        assert_eq!(wide.max_idx(), 255);
+        assert_eq!(wide.encode(1, 1), (1 << 24) + 1);
+        for &(idx, id) in &[(0, 1), (127, 5), (128, 0), (200, 12), (255, (1 << 24) - 1)] {
+            assert_eq!(wide.decode(wide.encode(idx, id)), (idx, id));
+        }
+        assert_ne!(wide.encode(200, 12), wide.encode(72, 12), "high index bits aren't dropped");
+
+        assert!(IdLayout::try_from(0).is_err());
+        assert!(IdLayout::try_from(9).is_err());
+        assert_eq!(serde_json::to_string(&wide).unwrap(), "8");
+        assert_eq!(serde_json::from_str::<IdLayout>("8").unwrap(), wide);
+        for idx_bits in &["0", "9", "40"] {
+            assert!(
+                serde_json::from_str::<IdLayout>(idx_bits).is_err(),
+                "{} is rejected",
+                idx_bits
+            );
+        }
+    }
+
+    #[test]
+    fn lang_set_test() {
+        let mut lang_set = LangSet::default();
+        assert!(lang_set.is_empty());
+        lang_set.insert(3);
+        lang_set.insert(127);
+        assert!(lang_set.contains(3) && lang_set.contains(127));
+        assert!(!lang_set.contains(4) && !lang_set.contains(200));
+        assert_eq!(lang_set.bits(), (1 << 3) | (1 << 127));
+
+        let other = LangSet::from(&[4, 127][..]);
+        assert_eq!(lang_set.union(other), LangSet::from(&[3, 4, 127][..]));
+        assert_eq!(lang_set.intersection(other), LangSet::from(&[127][..]));
+        assert!(lang_set.intersects(other));
+        assert!(!lang_set.intersects(LangSet::from(&[0, 1][..])));
+
+        // the all-languages sentinel
+        assert!(LangSet::all().is_all());
+        assert_eq!(LangSet::all(), ALL_LANGUAGES);
+        assert!(LangSet::all().contains(0) && LangSet::all().contains(200));
+        assert!(LangSet::all().intersects(lang_set));
+        assert!(!lang_set.is_all());
+        assert!(lang_set.union(LangSet::all()).is_all());
+        assert!(LangSet::from(&(0..128).collect::<Vec<u32>>()[..]).is_all());
+
+        // serialized as the raw bitfield
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1 << 5) };
+        assert_eq!(serde_json::to_string(&key).unwrap(), r#"{"phrase_id":1,"lang_set":32}"#,);
+        assert_eq!(
+            serde_json::from_str::<GridKey>(r#"{"phrase_id":1,"lang_set":32}"#).unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn langarray_to_langset_test() {
+        assert_eq!(langarray_to_langset(&[]).unwrap(), LangSet::default());
+        assert_eq!(langarray_to_langset(&[0, 1, 127]).unwrap(), LangSet::from(0b11 | (1 << 127)));
+
+        let err = langarray_to_langset(&[5, 200]).unwrap_err();
+        match err.downcast_ref::<LangSetError>() {
+            Some(LangSetError::OutOfRange { ids }) => assert_eq!(ids, &[200]),
+            None => panic!("expected an out of range error"),
+        }
+        assert!(err.to_string().contains("200"), "the dropped id is reported");
+    }
+
+    #[test]
+    fn grid_key_from_bytes_test() {
+        let key = GridKey { phrase_id: 258, lang_set: LangSet::from(1 << 9) };
+        let mut db_key = Vec::new();
+        key.write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+        assert_eq!(GridKey::from_bytes(&db_key).unwrap(), key);
+
+        for bad_key in [vec![], vec![0, 0, 1], vec![0; 22]].iter() {
+            let err = GridKey::from_bytes(bad_key).unwrap_err();
+            match err.downcast_ref::<KeyError>() {
+                Some(KeyError::Malformed { key, .. }) => assert_eq!(key, bad_key),
+                other => panic!("expected a malformed key error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn from_lnglat_bbox_test() {
+        // roughly San Francisco
+        let opts = MatchOpts::from_lnglat_bbox(-122.52, 37.70, -122.35, 37.83, 14).unwrap();
+        assert_eq!(opts.bbox, Some([2615, 6329, 2623, 6337]));
+        assert_eq!(opts.zoom, 14);
+        assert_eq!(MatchOpts { bbox: None, zoom: 16, ..opts }, MatchOpts::default());
+
+        let opts = MatchOpts::from_lnglat_bbox(-180., -90., 180., 90., 3).unwrap();
+        assert_eq!(opts.bbox, Some([0, 0, 7, 7]), "the whole world covers the whole zoom level");
+
+        // Fiji, which straddles the antimeridian
+        let err = MatchOpts::from_lnglat_bbox(177., -21., -178., -12., 6).unwrap_err();
+        match err.downcast_ref::<LngLatError>() {
+            Some(LngLatError::CrossesAntimeridian { west, east }) => {
+                assert_eq!((*west, *east), (177., -178.))
+            }
+            other => panic!("expected an antimeridian error, got {:?}", other),
+        }
+        let err = MatchOpts::from_lnglat_bbox(-122.52, 37.70, -122.35, 37.83, 18).unwrap_err();
+        assert!(err.downcast_ref::<LngLatError>().is_some(), "zoom 18 is rejected");
+    }
+
+    #[test]
+    fn match_opts_builder_test() {
+        assert_eq!(MatchOptsBuilder::new().build(), MatchOpts::default());
+
+        let built = MatchOptsBuilder::new()
+            .zoom(14)
+            .bbox([1, 2, 3, 4])
+            .proximity([2, 3], 40.)
+            .proximity_curve(ProximityCurve::Gaussian)
+            .proximity_points(vec![([2, 3], 1.), ([4, 5], 0.5)], ProximityCombine::WeightedMean)
+            .dedup_by_feature(true)
+            .max_per_feature(2)
+            .distance_unit(DistanceUnit::Kilometers)
+            .id_filter([3, 5].iter().cloned().collect())
+            .min_score(6)
+            .min_relev(0.5)
+            .bbox_soft_margin(2)
+            .max_per_language(3)
+            .dedup_by_source_phrase(true)
+            .apply_stack_penalties(false)
+            .id_layout(IdLayout::new(8))
+            .source_phrase_boosts([(2, 1.5)].iter().cloned().collect())
+            .language_penalty_inside_radius(true)
+            .tiebreak_seed(7)
+            .proximity_max_within_radius(4)
+            .scoredist_tiebreak(ScoreTiebreak::ByScore)
+            .build();
+        assert_eq!(
+            built,
+            MatchOpts {
+                bbox: Some([1, 2, 3, 4]),
+                proximity: Some(Proximity { point: [2, 3], radius: 40. }),
+                zoom: 14,
+                proximity_curve: ProximityCurve::Gaussian,
+                proximity_points: Some(vec![([2, 3], 1.), ([4, 5], 0.5)]),
+                proximity_combine: ProximityCombine::WeightedMean,
+                dedup_by_feature: true,
+                max_per_feature: Some(2),
+                distance_unit: DistanceUnit::Kilometers,
+                id_filter: Some(Arc::new([3, 5].iter().cloned().collect())),
+                min_score: Some(6),
+                min_relev: Some(0.5),
+                bbox_soft_margin: Some(2),
+                max_per_language: Some(3),
+                dedup_by_source_phrase: true,
+                apply_stack_penalties: false,
+                id_layout: IdLayout::new(8),
+                source_phrase_boosts: Some([(2, 1.5)].iter().cloned().collect()),
+                language_penalty_inside_radius: true,
+                tiebreak_seed: Some(7),
+                proximity_max_within_radius: Some(4),
+                scoredist_tiebreak: ScoreTiebreak::ByScore,
+            }
+        );
+    }
+
+    #[test]
+    fn match_opts_bbox_deserialize_test() {
+        let parse = |json: &str| serde_json::from_str::<MatchOpts>(json).unwrap().bbox;
+
+        assert_eq!(
+            parse(r#"{"bbox": [1, 2, 3, 4], "proximity": null, "zoom": 14}"#),
+            Some([1, 2, 3, 4])
+        );
+        assert_eq!(
+            parse(r#"{"bbox": [1.2, 3.7, 5.1, 6.9], "proximity": null, "zoom": 14}"#),
+            Some([1, 3, 6, 7]),
+            "fractional bboxes are widened to the tiles covering them"
+        );
+        assert_eq!(
+            parse(r#"{"bbox": [1.0, 2.0, 3.0, 4.0], "proximity": null, "zoom": 14}"#),
+            Some([1, 2, 3, 4]),
+            "whole numbers as floats are left alone"
+        );
+        assert_eq!(parse(r#"{"bbox": null, "proximity": null, "zoom": 14}"#), None);
+        assert_eq!(parse(r#"{"proximity": null, "zoom": 14}"#), None);
+        assert!(serde_json::from_str::<MatchOpts>(r#"{"bbox": [1, 2, 3], "zoom": 14}"#).is_err());
+
+        let opts = serde_json::from_str::<MatchOpts>(r#"{"zoom": 14}"#).unwrap();
+        assert!(opts.apply_stack_penalties, "stack penalties default to on when left out");
+    }
+
     fn matchopts_proximity_generator(point: [u16; 2], radius: f64, zoom: u16) -> MatchOpts {
         MatchOpts {
             proximity: Some(Proximity { point: point, radius: radius }),
@@ -251,6 +1012,17 @@ mod tests {
         let zoomed_in_3z = MATCH_OPTS_PROXIMITY.2.adjust_to_zoom(7);
         let proximity_in_3z = zoomed_in_3z.proximity.unwrap();
         assert_eq!(proximity_in_3z.point, [51, 51], "4/6/6 zoomed in to zoom 7 should be 7/51/51");
+
+        let multi_point = MatchOpts {
+            proximity_points: Some(vec![([6, 6], 1.), ([2, 3], 0.5)]),
+            ..MATCH_OPTS_PROXIMITY.2.clone()
+        };
+        assert_eq!(
+            multi_point.adjust_to_zoom(5).proximity_points.unwrap(),
+            [([12, 12], 1.), ([4, 6], 0.5)],
+            "Proximity points are scaled along with the proximity point"
+        );
+        assert_eq!(multi_point.proximity_seek_point(), Some([6, 6]), "Highest weight point");
     }
 
     fn matchopts_bbox_generator(bbox: [u16; 4], zoom: u16) -> MatchOpts {
@@ -366,12 +1138,44 @@ pub struct GridEntry {
     pub source_phrase_hash: u8,
 }
 
+impl GridEntry {
+    /// The key grids are read back from a record in: relev and score descending, then morton
+    /// order of the coordinates descending, then id and source phrase hash descending. Sorting
+    /// grids by it, e.g. with `sort_by_key(GridEntry::read_order_key)`, puts them in the order a
+    /// store hands them back in. This is separate from `GridEntry`'s own `PartialOrd`, which
+    /// the types holding a `GridEntry` rely on.
+    #[inline]
+    pub fn read_order_key(
+        &self,
+    ) -> (Reverse<OrderedFloat<f64>>, Reverse<u8>, Reverse<u32>, Reverse<u32>, Reverse<u8>) {
+        (
+            Reverse(OrderedFloat(self.relev)),
+            Reverse(self.score),
+            Reverse(interleave_morton(self.x, self.y)),
+            Reverse(self.id),
+            Reverse(self.source_phrase_hash),
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialOrd, PartialEq)]
 pub struct MatchEntry {
     pub grid_entry: GridEntry,
     pub matches_language: bool,
     pub distance: f64,
     pub scoredist: f64,
+    /// The proximity radius `scoredist` was computed with, in the match options' distance unit,
+    /// or 0 if there was no proximity point
+    #[serde(default)]
+    pub radius: f64,
+    /// Whether the grid is inside the match options' bbox; grids only let in by
+    /// `bbox_soft_margin` are outside it. Always true without a bbox.
+    #[serde(default = "default_true")]
+    pub within_bbox: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, PartialEq, Clone)]
@@ -392,6 +1196,86 @@ pub struct CoalesceContext {
     pub entries: Vec<CoalesceEntry>,
 }
 
+impl CoalesceContext {
+    /// How much each of `entries` contributed to `relev`: its relevance after the language
+    /// penalty and its subquery's weight, in the same order as `entries`. For stacked results
+    /// these add up to `relev`, give or take the 0.01 stacking penalty (see
+    /// `MatchOpts::apply_stack_penalties`).
+    pub fn relev_contributions(&self) -> Vec<f64> {
+        self.entries.iter().map(|entry| entry.grid_entry.relev).collect()
+    }
+
+    /// The key coalesce results are ordered by: relevance and scoredist descending, then the
+    /// subquery index ascending, then x, y and feature id descending as the final tiebreakers.
+    /// All coalesce paths sort with this key so equal-relevance results come out in the same order.
+    #[inline]
+    pub fn sort_key(
+        &self,
+    ) -> (
+        Reverse<OrderedFloat<f64>>,
+        Reverse<OrderedFloat<f64>>,
+        u16,
+        Reverse<u16>,
+        Reverse<u16>,
+        Reverse<u32>,
+    ) {
+        (
+            Reverse(OrderedFloat(self.relev)),
+            Reverse(OrderedFloat(self.entries[0].scoredist)),
+            self.entries[0].idx,
+            Reverse(self.entries[0].grid_entry.x),
+            Reverse(self.entries[0].grid_entry.y),
+            Reverse(self.entries[0].grid_entry.id),
+        )
+    }
+
+    /// The key coalesce sorts its results by under `match_opts`: `sort_key`, with the tiebreaks
+    /// `MatchOpts::scoredist_tiebreak` and `MatchOpts::tiebreak_seed` ask for. With the default
+    /// options it orders the same way as `sort_key`.
+    #[inline]
+    pub fn sort_key_for(
+        &self,
+        match_opts: &MatchOpts,
+    ) -> (
+        Reverse<OrderedFloat<f64>>,
+        Reverse<OrderedFloat<f64>>,
+        Reverse<u8>,
+        u16,
+        Reverse<u16>,
+        Reverse<u16>,
+        Reverse<u64>,
+    ) {
+        let (relev, scoredist, idx, x, y, Reverse(id)) = self.sort_key();
+        let score = match match_opts.scoredist_tiebreak {
+            ScoreTiebreak::ByPosition => 0,
+            ScoreTiebreak::ByScore => self.entries[0].grid_entry.score,
+        };
+        // XORing in the seed only changes the order of results that tie on everything before it
+        let id = u64::from(id) ^ match_opts.tiebreak_seed.unwrap_or(0);
+        (relev, scoredist, Reverse(score), idx, x, y, Reverse(id))
+    }
+}
+
+/// Information about how a coalesce went, alongside its results
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct CoalesceStats {
+    /// Whether some subquery matched more than `MAX_GRIDS_PER_PHRASE` grids, so only the best
+    /// of them (the nearest first, with a proximity point) were considered
+    pub truncated: bool,
+}
+
+/// Encodes coalesce results in bincode's compact binary format, for passing them between Rust
+/// processes without going through the JSON-ish neon representation.
+pub fn serialize_contexts(contexts: &[CoalesceContext]) -> Vec<u8> {
+    // contexts are plain structs of numbers and vecs, so encoding them can't fail
+    bincode::serialize(contexts).expect("coalesce contexts are always serializable")
+}
+
+/// Decodes coalesce results written by `serialize_contexts`.
+pub fn deserialize_contexts(data: &[u8]) -> Result<Vec<CoalesceContext>, Error> {
+    Ok(bincode::deserialize(data)?)
+}
+
 fn serialize_path<S: Serializer, T: Borrow<GridStore>>(store: &T, s: S) -> Result<S::Ok, S::Error> {
     s.serialize_str(store.borrow().path.to_str().unwrap())
 }
@@ -405,6 +1289,113 @@ pub struct PhrasematchSubquery<T: Borrow<GridStore> + Clone> {
     pub idx: u16,
     pub zoom: u16,
     pub mask: u32,
+    /// Proximity radius to use for this subquery instead of the one in the match options
+    pub radius_override: Option<f64>,
+}
+
+impl<T: Borrow<GridStore> + Clone> PhrasematchSubquery<T> {
+    /// A conservative, symmetric pairwise check for whether grids from this subquery and `other`
+    /// can end up in the same coalesce context: their masks can't share any query tokens, and
+    /// they have to come from different indexes, whatever their zooms. It doesn't match
+    /// `coalesce_multi` exactly, which checks zooms and masks per context and can stack an index
+    /// onto a lower-zoom context that holds a different one.
+    pub fn can_stack_with(&self, other: &Self) -> bool {
+        self.mask & other.mask == 0 && self.idx != other.idx
+    }
+}
+
+/// Bits grid ids are stored in, which is as wide as a `tmp_id`'s id part can usefully be
+const GRID_ID_BITS: u32 = 24;
+
+/// How a `tmp_id` splits its 32 bits between a subquery index, in the top `idx_bits` bits, and a
+/// feature id, in the bits below them. The default keeps carmen's `(idx << 25) + id`, which
+/// allows 128 subqueries; giving the index 8 bits allows 256, while still leaving room for any
+/// 24-bit grid id. It's serialized as just `idx_bits`, and deserializing checks it the way
+/// `new` does.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct IdLayout {
+    idx_bits: u32,
+}
+
+impl IdLayout {
+    /// Panics unless `idx_bits` is from 1 to 8: the index needs at least one bit, and more than
+    /// 8 would leave fewer bits for the id than grid ids need
+    pub fn new(idx_bits: u32) -> IdLayout {
+        match IdLayout::try_from(idx_bits) {
+            Ok(id_layout) => id_layout,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    pub fn idx_bits(&self) -> u32 {
+        self.idx_bits
+    }
+
+    /// The largest subquery index this layout can hold
+    pub fn max_idx(&self) -> u16 {
+        ((1u32 << self.idx_bits) - 1) as u16
+    }
+
+    fn id_bits(&self) -> u32 {
+        32 - self.idx_bits
+    }
+
+    /// Packs a subquery index and a feature id into a `tmp_id`
+    #[inline]
+    pub fn encode(&self, idx: u16, id: u32) -> u32 {
+        debug_assert!(
+            idx <= self.max_idx(),
+            "subquery index {} doesn't fit in {} bits",
+            idx,
+            self.idx_bits
+        );
+        debug_assert!(id < (1 << GRID_ID_BITS), "feature id {} doesn't fit in 24 bits", id);
+        ((idx as u32) << self.id_bits()) + id
+    }
+
+    /// Splits a `tmp_id` made by `encode` with this layout back into its subquery index and
+    /// feature id
+    #[inline]
+    pub fn decode(&self, tmp_id: u32) -> (u16, u32) {
+        ((tmp_id >> self.id_bits()) as u16, tmp_id & ((1 << self.id_bits()) - 1))
+    }
+}
+
+impl Default for IdLayout {
+    fn default() -> Self {
+        IdLayout { idx_bits: 7 }
+    }
+}
+
+impl TryFrom<u32> for IdLayout {
+    type Error = IdLayoutError;
+
+    fn try_from(idx_bits: u32) -> Result<Self, Self::Error> {
+        if idx_bits == 0 || idx_bits > 32 - GRID_ID_BITS {
+            return Err(IdLayoutError::OutOfRange { idx_bits });
+        }
+        Ok(IdLayout { idx_bits })
+    }
+}
+
+impl From<IdLayout> for u32 {
+    fn from(id_layout: IdLayout) -> u32 {
+        id_layout.idx_bits
+    }
+}
+
+/// Packs a subquery index and a feature id into a `tmp_id` with the default `IdLayout`: the
+/// index goes in the top 7 bits and the id in the bits below it.
+#[inline]
+pub fn encode_tmp_id(idx: u16, id: u32) -> u32 {
+    IdLayout::default().encode(idx, id)
+}
+
+/// Splits a `tmp_id` made by `encode_tmp_id` back into its subquery index and feature id.
+#[inline]
+pub fn decode_tmp_id(tmp_id: u32) -> (u16, u32) {
+    IdLayout::default().decode(tmp_id)
 }
 
 #[inline]
@@ -430,6 +1421,100 @@ pub fn relev_int_to_float(relev: u8) -> f64 {
     }
 }
 
+/// Converts an array of language IDs into a language set bitfield. IDs that don't fit in the
+/// 128-bit field are reported in the error instead of being silently dropped.
+pub fn langarray_to_langset(lang_array: &[u32]) -> Result<LangSet, Error> {
+    let mut out = LangSet::default();
+    let mut out_of_range = Vec::new();
+    for lang in lang_array {
+        if *lang >= 128 {
+            out_of_range.push(*lang);
+        } else {
+            out.insert(*lang as u8);
+        }
+    }
+    if out_of_range.is_empty() {
+        Ok(out)
+    } else {
+        Err(LangSetError::OutOfRange { ids: out_of_range }.into())
+    }
+}
+
+/// Errors returned by `GridStore` and `GridStoreBuilder`
+#[derive(Debug, thiserror::Error)]
+pub enum GridStoreError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("rocksdb error: {0}")]
+    RocksDb(#[from] rocksdb::Error),
+    #[error("malformed {what}: {reason}")]
+    Decode { what: &'static str, reason: String },
+    #[error("malformed key {key:?}: {reason}")]
+    InvalidKey { key: Vec<u8>, reason: &'static str },
+    #[error("cursor at offset {offset} doesn't match the query it's resuming")]
+    InvalidCursor { offset: usize },
+    /// Any other error, such as invalid arguments to a builder method
+    #[error("{0}")]
+    Other(Error),
+}
+
+impl From<Error> for GridStoreError {
+    /// Recovers the specific variant for errors raised through `failure` internally
+    fn from(err: Error) -> Self {
+        let err = match err.downcast::<KeyError>() {
+            Ok(KeyError::Malformed { key, reason }) => {
+                return GridStoreError::InvalidKey { key, reason }
+            }
+            Err(err) => err,
+        };
+        let err = match err.downcast::<rocksdb::Error>() {
+            Ok(err) => return GridStoreError::RocksDb(err),
+            Err(err) => err,
+        };
+        match err.downcast::<std::io::Error>() {
+            Ok(err) => GridStoreError::Io(err),
+            Err(err) => GridStoreError::Other(err),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum KeyError {
+    #[fail(display = "malformed key {:?}: {}", key, reason)]
+    Malformed { key: Vec<u8>, reason: &'static str },
+}
+
+#[derive(Debug, Fail)]
+pub enum IdLayoutError {
+    #[fail(display = "a {}-bit subquery index isn't supported (must be from 1 to 8)", idx_bits)]
+    OutOfRange { idx_bits: u32 },
+}
+
+#[derive(Debug, Fail)]
+pub enum KeyOrderingError {
+    #[fail(display = "{} isn't supported for stores with {:?} key ordering", operation, ordering)]
+    Unsupported { operation: &'static str, ordering: KeyOrdering },
+    #[fail(display = "stores with {:?} and {:?} key orderings can't be walked together", a, b)]
+    Mismatch { a: KeyOrdering, b: KeyOrdering },
+}
+
+#[derive(Debug, Fail)]
+pub enum LngLatError {
+    #[fail(display = "zoom {} is past the deepest supported zoom, 16", zoom)]
+    ZoomOutOfRange { zoom: u16 },
+    #[fail(
+        display = "box crosses the antimeridian (west {} > east {}), which isn't supported",
+        west, east
+    )]
+    CrossesAntimeridian { west: f64, east: f64 },
+}
+
+#[derive(Debug, Fail)]
+pub enum LangSetError {
+    #[fail(display = "language IDs out of range (must be below 128): {:?}", ids)]
+    OutOfRange { ids: Vec<u32> },
+}
+
 // the groupby in itertools doesn't take ownership of the thing it's grouping, instead returning
 // groups that reference an unowned buffer -- this is tricky for lifetime purposes if you want to
 // return an iterator based on a groupby. This version makes a slightly different tradeoff -- it
@@ -2,26 +2,31 @@ mod builder;
 mod coalesce;
 mod common;
 mod gridstore_format;
+mod packed;
 mod spatial;
 mod store;
 
 pub use builder::*;
-pub use coalesce::coalesce;
+pub use coalesce::{
+    coalesce, coalesce_grouped, coalesce_iter, coalesce_with_histogram, coalesce_with_stats,
+    HISTOGRAM_BUCKET_SIZE,
+};
 pub use common::*;
+pub use spatial::{DistanceUnit, ProximityCombine, ProximityCurve};
 pub use store::*;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use once_cell::sync::Lazy;
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, BTreeSet};
 
     #[test]
     fn combined_test() {
         let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
         let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
         let mut entries = vec![
             GridEntry { id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 },
@@ -35,16 +40,105 @@ mod tests {
         let reader = GridStore::new(directory.path()).unwrap();
         let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
 
-        entries.sort_by(|a, b| b.partial_cmp(a).unwrap());
-        assert_eq!(
-            record, entries,
-            "identical entries come out as went in, in reverse-sorted order"
-        );
+        entries.sort_by_key(GridEntry::read_order_key);
+        assert_eq!(record, entries, "identical entries come out as went in, in sorted order");
+        assert_eq!(reader.phrase_entry_count(&key).unwrap(), entries.len());
 
         {
-            let key = GridKey { phrase_id: 2, lang_set: 1 };
+            let key = GridKey { phrase_id: 2, lang_set: LangSet::from(1) };
             let record = reader.get(&key).expect("Failed to get key");
             assert!(record.is_none(), "Retrieved no results");
+            assert_eq!(reader.phrase_entry_count(&key).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn open_options_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        let entries = vec![
+            GridEntry { id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 },
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 7, source_phrase_hash: 2 },
+        ];
+        builder.insert(&key, entries).expect("Unable to insert record");
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let expected: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
+
+        let default_opts = GridStore::open(directory.path(), GridStoreOpenOptions::default());
+        let custom_opts = GridStore::open(
+            directory.path(),
+            GridStoreOpenOptions {
+                allow_mmap_reads: false,
+                max_open_files: Some(16),
+                key_ordering: KeyOrdering::PhraseFirst,
+            },
+        );
+        for opened in vec![default_opts, custom_opts] {
+            let opened = opened.unwrap();
+            assert_eq!(opened.path, reader.path);
+            let record: Vec<_> = opened.get(&key).unwrap().unwrap().collect();
+            assert_eq!(record, expected, "every way of opening reads the same data");
+        }
+    }
+
+    #[test]
+    fn open_many_test() {
+        let directories: Vec<tempfile::TempDir> =
+            (0..2).map(|_| tempfile::tempdir().unwrap()).collect();
+        for (i, directory) in directories.iter().enumerate() {
+            let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+            let entries = vec![GridEntry {
+                id: i as u32,
+                x: 1,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, entries).unwrap();
+            builder.finish().unwrap();
+        }
+
+        let paths: Vec<_> = directories.iter().map(|directory| directory.path()).collect();
+        let stores = GridStore::open_many(&paths, &GridStoreOpenOptions::default()).unwrap();
+        assert_eq!(stores.len(), 2);
+        for (i, store) in stores.iter().enumerate() {
+            assert_eq!(store.path, paths[i], "stores come back in the order they were asked for");
+            let ids: Vec<_> = store
+                .get(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) })
+                .unwrap()
+                .unwrap()
+                .map(|entry| entry.id)
+                .collect();
+            assert_eq!(ids, [i as u32]);
+        }
+
+        let missing = directories[0].path().join("missing");
+        assert!(
+            GridStore::open_many(&[paths[0], missing.as_path()], &GridStoreOpenOptions::default())
+                .is_err(),
+            "a store that fails to open fails the whole call"
+        );
+    }
+
+    #[test]
+    fn error_variants_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        match GridStore::new(directory.path().join("missing")) {
+            Err(GridStoreError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("expected an I/O error, got {:?}", other.map(|store| store.path)),
+        }
+
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        builder.insert(&GridKey { phrase_id: 3, lang_set: LangSet::from(1) }, vec![]).unwrap();
+        match builder.renumber(&[0, 1]) {
+            Err(err @ GridStoreError::Other(_)) => {
+                assert_eq!(err.to_string(), "out of bounds: 3 is past the end of the 2-entry map")
+            }
+            other => panic!("expected a renumbering error, got {:?}", other),
         }
     }
 
@@ -56,15 +150,15 @@ mod tests {
         // phrase IDs are descending, grid IDs are ascending
         let items = vec![
             (
-                GridKey { phrase_id: 2, lang_set: 1 },
+                GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
                 GridEntry { id: 0, x: 1, y: 1, relev: 1., score: 7, source_phrase_hash: 2 },
             ),
             (
-                GridKey { phrase_id: 1, lang_set: 1 },
+                GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
                 GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 7, source_phrase_hash: 2 },
             ),
             (
-                GridKey { phrase_id: 0, lang_set: 1 },
+                GridKey { phrase_id: 0, lang_set: LangSet::from(1) },
                 GridEntry { id: 2, x: 1, y: 1, relev: 1., score: 7, source_phrase_hash: 2 },
             ),
         ];
@@ -79,8 +173,11 @@ mod tests {
         let reader = GridStore::new(directory.path()).unwrap();
 
         for id in 0..=2 {
-            let entries: Vec<_> =
-                reader.get(&GridKey { phrase_id: id, lang_set: 1 }).unwrap().unwrap().collect();
+            let entries: Vec<_> = reader
+                .get(&GridKey { phrase_id: id, lang_set: LangSet::from(1) })
+                .unwrap()
+                .unwrap()
+                .collect();
             assert_eq!(id, entries[0].id);
         }
     }
@@ -90,7 +187,7 @@ mod tests {
         let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
         let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
         let mut entries = vec![
             GridEntry { id: 1, x: 1, y: 1, relev: 1.0, score: 1, source_phrase_hash: 0 },
@@ -104,21 +201,214 @@ mod tests {
         let reader = GridStore::new(directory.path()).unwrap();
         let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
 
-        entries.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        entries.sort_by_key(GridEntry::read_order_key);
+        assert_eq!(record, entries, "identical entries come out as went in, in sorted order");
+
+        assert_eq!(reader.source_phrase_hashes(&key).unwrap(), vec![0, 2, 3]);
+        let missing = GridKey { phrase_id: 2, lang_set: LangSet::from(1) };
+        assert_eq!(reader.source_phrase_hashes(&missing).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn proximity_max_within_radius_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id, x, y| GridEntry { id, x, y, relev: 1., score: 1, source_phrase_hash: 0 };
+        // a dense cluster around the proximity point, and a couple of grids far outside the radius
+        let mut entries: Vec<GridEntry> = (1..=6).map(|id| grid(id, 100, 100)).collect();
+        entries.push(grid(10, 600, 600));
+        entries.push(grid(11, 700, 700));
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        builder.insert(&key, entries).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let match_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let matches = |match_opts: &MatchOpts| -> Vec<MatchEntry> {
+            reader
+                .streaming_get_matching(&match_key, match_opts, std::usize::MAX)
+                .unwrap()
+                .collect()
+        };
+        let opts = MatchOptsBuilder::new().zoom(14).proximity([100, 100], 1.);
+
+        let uncapped = matches(&opts.clone().build());
+        assert_eq!(uncapped.len(), 8);
+        assert_eq!(uncapped.iter().filter(|entry| entry.distance == 0.).count(), 6);
+
+        let capped = matches(&opts.proximity_max_within_radius(3).build());
+        assert_eq!(capped.len(), 5);
+        assert!(
+            capped[..3].iter().all(|entry| entry.distance == 0.),
+            "the first three grids come from the cluster"
+        );
+        let outside_ids: Vec<u32> = capped[3..].iter().map(|entry| entry.grid_entry.id).collect();
+        assert_eq!(outside_ids, vec![10, 11], "grids outside the radius still fill the results");
+        assert_eq!(&capped[..3], &uncapped[..3], "the cluster's best grids are the ones kept");
+
+        let no_proximity = MatchOptsBuilder::new().zoom(14).proximity_max_within_radius(3).build();
+        assert_eq!(matches(&no_proximity).len(), 8, "without a proximity point nothing is capped");
+    }
+
+    #[test]
+    fn source_phrase_boosts_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        let entries = vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1.0, score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 1, x: 1, y: 1, relev: 0.6, score: 1, source_phrase_hash: 2 },
+            GridEntry { id: 1, x: 1, y: 1, relev: 0.4, score: 1, source_phrase_hash: 3 },
+        ];
+        builder.insert(&key, entries).expect("Unable to insert record");
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let match_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let matches = |boosts: &[(u8, f64)]| -> Vec<(u8, f64)> {
+            let match_opts = MatchOptsBuilder::new()
+                .source_phrase_boosts(boosts.iter().cloned().collect())
+                .build();
+            reader
+                .streaming_get_matching(&match_key, &match_opts, std::usize::MAX)
+                .unwrap()
+                .map(|entry| {
+                    let relev = (entry.grid_entry.relev * 100.).round() / 100.;
+                    (entry.grid_entry.source_phrase_hash, relev)
+                })
+                .collect()
+        };
+
         assert_eq!(
-            record, entries,
-            "identical entries come out as went in, in reverse-sorted order"
+            matches(&[(2, 1.5)]),
+            vec![(0, 1.0), (2, 0.9), (3, 0.4)],
+            "the boosted hash's relevance is multiplied, and hashes without a boost are unchanged"
+        );
+        assert_eq!(
+            matches(&[(2, 1.5), (3, 3.0)]),
+            vec![(3, 1.2), (0, 1.0), (2, 0.9)],
+            "a boost can move a grid ahead of ones stored with a higher relevance"
         );
     }
 
     #[test]
-    fn cover_test() {
+    fn full_world_bbox_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        let entries: Vec<_> = (0..40u32)
+            .map(|i| GridEntry {
+                id: i,
+                x: (i * 7 % 64) as u16,
+                y: (i * 13 % 64) as u16,
+                relev: 1.,
+                score: (i % 4) as u8,
+                source_phrase_hash: 0,
+            })
+            .collect();
+        builder.insert(&key, entries).expect("Unable to insert record");
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let get = |match_opts: &MatchOpts| -> Vec<_> {
+            reader.streaming_get_matching(&search_key, match_opts, MAX_CONTEXTS).unwrap().collect()
+        };
+
+        let proximity_only = MatchOpts {
+            zoom: 6,
+            proximity: Some(Proximity { point: [20, 30], radius: 40. }),
+            ..MatchOpts::default()
+        };
+        let full_world = MatchOpts { bbox: Some([0, 0, 63, 63]), ..proximity_only.clone() };
+        let results = get(&proximity_only);
+        assert_eq!(results.len(), 40);
+        assert_eq!(get(&full_world), results, "a full-world bbox doesn't change the results");
+
+        let no_filters = MatchOpts { zoom: 6, ..MatchOpts::default() };
+        let full_world = MatchOpts { bbox: Some([0, 0, 63, 63]), ..no_filters.clone() };
+        assert_eq!(get(&full_world), get(&no_filters));
+    }
+
+    #[test]
+    fn get_raw_test() {
         let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
         let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        let entries = vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 7, source_phrase_hash: 2 },
+            GridEntry { id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 },
+        ];
+        builder.insert(&key, entries).expect("Unable to insert record");
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let raw = reader.get_raw(&key).unwrap().unwrap();
+        // relev 1.0 is stored as 3 and 0.8 as 2, in the top four bits; x:1, y:1 -> z-order 3
+        assert_eq!(raw, vec![(0x37, 3, (1 << 8) | 2), (0x23, 12, 2 << 8)]);
+
+        assert_eq!(
+            reader.get_raw(&GridKey { phrase_id: 2, lang_set: LangSet::from(1) }).unwrap(),
+            None
+        );
+    }
 
+    #[test]
+    fn get_coords_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
         let entries = vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 3, x: 1, y: 1, relev: 0.6, score: 3, source_phrase_hash: 0 },
+            GridEntry { id: 4, x: 9, y: 2, relev: 0.8, score: 7, source_phrase_hash: 1 },
+            GridEntry { id: 5, x: 300, y: 40, relev: 1., score: 2, source_phrase_hash: 2 },
+        ];
+        builder.insert(&key, entries).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let coords: Vec<(u16, u16)> = reader.get_coords(&key).unwrap().unwrap().collect();
+        assert_eq!(coords.len(), 4, "one coord per relev/score level it's stored at");
+
+        let coord_set: BTreeSet<(u16, u16)> = coords.into_iter().collect();
+        let entry_set: BTreeSet<(u16, u16)> =
+            reader.get(&key).unwrap().unwrap().map(|entry| (entry.x, entry.y)).collect();
+        assert_eq!(coord_set, entry_set);
+
+        assert!(reader
+            .get_coords(&GridKey { phrase_id: 2, lang_set: LangSet::from(1) })
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cover_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+
+        let mut entries = vec![
             GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
             GridEntry { id: 1, x: 1, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
             GridEntry { id: 1, x: 2, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -130,10 +420,13 @@ mod tests {
         let reader = GridStore::new(directory.path()).unwrap();
         let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
 
-        // Results come back morton order. Maybe we should implement a custom partial_cmp
+        // Results come back in descending morton order
         assert_eq!(record[0], entries[1], "expected first result");
         assert_eq!(record[1], entries[2], "expected second result");
         assert_eq!(record[2], entries[0], "expected second result");
+
+        entries.sort_by_key(GridEntry::read_order_key);
+        assert_eq!(record, entries, "sorting grids reproduces read order");
     }
 
     #[test]
@@ -141,7 +434,7 @@ mod tests {
         let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
         let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
         let mut entries = vec![
             GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -154,11 +447,8 @@ mod tests {
         let reader = GridStore::new(directory.path()).unwrap();
         let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
 
-        entries.sort_by(|a, b| b.partial_cmp(a).unwrap());
-        assert_eq!(
-            record, entries,
-            "identical entries come out as went in, in reverse-sorted order"
-        );
+        entries.sort_by_key(GridEntry::read_order_key);
+        assert_eq!(record, entries, "identical entries come out as went in, in sorted order");
     }
 
     #[test]
@@ -167,10 +457,10 @@ mod tests {
         let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
         let keys = vec![
-            GridKey { phrase_id: 1, lang_set: 1 },
-            GridKey { phrase_id: 1, lang_set: 2 },
-            GridKey { phrase_id: 2, lang_set: 1 },
-            GridKey { phrase_id: 1, lang_set: 1 },
+            GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+            GridKey { phrase_id: 1, lang_set: LangSet::from(2) },
+            GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+            GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
         ];
 
         let mut i = 0;
@@ -193,8 +483,12 @@ mod tests {
 
         let reader = GridStore::new(directory.path()).unwrap();
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 2 }, lang_set: 1 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 2 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
@@ -203,19 +497,23 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0 }
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true }
             ]
         );
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
@@ -224,23 +522,27 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0 }
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true }
             ]
         );
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 0 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(0),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
@@ -249,23 +551,27 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0 }
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true }
             ]
         );
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 2 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(2),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
@@ -274,23 +580,27 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0 }
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true }
             ]
         );
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 3 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(3),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
@@ -299,39 +609,51 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 }
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true }
             ]
         );
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 1 }, lang_set: 1 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 1 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
             .collect();
         assert_eq!(records, []);
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 3, end: 4 }, lang_set: 1 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 3, end: 4 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
             .collect();
         assert_eq!(records, []);
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(
                 &search_key,
@@ -344,17 +666,21 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0 },
-                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0 }
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 1.0, radius: 0.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 0.96, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: false, distance: 0.0, scoredist: 7.0, radius: 0.0, within_bbox: true }
             ]
         );
 
         // Search just below existing records where z-order curve overlaps with bbox, but we do not
         // want records.
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(
                 &search_key,
@@ -366,8 +692,12 @@ mod tests {
         assert_eq!(records.len(), 0, "no matching recods in bbox");
 
         // Search where neither z-order curve or actual x,y overlap with bbox.
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(
                 &search_key,
@@ -382,8 +712,12 @@ mod tests {
             .collect();
         assert_eq!(records.len(), 0, "no matching recods in bbox");
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 2 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(2),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(
                 &search_key,
@@ -400,23 +734,27 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 15750.000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 2.0, scoredist: 913.3852617539986 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: false, distance: 16.0, scoredist: 787.5000000000001 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: false, distance: 31.0, scoredist: 406.4516129032259 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: false, distance: 31.0, scoredist: 406.4516129032259 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: false, distance: 32.0, scoredist: 393.75000000000006 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 14.0, scoredist: 130.48360882199978 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: false, distance: 30.0, scoredist: 60.89235078359991 }
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 15750.000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 2.0, scoredist: 913.3852617539986, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 42, y: 1, id: 22, source_phrase_hash: 0 }, matches_language: false, distance: 16.0, scoredist: 787.5000000000001, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 31, source_phrase_hash: 0 }, matches_language: false, distance: 31.0, scoredist: 406.4516129032259, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 57, y: 1, id: 29, source_phrase_hash: 0 }, matches_language: false, distance: 31.0, scoredist: 406.4516129032259, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 58, y: 1, id: 30, source_phrase_hash: 0 }, matches_language: false, distance: 32.0, scoredist: 393.75000000000006, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 14.0, scoredist: 130.48360882199978, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 56, y: 1, id: 28, source_phrase_hash: 0 }, matches_language: false, distance: 30.0, scoredist: 60.89235078359991, radius: 1000.0, within_bbox: true }
             ]
         );
 
-        let search_key =
-            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 2 };
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(2),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
         let records: Vec<_> = reader
             .streaming_get_matching(
                 &search_key,
@@ -433,13 +771,13 @@ mod tests {
         assert_eq!(
             records,
             [
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 15750.000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 2.0, scoredist: 913.3852617539986 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002 },
-                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 14.0, scoredist: 130.48360882199978 }
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 26, y: 1, id: 14, source_phrase_hash: 0 }, matches_language: true, distance: 0.0, scoredist: 15750.000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 15, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 25, y: 1, id: 13, source_phrase_hash: 0 }, matches_language: true, distance: 1.0, scoredist: 12600.000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 24, y: 1, id: 12, source_phrase_hash: 0 }, matches_language: true, distance: 2.0, scoredist: 913.3852617539986, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 23, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 7, x: 41, y: 1, id: 21, source_phrase_hash: 0 }, matches_language: false, distance: 15.0, scoredist: 840.0000000000002, radius: 1000.0, within_bbox: true },
+                MatchEntry { grid_entry: GridEntry { relev: 1.0, score: 1, x: 40, y: 1, id: 20, source_phrase_hash: 0 }, matches_language: false, distance: 14.0, scoredist: 130.48360882199978, radius: 1000.0, within_bbox: true }
             ]
         );
 
@@ -450,107 +788,1962 @@ mod tests {
         assert_eq!(listed_keys.unwrap(), orig_keys);
     }
 
-    static PREFIX_DATA: Lazy<(
-        GridStore,
-        GridStore,
-        Vec<String>,
-        tempfile::TempDir,
-        tempfile::TempDir,
-    )> = Lazy::new(|| {
-        let directory_with_boundaries: tempfile::TempDir = tempfile::tempdir().unwrap();
-        let directory_without_boundaries: tempfile::TempDir = tempfile::tempdir().unwrap();
-
-        let mut builder_with_boundaries =
-            GridStoreBuilder::new(directory_with_boundaries.path()).unwrap();
-        let mut builder_without_boundaries =
-            GridStoreBuilder::new(directory_without_boundaries.path()).unwrap();
+    /// Builds the same store as `matching_test`: two records each under phrase 1 in languages 1
+    /// and 2 and phrase 2 in language 1, with ids counting up across them. Returns the store's
+    /// keys in order alongside it.
+    fn matching_test_store() -> (tempfile::TempDir, GridStore, Vec<GridKey>) {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-        // this will produce 5000 phrases aaa, aab, aac, ...
-        let alphabet = "abcdefghijklmnopqrstuvwxyz";
-        let phrases: Vec<String> = alphabet
-            .bytes()
-            .flat_map(move |l1| {
-                alphabet.bytes().flat_map(move |l2| {
-                    alphabet.bytes().map(move |l3| String::from_utf8(vec![l1, l2, l3]).unwrap())
-                })
-            })
-            .take(5000)
-            .collect();
+        let mut keys = vec![
+            GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+            GridKey { phrase_id: 1, lang_set: LangSet::from(2) },
+            GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+            GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        ];
 
-        // insert phrases
-        for i in 0..=(phrases.len() as u32) {
-            let key = GridKey { phrase_id: i, lang_set: 1 };
-            let entries = vec![GridEntry {
-                id: i,
-                x: i as u16,
-                y: 1,
-                relev: 1.,
-                score: 1,
-                source_phrase_hash: 0,
-            }];
-            builder_with_boundaries.insert(&key, entries.clone()).expect("Unable to insert record");
-            builder_without_boundaries
-                .insert(&key, entries.clone())
-                .expect("Unable to insert record");
-        }
+        let mut i = 0;
+        for key in keys.iter() {
+            for _j in 0..2 {
+                #[cfg_attr(rustfmt, rustfmt::skip)]
+                let entries = vec![
+                    GridEntry { id: i, x: (2 * i) as u16, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+                    GridEntry { id: i + 1, x: ((2 * i) + 1) as u16, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
+                    GridEntry { id: i + 2, x: ((2 * i) + 2) as u16, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
+                    GridEntry { id: i + 3, x: ((2 * i) + 1) as u16, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
+                ];
+                i += 4;
 
-        // calculate bins
-        let mut bins: BTreeMap<u8, u32> = BTreeMap::new();
-        for (i, phrase) in phrases.iter().enumerate() {
-            // insert the first occurrence of every prefix
-            bins.entry(phrase.bytes().next().unwrap()).or_insert(i as u32);
+                builder.insert(key, entries).expect("Unable to insert record");
+            }
         }
-        let mut boundaries: Vec<_> = bins.values().cloned().collect();
-        boundaries.push(phrases.len() as u32);
-
-        builder_with_boundaries.load_bin_boundaries(boundaries).expect("Failed to load boundaries");
-
-        builder_with_boundaries.finish().unwrap();
-        builder_without_boundaries.finish().unwrap();
 
-        let reader_with_boundaries = GridStore::new(directory_with_boundaries.path()).unwrap();
-        let reader_without_boundaries =
-            GridStore::new(directory_without_boundaries.path()).unwrap();
+        builder.finish().unwrap();
+        keys.sort();
+        keys.dedup();
 
-        (
-            reader_with_boundaries,
-            reader_without_boundaries,
-            phrases,
-            directory_with_boundaries,
-            directory_without_boundaries,
-        )
-    });
+        let reader = GridStore::new(directory.path()).unwrap();
+        (directory, reader, keys)
+    }
 
-    fn find_prefix_range(prefix: &str) -> (u32, u32) {
-        let phrases = &PREFIX_DATA.2;
+    #[test]
+    fn entries_stream_test() {
+        let (_directory, reader, keys) = matching_test_store();
 
-        let start =
-            phrases.iter().enumerate().find(|(_, phrase)| phrase.starts_with(prefix)).unwrap().0;
-        let end = phrases
+        let rows: Vec<(GridKey, GridEntry)> =
+            reader.entries_stream().map(|item| item.unwrap()).collect();
+        let flattened: Vec<(GridKey, GridEntry)> = reader
             .iter()
-            .enumerate()
-            .rev()
-            .find(|(_, phrase)| phrase.starts_with(prefix))
-            .unwrap()
-            .0
-            + 1;
-        (start as u32, end as u32)
+            .map(|item| item.unwrap())
+            .flat_map(|(key, entries)| entries.into_iter().map(move |entry| (key.clone(), entry)))
+            .collect();
+        let entry_count: usize =
+            keys.iter().map(|key| reader.phrase_entry_count(key).unwrap()).sum();
+        assert_eq!(rows.len(), entry_count, "one row per grid in the store");
+        assert_eq!(rows, flattened, "rows come out in the same order as iter's grids");
     }
 
     #[test]
-    fn prefix_make_bins() {
-        Lazy::force(&PREFIX_DATA);
+    fn has_match_test() {
+        let (_directory, reader, _keys) = matching_test_store();
+
+        let match_key = |match_phrase, lang_set| MatchKey {
+            match_phrase,
+            lang_set: LangSet::from(lang_set),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let keys = [
+            (match_key(MatchPhrase::Exact(1), 1), true),
+            (match_key(MatchPhrase::Exact(2), 1), true),
+            // phrase 2 has no key in language 1, but matching returns its grids with a penalty
+            (match_key(MatchPhrase::Exact(2), 2), true),
+            (match_key(MatchPhrase::Exact(3), 1), false),
+            (match_key(MatchPhrase::Range { start: 0, end: 1 }, 1), false),
+            (match_key(MatchPhrase::Range { start: 2, end: 10 }, 1), true),
+            (match_key(MatchPhrase::Range { start: 3, end: 10 }, 1), false),
+            (match_key(MatchPhrase::Set(vec![0, 3, 7]), 1), false),
+            (match_key(MatchPhrase::Set(vec![0, 2]), 1), true),
+        ];
+        for (key, expected) in keys.iter() {
+            assert_eq!(reader.has_match(key).unwrap(), *expected, "has_match for {:?}", key);
+            let any = reader
+                .streaming_get_matching(key, &MatchOpts::default(), std::usize::MAX)
+                .unwrap()
+                .next()
+                .is_some();
+            assert_eq!(any, *expected, "agrees with matching for {:?}", key);
+        }
     }
 
     #[test]
-    fn prefix_test_with_bins() {
-        let (reader_with_boundaries, reader_without_boundaries) = (&PREFIX_DATA.0, &PREFIX_DATA.1);
-        let starts_with_b = find_prefix_range("b");
+    fn get_matching_filtered_test() {
+        let (_directory, reader, _keys) = matching_test_store();
+
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let all: Vec<MatchEntry> = reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
+            .unwrap()
+            .collect();
+        let top_scores: Vec<MatchEntry> = reader
+            .get_matching_filtered(&search_key, &MatchOpts::default(), |grid| grid.score == 7)
+            .unwrap()
+            .collect();
+        assert!(!top_scores.is_empty());
+        assert!(top_scores.len() < all.len());
+        assert_eq!(
+            top_scores,
+            all.into_iter().filter(|entry| entry.grid_entry.score == 7).collect::<Vec<_>>(),
+            "only the score 7 grids are returned, in the usual order"
+        );
+    }
+
+    #[test]
+    fn id_filter_test() {
+        let (_directory, reader, _keys) = matching_test_store();
+
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let filtered_ids = |id_filter: &[u32]| -> Vec<u32> {
+            let match_opts = MatchOpts {
+                id_filter: Some(std::sync::Arc::new(id_filter.iter().cloned().collect())),
+                ..MatchOpts::default()
+            };
+            reader
+                .streaming_get_matching(&search_key, &match_opts, MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| entry.grid_entry.id)
+                .collect()
+        };
+        assert_eq!(filtered_ids(&[12, 14]), vec![14, 12]);
+        assert_eq!(filtered_ids(&[12, 14, 999]), vec![14, 12], "ids not in the store are ignored");
+        assert_eq!(filtered_ids(&[]), Vec::<u32>::new(), "an empty filter matches nothing");
+    }
+
+    #[test]
+    fn warm_test() {
+        let (_directory, reader, _keys) = matching_test_store();
+
+        reader.warm(0, std::u32::MAX).unwrap();
+        reader.warm(2, 2).unwrap();
+        reader.warm(5, 1).unwrap();
+    }
+
+    #[test]
+    fn get_matching_page_test() {
+        let (_directory, reader, _keys) = matching_test_store();
+
+        // paging through in twos gives the same results as getting them all at once
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let match_opts = MatchOpts {
+            proximity: Some(Proximity { point: [26, 1], radius: 1000. }),
+            ..MatchOpts::default()
+        };
+        let all_records: Vec<_> = reader
+            .streaming_get_matching(&search_key, &match_opts, MAX_CONTEXTS)
+            .unwrap()
+            .collect();
+        let mut paged_records = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let (page, next_cursor) =
+                reader.get_matching_page(&search_key, &match_opts, cursor, 2).unwrap();
+            assert!(page.len() <= 2);
+            paged_records.extend(page);
+            pages += 1;
+            match next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
+            }
+        }
+        assert_eq!(paged_records, all_records);
+        assert_eq!(pages, (all_records.len() + 1) / 2);
+
+        let (_, cursor) = reader.get_matching_page(&search_key, &match_opts, None, 2).unwrap();
+        let other_key = MatchKey { match_phrase: MatchPhrase::Exact(2), ..search_key.clone() };
+        match reader.get_matching_page(&other_key, &match_opts, cursor, 2) {
+            Err(GridStoreError::InvalidCursor { offset }) => assert_eq!(offset, 2),
+            other => panic!("a cursor from another query is rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn iter_rev_test() {
+        let (_directory, reader, _keys) = matching_test_store();
+
+        let reversed: Vec<_> = reader.iter_rev().map(|item| item.unwrap()).collect();
+        let mut forward: Vec<_> = reader.iter().map(|item| item.unwrap()).collect();
+        forward.reverse();
+        assert_eq!(reversed, forward);
+        let reversed_keys: Vec<_> = reversed.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            reversed_keys,
+            [
+                GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+                GridKey { phrase_id: 1, lang_set: LangSet::from(2) },
+                GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_in_range_test() {
+        let (_directory, reader, keys) = matching_test_store();
+
+        let in_range: Result<Vec<_>, _> = reader.keys_in_range(1, 2).collect();
+        assert_eq!(in_range.unwrap(), &keys[..2], "only phrase 1's keys are in [1, 2)");
+        let in_range: Result<Vec<_>, _> = reader.keys_in_range(2, 10).collect();
+        assert_eq!(in_range.unwrap(), &keys[2..]);
+        assert_eq!(reader.keys_in_range(0, 1).count(), 0);
+        assert_eq!(reader.keys_in_range(3, 10).count(), 0);
+    }
+
+    #[test]
+    fn grids_in_tile_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        // four grids in the children of the z13 tile 1/1, and one outside of it
+        let entries = vec![
+            GridEntry { id: 1, x: 2, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 3, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 3, x: 2, y: 3, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 4, x: 3, y: 3, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 5, x: 4, y: 3, relev: 1., score: 1, source_phrase_hash: 0 },
+        ];
+        builder.insert(&key, entries).expect("Unable to insert record");
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+
+        // a parent tile covers all of its children
+        let mut ids: Vec<_> = reader
+            .grids_in_tile(&search_key, 13, 1, 1, 14)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.grid_entry.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, [1, 2, 3, 4], "parent tile returns all child grids");
+
+        // a child tile matches the grid of its parent
+        let ids: Vec<_> = reader
+            .grids_in_tile(&search_key, 15, 9, 6, 14)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.grid_entry.id)
+            .collect();
+        assert_eq!(ids, [5], "child tile returns the parent grid");
+
+        // a tile at the store zoom only matches itself
+        let ids: Vec<_> = reader
+            .grids_in_tile(&search_key, 14, 2, 2, 14)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.grid_entry.id)
+            .collect();
+        assert_eq!(ids, [1]);
+    }
+
+    #[test]
+    fn get_matching_at_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        let entries: Vec<GridEntry> = (0..20)
+            .map(|i| GridEntry {
+                id: i,
+                x: (i * 300) as u16,
+                y: (i * 150) as u16,
+                relev: 1.,
+                score: (i % 7) as u8,
+                source_phrase_hash: 0,
+            })
+            .collect();
+        builder.insert(&key, entries).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        // the options are at z6, and the store is at z14
+        let match_opts = MatchOpts {
+            zoom: 6,
+            bbox: Some([0, 0, 15, 15]),
+            proximity: Some(Proximity { point: [10, 5], radius: 400. }),
+            ..MatchOpts::default()
+        };
+
+        let at_zoom: Vec<_> =
+            reader.get_matching_at(&search_key, &match_opts, 14).unwrap().collect();
+        let adjusted = match_opts.adjust_to_zoom(14);
+        assert_eq!(adjusted.zoom, 14);
+        let manual: Vec<_> = reader
+            .streaming_get_matching(&search_key, &adjusted, std::usize::MAX)
+            .unwrap()
+            .collect();
+        assert!(!manual.is_empty() && manual.len() < 20, "the bbox filters some grids out");
+        assert_eq!(at_zoom, manual);
+    }
+
+    #[test]
+    fn phrase_languages_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        let entries =
+            vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }];
+        // languages [1], [2] and [0, 1] for phrase 1
+        for lang_set in [1 << 1, 1 << 2, (1 << 0) | (1 << 1)].iter() {
+            let key = GridKey { phrase_id: 1, lang_set: LangSet::from(*lang_set) };
+            builder.insert(&key, entries.clone()).expect("Unable to insert record");
+        }
+        builder
+            .insert(&GridKey { phrase_id: 2, lang_set: LangSet::from(1 << 5) }, entries.clone())
+            .unwrap();
+        builder.insert(&GridKey::all_languages(3), entries.clone()).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        assert_eq!(
+            reader.phrase_languages(1).unwrap(),
+            LangSet::from(0b111),
+            "union of the stored lang sets"
+        );
+        assert_eq!(reader.phrase_languages(2).unwrap(), LangSet::from(1 << 5));
+        assert_eq!(reader.phrase_languages(3).unwrap(), ALL_LANGUAGES, "all-languages marker");
+        assert_eq!(
+            reader.phrase_languages(4).unwrap(),
+            LangSet::default(),
+            "missing phrase has no languages"
+        );
+
+        // the all-languages key sorts alongside the specific-language ones
+        let in_range: Result<Vec<_>, _> = reader.keys_in_range(2, 4).collect();
+        assert_eq!(
+            in_range.unwrap(),
+            vec![
+                GridKey { phrase_id: 2, lang_set: LangSet::from(1 << 5) },
+                GridKey::all_languages(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_language_matching_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        // phrase 1 inserted separately under languages [0], [1] and [2]
+        for (lang, id) in [(0, 1), (1, 2), (2, 3)].iter() {
+            let key = GridKey { phrase_id: 1, lang_set: langarray_to_langset(&[*lang]).unwrap() };
+            let entries = vec![GridEntry {
+                id: *id,
+                x: *id as u16,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&key, entries).expect("Unable to insert record");
+        }
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: langarray_to_langset(&[0, 2]).unwrap(),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let records: Vec<_> = reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
+            .unwrap()
+            .map(|entry| (entry.grid_entry.id, entry.grid_entry.relev, entry.matches_language))
+            .collect();
+        assert_eq!(
+            records,
+            vec![(3, 1., true), (1, 1., true), (2, 0.96, false)],
+            "grids from both requested languages match, the other language is penalized"
+        );
+    }
+
+    #[test]
+    fn lang_fallbacks_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        // phrase 1 with a grid under each of languages [0] to [3], at increasing distances
+        for lang in 0..4u32 {
+            let key = GridKey { phrase_id: 1, lang_set: langarray_to_langset(&[lang]).unwrap() };
+            let entries = vec![GridEntry {
+                id: lang,
+                x: lang as u16,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&key, entries).expect("Unable to insert record");
+        }
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        // prefer [3], then fall back to [1], then [2]
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: langarray_to_langset(&[3]).unwrap(),
+            lang_fallbacks: vec![
+                langarray_to_langset(&[1]).unwrap(),
+                langarray_to_langset(&[2]).unwrap(),
+            ],
+            ignore_language: false,
+        };
+        let records: Vec<_> = reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
+            .unwrap()
+            .map(|entry| (entry.grid_entry.id, entry.grid_entry.relev, entry.matches_language))
+            .collect();
+        let ids: Vec<_> = records.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, vec![3, 1, 2, 0], "earlier fallback tiers outrank later ones");
+        assert_eq!(records[0].1, 1.);
+        assert!(records[0].1 > records[1].1 && records[1].1 > records[2].1);
+        assert!(records[2].1 > records[3].1);
+        assert_eq!(records[3].1, LANGUAGE_MISMATCH_MULTIPLIER);
+        assert_eq!(
+            records.iter().map(|(_, _, matches)| *matches).collect::<Vec<_>>(),
+            vec![true, true, true, false]
+        );
+
+        // with no fallbacks, everything outside lang_set gets the same penalty
+        let search_key = MatchKey { lang_fallbacks: vec![], ..search_key };
+        let relevs: Vec<_> = reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
+            .unwrap()
+            .map(|entry| entry.grid_entry.relev)
+            .collect();
+        assert_eq!(relevs, vec![1., 0.96, 0.96, 0.96]);
+    }
+
+    #[test]
+    fn ignore_language_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        // phrase 1 under languages [0], [1], no languages at all, and all languages
+        let lang_sets =
+            [LangSet::from(&[0][..]), LangSet::from(&[1][..]), LangSet::default(), ALL_LANGUAGES];
+        for (id, lang_set) in lang_sets.iter().enumerate() {
+            let entries = vec![GridEntry {
+                id: id as u32,
+                x: id as u16,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&GridKey { phrase_id: 1, lang_set: *lang_set }, entries).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let relevs = |match_key: &MatchKey| -> Vec<(u32, f64, bool)> {
+            let mut records: Vec<_> = reader
+                .streaming_get_matching(match_key, &MatchOpts::default(), MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| (entry.grid_entry.id, entry.grid_entry.relev, entry.matches_language))
+                .collect();
+            records.sort_by_key(|(id, _, _)| *id);
+            records
+        };
+
+        let all_languages = MatchKey::all_languages(MatchPhrase::Exact(1));
+        assert_eq!(
+            relevs(&all_languages),
+            vec![(0, 1., true), (1, 1., true), (2, 0.96, false), (3, 1., true)],
+            "an all-languages query still penalizes grids stored under no language"
+        );
+
+        let ignoring = MatchKey {
+            lang_set: LangSet::from(&[0][..]),
+            ignore_language: true,
+            ..all_languages.clone()
+        };
+        assert_eq!(
+            relevs(&ignoring),
+            vec![(0, 1., true), (1, 1., true), (2, 1., true), (3, 1., true)],
+            "ignoring language matches everything without a penalty, whatever lang_set is"
+        );
+        let not_ignoring = MatchKey { ignore_language: false, ..ignoring };
+        assert_eq!(relevs(&not_ignoring)[1], (1, 0.96, false));
+    }
+
+    #[test]
+    fn can_stack_with_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        GridStoreBuilder::new(directory.path()).unwrap().finish().unwrap();
+        let store = GridStore::new(directory.path()).unwrap();
+        let subquery = |idx, zoom, mask| PhrasematchSubquery {
+            store: &store,
+            weight: 0.5,
+            match_key: MatchKey::all_languages(MatchPhrase::Exact(1)),
+            idx,
+            zoom,
+            mask,
+            radius_override: None,
+        };
+
+        let base = subquery(0, 14, 0b001);
+        assert!(base.can_stack_with(&subquery(1, 14, 0b010)), "same zoom, different index");
+        assert!(base.can_stack_with(&subquery(1, 12, 0b110)), "different zoom, different index");
+        assert!(subquery(1, 12, 0b110).can_stack_with(&base), "symmetric");
+
+        assert!(!base.can_stack_with(&subquery(1, 12, 0b011)), "masks share a token");
+        assert!(!base.can_stack_with(&subquery(0, 14, 0b010)), "same index at the same zoom");
+        assert!(!base.can_stack_with(&subquery(0, 12, 0b110)), "same index, different zoom");
+        assert!(!subquery(0, 12, 0b110).can_stack_with(&base), "symmetric");
+        assert!(!base.can_stack_with(&base), "a subquery can't stack with itself");
+    }
+
+    #[test]
+    fn phrase_set_matching_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        for phrase_id in 1..=4 {
+            let entries = vec![GridEntry {
+                id: phrase_id * 10,
+                x: phrase_id as u16,
+                y: 1,
+                relev: 1.,
+                score: phrase_id as u8,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&GridKey { phrase_id, lang_set: LangSet::from(1) }, entries).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let matching_ids = |phrase_ids: Vec<u32>| -> Vec<u32> {
+            let match_key = MatchKey {
+                match_phrase: MatchPhrase::Set(phrase_ids),
+                lang_set: LangSet::from(1),
+                lang_fallbacks: vec![],
+                ignore_language: false,
+            };
+            reader
+                .streaming_get_matching(&match_key, &MatchOpts::default(), MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| entry.grid_entry.id)
+                .collect()
+        };
+
+        assert_eq!(matching_ids(vec![1, 3]), vec![30, 10], "only phrases 1 and 3, best first");
+        assert_eq!(matching_ids(vec![3, 1, 3]), vec![30, 10], "order and repeats don't matter");
+        assert_eq!(matching_ids(vec![3, 9]), vec![30], "missing phrases are skipped");
+        assert_eq!(matching_ids(vec![]), Vec::<u32>::new(), "empty set matches nothing");
+
+        let mut db_key = Vec::new();
+        GridKey { phrase_id: 4, lang_set: LangSet::from(1) }
+            .write_to(TypeMarker::SinglePhrase, &mut db_key)
+            .unwrap();
+        assert!(TypeMarker::SinglePhrase.marks(&db_key));
+        assert!(!TypeMarker::PrefixBin.marks(&db_key));
+    }
+
+    #[test]
+    fn match_entry_radius_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let entries = vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 9, y: 9, relev: 1., score: 3, source_phrase_hash: 0 },
+        ];
+        builder.insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, entries).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let match_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let radii = |match_opts: &MatchOpts| -> Vec<f64> {
+            reader
+                .streaming_get_matching(&match_key, match_opts, MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| entry.radius)
+                .collect()
+        };
+
+        let match_opts = MatchOpts {
+            proximity: Some(Proximity { point: [1, 1], radius: 400. }),
+            ..MatchOpts::default()
+        };
+        assert_eq!(radii(&match_opts), vec![400., 400.]);
+        assert_eq!(radii(&MatchOpts::default()), vec![0., 0.], "no proximity, no radius");
+    }
+
+    #[test]
+    fn distance_unit_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let entries: Vec<_> = (0..6)
+            .map(|i| GridEntry {
+                id: i,
+                x: i as u16 * 5,
+                y: 1,
+                relev: 1.,
+                score: 3,
+                source_phrase_hash: 0,
+            })
+            .collect();
+        builder.insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, entries).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let match_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let matches = |radius, distance_unit| -> Vec<MatchEntry> {
+            let match_opts = MatchOpts {
+                zoom: 14,
+                proximity: Some(Proximity { point: [1, 1], radius }),
+                distance_unit,
+                ..MatchOpts::default()
+            };
+            reader.streaming_get_matching(&match_key, &match_opts, MAX_CONTEXTS).unwrap().collect()
+        };
+
+        let miles = matches(10., DistanceUnit::Miles);
+        let kilometers = matches(16.09344, DistanceUnit::Kilometers);
+        assert_eq!(miles.len(), kilometers.len());
+        for (in_miles, in_kilometers) in miles.iter().zip(kilometers.iter()) {
+            assert_eq!(in_miles.grid_entry, in_kilometers.grid_entry);
+            assert!((in_miles.scoredist - in_kilometers.scoredist).abs() < 1e-6);
+            assert_eq!(in_kilometers.radius, 16.09344, "the radius is reported as given");
+        }
+        let scoredists = |entries: &[MatchEntry]| -> Vec<f64> {
+            entries.iter().map(|entry| entry.scoredist).collect()
+        };
+        assert!(
+            scoredists(&matches(16.09344, DistanceUnit::Miles)) != scoredists(&kilometers),
+            "the unit makes a difference"
+        );
+    }
+
+    #[test]
+    fn into_builder_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id, score| GridEntry {
+            id,
+            x: id as u16,
+            y: 1,
+            relev: 0.8,
+            score,
+            source_phrase_hash: 3,
+        };
+        builder
+            .insert(
+                &GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+                vec![grid(1, 2), grid(2, 7)],
+            )
+            .unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 2, lang_set: LangSet::from(3) }, vec![grid(3, 1)])
+            .unwrap();
+        builder.insert(&GridKey::all_languages(3), vec![grid(4, 4)]).unwrap();
+        builder.load_bin_boundaries(vec![0, 2, 4]).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let original: Vec<_> = reader.iter().map(|item| item.unwrap()).collect();
+
+        let edited_directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = reader.into_builder(edited_directory.path()).unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 2, lang_set: LangSet::from(3) }, vec![grid(5, 6)])
+            .unwrap();
+        builder.finish().unwrap();
+
+        let edited = GridStore::new(edited_directory.path()).unwrap();
+        let edited_records: Vec<_> = edited.iter().map(|item| item.unwrap()).collect();
+        assert_eq!(edited_records.len(), 3);
+        assert_eq!(edited_records[0], original[0], "untouched phrases are carried over");
+        assert_eq!(
+            edited_records[1],
+            (GridKey { phrase_id: 2, lang_set: LangSet::from(3) }, vec![grid(5, 6)])
+        );
+        assert_eq!(edited_records[2], original[2]);
+        assert_eq!(edited.bin_boundaries(), vec![0, 2, 4]);
+
+        let source = GridStore::new(directory.path()).unwrap();
+        assert_eq!(source.iter().count(), 3, "the source store is left alone");
+        assert_eq!(
+            source
+                .get(&GridKey { phrase_id: 2, lang_set: LangSet::from(3) })
+                .unwrap()
+                .unwrap()
+                .next(),
+            Some(grid(3, 1))
+        );
+    }
+
+    #[test]
+    fn retain_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id, score| GridEntry {
+            id,
+            x: id as u16,
+            y: 1,
+            relev: 1.,
+            score,
+            source_phrase_hash: 0,
+        };
+        builder
+            .insert(
+                &GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+                vec![grid(1, 2), grid(2, 7)],
+            )
+            .unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 2, lang_set: LangSet::from(1) }, vec![grid(3, 1)])
+            .unwrap();
+        builder
+            .insert(
+                &GridKey { phrase_id: 3, lang_set: LangSet::from(1) },
+                vec![grid(4, 5), grid(5, 6)],
+            )
+            .unwrap();
+        builder.load_bin_boundaries(vec![0, 2, 4]).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let retained_directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        reader.retain(retained_directory.path(), |_, entry| entry.score >= 5).unwrap();
+
+        let retained = GridStore::new(retained_directory.path()).unwrap();
+        let mut records: Vec<_> = retained.iter().map(|item| item.unwrap()).collect();
+        for (_, entries) in records.iter_mut() {
+            entries.sort_by_key(|entry| entry.id);
+        }
+        assert_eq!(
+            records,
+            vec![
+                (GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, vec![grid(2, 7)]),
+                (
+                    GridKey { phrase_id: 3, lang_set: LangSet::from(1) },
+                    vec![grid(4, 5), grid(5, 6)]
+                ),
+            ],
+            "only grids with a score of at least 5 are kept, and emptied keys are dropped"
+        );
+        assert_eq!(retained.bin_boundaries(), vec![0, 2, 4]);
+
+        // the prefix bin for phrases 2 and 3 is rebuilt from the retained grids
+        let match_key = MatchKey::all_languages(MatchPhrase::Range { start: 2, end: 4 });
+        let mut bin_ids: Vec<u32> = retained
+            .streaming_get_matching(&match_key, &MatchOpts::default(), std::usize::MAX)
+            .unwrap()
+            .map(|entry| entry.grid_entry.id)
+            .collect();
+        bin_ids.sort();
+        assert_eq!(bin_ids, vec![4, 5]);
+        assert_eq!(reader.iter().count(), 3, "the source store is left alone");
+    }
+
+    #[test]
+    fn rename_languages_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id| GridEntry { id, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 };
+        builder
+            .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(&[1][..]) }, vec![grid(1)])
+            .unwrap();
+        builder.insert(&GridKey::all_languages(2), vec![grid(2)]).unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 3, lang_set: LangSet::from(&[1, 5][..]) }, vec![grid(3)])
+            .unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let renamed_directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        reader.rename_languages(&[0, 3], renamed_directory.path()).unwrap();
+
+        let renamed = GridStore::new(renamed_directory.path()).unwrap();
+        let keys: Vec<GridKey> = renamed.keys().map(|key| key.unwrap()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                GridKey { phrase_id: 1, lang_set: LangSet::from(&[3][..]) },
+                GridKey::all_languages(2),
+                GridKey { phrase_id: 3, lang_set: LangSet::from(&[3, 5][..]) },
+            ],
+            "language 1 becomes 3, unmapped languages and all-languages keys are left alone"
+        );
+
+        let matches_language = |lang| {
+            let match_key = MatchKey {
+                match_phrase: MatchPhrase::Exact(1),
+                lang_set: LangSet::from(&[lang][..]),
+                lang_fallbacks: vec![],
+                ignore_language: false,
+            };
+            let entries: Vec<_> = renamed
+                .streaming_get_matching(&match_key, &MatchOpts::default(), std::usize::MAX)
+                .unwrap()
+                .collect();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].grid_entry.id, 1);
+            entries[0].matches_language
+        };
+        assert!(matches_language(3), "the renamed store matches under the new language");
+        assert!(!matches_language(1), "and no longer under the old one");
+
+        let err_directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        match reader.rename_languages(&[0, 200], err_directory.path().join("out")) {
+            Err(GridStoreError::Other(_)) => {}
+            other => panic!("expected an out of range error, got {:?}", other),
+        }
+        assert!(!err_directory.path().join("out").exists(), "nothing is written");
+    }
+
+    #[test]
+    fn all_languages_key_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let lang_sets = [1, 2, 1 << 100, ALL_LANGUAGES.bits()];
+        for (i, lang_set) in lang_sets.iter().enumerate() {
+            let entries = vec![GridEntry {
+                id: i as u32,
+                x: 1,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder
+                .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(*lang_set) }, entries)
+                .unwrap();
+        }
+        builder.finish().unwrap();
+
+        assert_eq!(GridKey::all_languages(1), GridKey { phrase_id: 1, lang_set: ALL_LANGUAGES });
+        let reader = GridStore::new(directory.path()).unwrap();
+        let search_key = MatchKey::all_languages(MatchPhrase::Exact(1));
+        let mut matched: Vec<(u32, bool)> = reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
+            .unwrap()
+            .map(|entry| (entry.grid_entry.id, entry.matches_language))
+            .collect();
+        matched.sort();
+        assert_eq!(
+            matched,
+            [(0, true), (1, true), (2, true), (3, true)],
+            "grids stored under any language match"
+        );
+    }
+
+    #[test]
+    fn max_per_language_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid =
+            |id| GridEntry { id, x: id as u16, y: 1, relev: 1., score: 3, source_phrase_hash: 0 };
+        // three grids in each of languages 0, 1 and 2, plus two for all languages
+        for lang in 0..3 {
+            let entries = (0..3).map(|i| grid(lang * 10 + i)).collect();
+            builder
+                .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1 << lang) }, entries)
+                .unwrap();
+        }
+        builder.insert(&GridKey::all_languages(1), vec![grid(30), grid(31)]).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let languages = |match_opts: &MatchOpts| -> Vec<u32> {
+            let search_key = MatchKey {
+                match_phrase: MatchPhrase::Exact(1),
+                lang_set: LangSet::from(0b111),
+                lang_fallbacks: vec![],
+                ignore_language: false,
+            };
+            let mut ids: Vec<u32> = reader
+                .streaming_get_matching(&search_key, match_opts, MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| entry.grid_entry.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(languages(&MatchOpts::default()).len(), 11);
+        let capped = languages(&MatchOpts { max_per_language: Some(1), ..MatchOpts::default() });
+        assert_eq!(capped.iter().filter(|id| **id < 10).count(), 1, "one grid in language 0");
+        assert_eq!(capped.iter().filter(|id| **id >= 10 && **id < 20).count(), 1);
+        assert_eq!(capped.iter().filter(|id| **id >= 20 && **id < 30).count(), 1);
+        assert_eq!(
+            capped.iter().filter(|id| **id >= 30).count(),
+            2,
+            "all-languages grids aren't capped"
+        );
+        assert_eq!(capped.len(), 5);
+    }
+
+    #[test]
+    fn min_score_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        let entries: Vec<GridEntry> =
+            [(1., 7), (1., 6), (1., 5), (1., 3), (1., 1), (0.8, 7), (0.8, 2)]
+                .iter()
+                .enumerate()
+                .map(|(i, &(relev, score))| GridEntry {
+                    id: i as u32,
+                    x: i as u16,
+                    y: 1,
+                    relev,
+                    score,
+                    source_phrase_hash: 0,
+                })
+                .collect();
+        builder.insert(&key, entries).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let search_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let matching = |match_opts: &MatchOpts| -> Vec<MatchEntry> {
+            reader.streaming_get_matching(&search_key, match_opts, MAX_CONTEXTS).unwrap().collect()
+        };
+
+        let all = matching(&MatchOpts::default());
+        assert_eq!(all.len(), 7);
+
+        let high = matching(&MatchOpts { min_score: Some(6), ..MatchOpts::default() });
+        assert_eq!(
+            high,
+            all.into_iter().filter(|entry| entry.grid_entry.score >= 6).collect::<Vec<_>>(),
+            "stopping early returns the same grids as filtering afterward"
+        );
+        assert_eq!(
+            high.iter().map(|entry| entry.grid_entry.id).collect::<Vec<_>>(),
+            [0, 1, 5],
+            "the minimum applies within every relevance level"
+        );
+
+        let none = matching(&MatchOpts { min_score: Some(8), ..MatchOpts::default() });
+        assert_eq!(none, []);
+
+        // Levels are read in the order they're written, and reading a relevance group stops at
+        // the first level below the minimum. A record written by hand with a higher score after
+        // a lower one shows the later level is never read, where filtering afterward would
+        // still return its grid.
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let builder = GridStoreBuilder::new(directory.path()).unwrap();
+        builder.finish().unwrap();
+        {
+            let mut writer = gridstore_format::Writer::new();
+            let low_ids = writer.write_fixed_vec(&[10u32 << 8]);
+            let high_ids = writer.write_fixed_vec(&[11u32 << 8]);
+            let low_coords =
+                writer.write_uniform_vec(&[gridstore_format::Coord { coord: 1, ids: low_ids }]);
+            let high_coords =
+                writer.write_uniform_vec(&[gridstore_format::Coord { coord: 2, ids: high_ids }]);
+            // relevance 1 with scores 3, then 6
+            let relev_scores = writer.write_var_vec(&[
+                gridstore_format::RelevScore { relev_score: 0x33, coords: low_coords },
+                gridstore_format::RelevScore { relev_score: 0x36, coords: high_coords },
+            ]);
+            writer.write_fixed_scalar(gridstore_format::PhraseRecord { relev_scores });
+
+            let mut db_key = Vec::new();
+            key.write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+            let db = rocksdb::DB::open_default(directory.path()).unwrap();
+            db.put(&db_key, writer.finish()).unwrap();
+        }
+        let reader = GridStore::new(directory.path()).unwrap();
+        let ids = |match_opts: &MatchOpts| -> Vec<u32> {
+            reader
+                .streaming_get_matching(&search_key, match_opts, MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| entry.grid_entry.id)
+                .collect()
+        };
+        assert_eq!(ids(&MatchOpts::default()), [11, 10]);
+        assert_eq!(
+            ids(&MatchOpts { min_score: Some(5), ..MatchOpts::default() }),
+            Vec::<u32>::new(),
+            "the score 6 level after the score 3 one isn't read"
+        );
+    }
+
+    #[test]
+    fn diff_test() {
+        let grid = |id, score| GridEntry {
+            id,
+            x: id as u16,
+            y: 1,
+            relev: 1.,
+            score,
+            source_phrase_hash: 0,
+        };
+        let build = |records: &[(GridKey, Vec<GridEntry>)]| {
+            let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+            let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+            for (key, entries) in records {
+                builder.insert(key, entries.clone()).unwrap();
+            }
+            builder.load_bin_boundaries(vec![0, 5]).unwrap();
+            builder.finish().unwrap();
+            let store = GridStore::new(directory.path()).unwrap();
+            (directory, store)
+        };
+
+        let records = vec![
+            (GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, vec![grid(1, 2), grid(2, 7)]),
+            (GridKey { phrase_id: 2, lang_set: LangSet::from(1) }, vec![grid(3, 1), grid(4, 1)]),
+            (GridKey { phrase_id: 3, lang_set: LangSet::from(2) }, vec![grid(5, 4)]),
+        ];
+        let mut changed = records.clone();
+        changed[1].1 = vec![grid(3, 1), grid(6, 5)];
+
+        let (_a_dir, a) = build(&records);
+        let (_same_dir, same) = build(&records);
+        let (_b_dir, b) = build(&changed);
+
+        assert_eq!(GridStore::diff(&a, &same).unwrap(), [], "identical stores have no diffs");
+        assert_eq!(
+            GridStore::diff(&a, &b).unwrap(),
+            [Diff::EntriesDiffer {
+                key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+                only_in_first: vec![grid(4, 1)],
+                only_in_second: vec![grid(6, 5)],
+            }]
+        );
+
+        let (_c_dir, c) = build(&[records[0].clone(), records[2].clone()]);
+        assert_eq!(
+            GridStore::diff(&a, &c).unwrap(),
+            [Diff::OnlyInFirst { key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) } }]
+        );
+        assert_eq!(
+            GridStore::diff(&c, &a).unwrap(),
+            [Diff::OnlyInSecond { key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) } }]
+        );
+    }
+
+    #[test]
+    fn merge_iter_test() {
+        let grid = |id| GridEntry { id, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 };
+        let build = |records: Vec<(GridKey, Vec<GridEntry>)>| {
+            let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+            let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+            for (key, entries) in records {
+                builder.insert(&key, entries).unwrap();
+            }
+            builder.finish().unwrap();
+            let store = GridStore::new(directory.path()).unwrap();
+            (directory, store)
+        };
+        let key = |phrase_id, lang_set| GridKey { phrase_id, lang_set: LangSet::from(lang_set) };
+
+        let (_dir1, store1) = build(vec![
+            (key(1, 1), vec![grid(1)]),
+            (key(3, 1), vec![grid(3)]),
+            (key(3, 256), vec![grid(4)]),
+            (key(7, 1), vec![grid(7)]),
+        ]);
+        let (_dir2, store2) = build(vec![
+            (key(2, 1), vec![grid(12)]),
+            (key(3, 1), vec![grid(13)]),
+            (key(7, 1), vec![grid(17)]),
+            (key(9, 2), vec![grid(19)]),
+        ]);
+
+        let merged: Vec<_> =
+            GridStore::merge_iter(&[&store1, &store2]).map(|item| item.unwrap()).collect();
+        assert_eq!(
+            merged,
+            vec![
+                (key(1, 1), vec![(0, vec![grid(1)])]),
+                (key(2, 1), vec![(1, vec![grid(12)])]),
+                (key(3, 1), vec![(0, vec![grid(3)]), (1, vec![grid(13)])]),
+                (key(3, 256), vec![(0, vec![grid(4)])]),
+                (key(7, 1), vec![(0, vec![grid(7)]), (1, vec![grid(17)])]),
+                (key(9, 2), vec![(1, vec![grid(19)])]),
+            ],
+            "keys shared by both stores are grouped, in store order"
+        );
+
+        let single: Vec<_> = GridStore::merge_iter(&[&store2]).map(|item| item.unwrap()).collect();
+        assert_eq!(single.len(), 4);
+        assert_eq!(GridStore::merge_iter(&[]).count(), 0);
+    }
+
+    #[test]
+    fn iter_rev_language_order_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        for (phrase_id, lang_set) in
+            &[(1, 1), (5, 1), (5, 2), (5, 256), (5, ALL_LANGUAGES.bits()), (7, 1)]
+        {
+            let entries = vec![GridEntry {
+                id: *phrase_id,
+                x: 1,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder
+                .insert(
+                    &GridKey { phrase_id: *phrase_id, lang_set: LangSet::from(*lang_set) },
+                    entries,
+                )
+                .unwrap();
+        }
+        builder.load_bin_boundaries(vec![0, 6, 8]).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let keys: Vec<_> = reader.iter_rev().map(|item| item.unwrap().0).collect();
+        // language suffixes order by their stripped bytes: [] < [1] < [1, 0] < [2]
+        let expected: Vec<_> =
+            [(7, 1), (5, 2), (5, 256), (5, 1), (5, ALL_LANGUAGES.bits()), (1, 1)]
+                .iter()
+                .map(|(phrase_id, lang_set)| GridKey {
+                    phrase_id: *phrase_id,
+                    lang_set: LangSet::from(*lang_set),
+                })
+                .collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn single_grid_bbox_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = GridEntry { id: 1, x: 5, y: 9, relev: 1., score: 3, source_phrase_hash: 0 };
+        builder
+            .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, vec![grid.clone()])
+            .unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let match_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let matching = |bbox, proximity| -> Vec<GridEntry> {
+            let match_opts =
+                MatchOpts { bbox: Some(bbox), proximity, zoom: 6, ..MatchOpts::default() };
+            reader
+                .streaming_get_matching(&match_key, &match_opts, MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| entry.grid_entry)
+                .collect()
+        };
+        let near = |point| Some(Proximity { point, radius: 40. });
+
+        assert_eq!(matching([5, 9, 5, 9], None), vec![grid.clone()], "bbox is exactly the grid");
+        assert_eq!(matching([0, 0, 63, 63], None), vec![grid.clone()], "bbox is the whole zoom");
+        assert_eq!(matching([5, 0, 5, 9], None), vec![grid.clone()], "grid is on the bbox's edge");
+        assert_eq!(matching([5, 9, 5, 9], near([0, 0])), vec![grid.clone()]);
+        assert_eq!(matching([5, 9, 5, 9], near([5, 9])), vec![grid.clone()]);
+        assert_eq!(matching([6, 9, 7, 9], None), vec![], "bbox just misses the grid");
+        assert_eq!(matching([0, 0, 4, 63], near([5, 9])), vec![]);
+    }
+
+    #[test]
+    fn bbox_soft_margin_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id, x, y| GridEntry { id, x, y, relev: 1., score: 3, source_phrase_hash: 0 };
+        // one grid inside the bbox, one a tile past its edge and one well outside it
+        builder
+            .insert(
+                &GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+                vec![grid(1, 12, 12), grid(2, 21, 15), grid(3, 40, 40)],
+            )
+            .unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let match_key = MatchKey {
+            match_phrase: MatchPhrase::Exact(1),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        };
+        let matching = |bbox_soft_margin| -> Vec<(u32, bool)> {
+            let match_opts = MatchOpts {
+                bbox: Some([10, 10, 20, 20]),
+                proximity: Some(Proximity { point: [21, 15], radius: 40. }),
+                zoom: 6,
+                bbox_soft_margin,
+                ..MatchOpts::default()
+            };
+            reader
+                .streaming_get_matching(&match_key, &match_opts, MAX_CONTEXTS)
+                .unwrap()
+                .map(|entry| (entry.grid_entry.id, entry.within_bbox))
+                .collect()
+        };
+
+        assert_eq!(matching(None), [(1, true)], "without a margin only the grid inside matches");
+        assert_eq!(
+            matching(Some(1)),
+            [(2, false), (1, true)],
+            "the grid next to the proximity point is let in, but flagged as outside the bbox"
+        );
+        assert_eq!(matching(Some(0)), matching(None));
+    }
+
+    #[test]
+    fn closest_feature_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let builder = GridStoreBuilder::new(directory.path()).unwrap();
+        builder.finish().unwrap();
+        let reader = GridStore::new(directory.path()).unwrap();
+        assert_eq!(
+            reader.closest_feature([5, 5], LangSet::from(1)).unwrap(),
+            None,
+            "empty store has no match"
+        );
+
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id, x, y| GridEntry { id, x, y, relev: 1., score: 1, source_phrase_hash: 0 };
+        builder
+            .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, vec![grid(1, 0, 0)])
+            .unwrap();
+        builder
+            .insert(
+                &GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+                vec![grid(2, 10, 10), grid(3, 6, 7)],
+            )
+            .unwrap();
+        // closest of all, but in another language
+        builder
+            .insert(&GridKey { phrase_id: 3, lang_set: LangSet::from(2) }, vec![grid(4, 5, 5)])
+            .unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let closest = reader.closest_feature([5, 5], LangSet::from(1)).unwrap().unwrap();
+        assert_eq!(closest.grid_entry.id, 3);
+        assert_eq!(closest.distance, spatial::tile_dist(5, 5, 6, 7));
+
+        let closest = reader.closest_feature([5, 5], LangSet::from(1 | 2)).unwrap().unwrap();
+        assert_eq!(closest.grid_entry.id, 4);
+        assert_eq!(closest.distance, 0.);
+
+        assert_eq!(
+            reader.closest_feature([5, 5], LangSet::from(4)).unwrap(),
+            None,
+            "no grids in language 2"
+        );
+    }
+
+    #[test]
+    fn memory_usage_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
+        let entries =
+            vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }];
+        builder.insert(&key, entries).expect("Unable to insert record");
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        assert_eq!(reader.get(&key).unwrap().unwrap().count(), 1);
+        // the numbers depend on the rocksdb build, so just check that they can be read
+        reader.memory_usage().expect("Failed to read memory usage");
+    }
+
+    #[test]
+    fn bbox_coverage_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let builder = GridStoreBuilder::new(directory.path()).unwrap();
+        builder.finish().unwrap();
+        let reader = GridStore::new(directory.path()).unwrap();
+        assert_eq!(reader.bbox_coverage().unwrap(), None, "an empty store covers nothing");
+
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id, x, y| GridEntry { id, x, y, relev: 1., score: 1, source_phrase_hash: 0 };
+        builder
+            .insert(
+                &GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+                vec![grid(1, 3, 40), grid(2, 20, 20)],
+            )
+            .unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 2, lang_set: LangSet::from(2) }, vec![grid(3, 55, 7)])
+            .unwrap();
+        builder.insert(&GridKey::all_languages(3), vec![grid(4, 10, 12)]).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        assert_eq!(reader.bbox_coverage().unwrap(), Some([3, 7, 55, 40]));
+    }
+
+    #[test]
+    fn compact_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid = |id| GridEntry { id, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 };
+        for phrase_id in 0..10 {
+            let key = GridKey { phrase_id, lang_set: LangSet::from(1) };
+            builder.insert(&key, vec![grid(phrase_id), grid(phrase_id + 100)]).unwrap();
+        }
+        builder.load_bin_boundaries(vec![0, 5, 10]).unwrap();
+        builder.finish().unwrap();
+
+        let open_opts = GridStoreOpenOptions { max_open_files: Some(16), ..Default::default() };
+        let reader = GridStore::open(directory.path(), open_opts).unwrap();
+        let before: Vec<_> = reader.iter().map(|item| item.unwrap()).collect();
+        let bin_boundaries = reader.bin_boundaries();
+        let reader = reader.compact().unwrap();
+
+        assert_eq!(reader.iter().map(|item| item.unwrap()).collect::<Vec<_>>(), before);
+        assert_eq!(reader.bin_boundaries(), bin_boundaries);
+        let reopened = GridStore::new(directory.path()).unwrap();
+        assert_eq!(reopened.iter().map(|item| item.unwrap()).collect::<Vec<_>>(), before);
+
+        let packed_directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let packed_file = packed_directory.path().join("store.pack");
+        reader.pack(&packed_file).unwrap();
+        let packed = GridStore::open_packed(&packed_file).unwrap().compact().unwrap();
+        assert_eq!(packed.iter().map(|item| item.unwrap()).collect::<Vec<_>>(), before);
+    }
+
+    #[test]
+    fn histogram_scores_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let grid =
+            |id, relev, score| GridEntry { id, x: 1, y: 1, relev, score, source_phrase_hash: 0 };
+        builder
+            .insert(
+                &GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+                vec![grid(1, 1., 0), grid(2, 1., 0), grid(3, 0.6, 0), grid(4, 1., 7)],
+            )
+            .unwrap();
+        builder
+            .insert(
+                &GridKey { phrase_id: 2, lang_set: LangSet::from(2) },
+                vec![grid(5, 0.8, 7), grid(6, 0.4, 15)],
+            )
+            .unwrap();
+        builder.insert(&GridKey::all_languages(3), vec![grid(7, 1., 3)]).unwrap();
+        // the prefix bins hold copies of the phrases' grids, which mustn't be counted again
+        builder.load_bin_boundaries(vec![0, 2, 4]).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let mut expected = [0u64; 16];
+        expected[0] = 3;
+        expected[3] = 1;
+        expected[7] = 2;
+        expected[15] = 1;
+        assert_eq!(reader.histogram_scores().unwrap(), expected);
+
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        GridStoreBuilder::new(directory.path()).unwrap().finish().unwrap();
+        let reader = GridStore::new(directory.path()).unwrap();
+        assert_eq!(reader.histogram_scores().unwrap(), [0u64; 16], "an empty store has no grids");
+    }
+
+    #[test]
+    fn estimate_keys_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        for phrase_id in 0..50 {
+            let entries = vec![GridEntry {
+                id: phrase_id,
+                x: 1,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&GridKey { phrase_id, lang_set: LangSet::from(1) }, entries).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let exact = reader.keys().count() as u64;
+        assert_eq!(exact, 50);
+        let estimate = reader.estimate_keys().unwrap();
+        assert!(
+            estimate >= exact / 10 && estimate <= exact * 10,
+            "estimate {} is within an order of magnitude of {}",
+            estimate,
+            exact
+        );
+    }
+
+    #[test]
+    fn pack_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        for phrase_id in 0..20 {
+            let entries: Vec<GridEntry> = (0..5)
+                .map(|i| GridEntry {
+                    id: phrase_id * 10 + i,
+                    x: (phrase_id + i * 3) as u16,
+                    y: (i * 7) as u16,
+                    relev: 1. - (i as f64) * 0.2,
+                    score: (i % 8) as u8,
+                    source_phrase_hash: i as u8,
+                })
+                .collect();
+            builder
+                .insert(&GridKey { phrase_id, lang_set: LangSet::from(1) }, entries.clone())
+                .unwrap();
+            if phrase_id % 3 == 0 {
+                builder.insert(&GridKey::all_languages(phrase_id), entries[..2].to_vec()).unwrap();
+            }
+        }
+        builder.load_bin_boundaries(vec![0, 8, 20]).unwrap();
+        builder.finish().unwrap();
+        let reader = GridStore::new(directory.path()).unwrap();
+
+        let packed_directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let packed_file = packed_directory.path().join("store.pack");
+        reader.pack(&packed_file).unwrap();
+        let packed = GridStore::open_packed(&packed_file).unwrap();
+
+        assert_eq!(packed.bin_boundaries(), vec![0, 8, 20]);
+        assert_eq!(GridStore::diff(&reader, &packed).unwrap(), vec![]);
+        let records = |store: &GridStore| -> Vec<_> { store.iter().map(|r| r.unwrap()).collect() };
+        assert_eq!(records(&packed), records(&reader));
+        let records_rev =
+            |store: &GridStore| -> Vec<_> { store.iter_rev().map(|r| r.unwrap()).collect() };
+        assert_eq!(records_rev(&packed), records_rev(&reader));
+        let keys = |store: &GridStore| -> Vec<_> {
+            store.keys_in_range(4, 13).map(|key| key.unwrap()).collect()
+        };
+        assert_eq!(keys(&packed), keys(&reader));
+        assert_eq!(packed.estimate_keys().unwrap(), reader.estimate_keys().unwrap());
+
+        let key = GridKey { phrase_id: 6, lang_set: LangSet::from(1) };
+        let get = |store: &GridStore| -> Vec<_> { store.get(&key).unwrap().unwrap().collect() };
+        assert_eq!(get(&packed), get(&reader));
+        assert!(packed
+            .get(&GridKey { phrase_id: 25, lang_set: LangSet::from(1) })
+            .unwrap()
+            .is_none());
+
+        let match_opts = MatchOpts {
+            zoom: 14,
+            proximity: Some(Proximity { point: [10, 10], radius: 40. }),
+            ..MatchOpts::default()
+        };
+        for match_phrase in vec![
+            MatchPhrase::Exact(3),
+            MatchPhrase::Range { start: 0, end: 8 },
+            MatchPhrase::Range { start: 5, end: 17 },
+            MatchPhrase::Set(vec![2, 9, 12]),
+        ] {
+            let match_key = MatchKey {
+                match_phrase,
+                lang_set: LangSet::from(1),
+                lang_fallbacks: vec![],
+                ignore_language: false,
+            };
+            let matches = |store: &GridStore| -> Vec<_> {
+                store.streaming_get_matching(&match_key, &match_opts, 100).unwrap().collect()
+            };
+            let expected = matches(&reader);
+            assert!(!expected.is_empty());
+            assert_eq!(matches(&packed), expected, "{:?} matches the same", match_key);
+        }
+
+        assert_eq!(packed.verify().unwrap(), vec![]);
+        drop(packed);
+
+        // opening doesn't read the records, so a damaged one is only caught by verify
+        let mut bytes = std::fs::read(&packed_file).unwrap();
+        // the first record starts right after the 16 byte header, with its key length
+        bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&packed_file, &bytes).unwrap();
+        let damaged = GridStore::open_packed(&packed_file).unwrap();
+        match damaged.verify() {
+            Err(GridStoreError::Decode { what, .. }) => assert_eq!(what, "packed store"),
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+        drop(damaged);
+
+        std::fs::write(&packed_file, b"not a packed store").unwrap();
+        match GridStore::open_packed(&packed_file) {
+            Err(GridStoreError::Decode { what, .. }) => assert_eq!(what, "packed store"),
+            other => panic!("expected a decode error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn key_ordering_test() {
+        let build = |key_ordering: KeyOrdering| -> tempfile::TempDir {
+            let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+            let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+            builder.set_key_ordering(key_ordering);
+            for phrase_id in 0..20 {
+                let entries: Vec<GridEntry> = (0..3)
+                    .map(|i| GridEntry {
+                        id: phrase_id * 10 + i,
+                        x: (phrase_id + i * 3) as u16,
+                        y: (i * 7) as u16,
+                        relev: 1. - (i as f64) * 0.2,
+                        score: (i % 8) as u8,
+                        source_phrase_hash: i as u8,
+                    })
+                    .collect();
+                let lang_set = LangSet::from(1 << (phrase_id % 3));
+                builder.insert(&GridKey { phrase_id, lang_set }, entries.clone()).unwrap();
+                if phrase_id % 4 == 0 {
+                    builder
+                        .insert(&GridKey::all_languages(phrase_id), entries[..1].to_vec())
+                        .unwrap();
+                }
+            }
+            builder.load_bin_boundaries(vec![0, 8, 20]).unwrap();
+            builder.finish().unwrap();
+            directory
+        };
+        let phrase_first_directory = build(KeyOrdering::PhraseFirst);
+        let language_first_directory = build(KeyOrdering::LanguageFirst);
+        let phrase_first = GridStore::new(phrase_first_directory.path()).unwrap();
+        let language_first = GridStore::open(
+            language_first_directory.path(),
+            GridStoreOpenOptions { key_ordering: KeyOrdering::LanguageFirst, ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(language_first.key_ordering(), KeyOrdering::LanguageFirst);
+
+        let keys: Vec<GridKey> = language_first.keys().map(|key| key.unwrap()).collect();
+        let mut expected: Vec<GridKey> = phrase_first.keys().map(|key| key.unwrap()).collect();
+        assert_ne!(keys, expected);
+        expected.sort_by_key(|key| (key.lang_set, key.phrase_id));
+        assert_eq!(keys, expected, "keys come out by language set, then phrase");
+
+        assert_eq!(language_first.bin_boundaries(), vec![0, 8, 20]);
+        let keys_in_range = |store: &GridStore| -> BTreeSet<_> {
+            store.keys_in_range(4, 13).map(|key| key.unwrap()).collect()
+        };
+        assert_eq!(keys_in_range(&language_first), keys_in_range(&phrase_first));
+        for phrase_id in 0..21 {
+            assert_eq!(
+                language_first.phrase_languages(phrase_id).unwrap(),
+                phrase_first.phrase_languages(phrase_id).unwrap()
+            );
+        }
+
+        let match_opts = MatchOpts {
+            zoom: 14,
+            proximity: Some(Proximity { point: [10, 10], radius: 40. }),
+            ..MatchOpts::default()
+        };
+        for match_phrase in vec![
+            MatchPhrase::Exact(3),
+            MatchPhrase::Exact(20),
+            MatchPhrase::Range { start: 0, end: 8 },
+            MatchPhrase::Range { start: 5, end: 17 },
+            MatchPhrase::Set(vec![2, 9, 12]),
+        ] {
+            for lang_set in vec![LangSet::from(1), LangSet::from(4), ALL_LANGUAGES] {
+                let match_key = MatchKey {
+                    match_phrase: match_phrase.clone(),
+                    lang_set,
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
+                };
+                let matches = |store: &GridStore| -> Vec<_> {
+                    store.streaming_get_matching(&match_key, &match_opts, 100).unwrap().collect()
+                };
+                assert_eq!(
+                    matches(&language_first),
+                    matches(&phrase_first),
+                    "{:?} matches the same",
+                    match_key
+                );
+                assert_eq!(
+                    language_first.has_match(&match_key).unwrap(),
+                    phrase_first.has_match(&match_key).unwrap()
+                );
+            }
+        }
+
+        assert_eq!(language_first.verify_parallel(3).unwrap(), vec![]);
+        match GridStore::diff(&language_first, &phrase_first) {
+            Err(GridStoreError::Other(_)) => (),
+            other => panic!("expected a key ordering error, got {:?}", other),
+        }
+        let packed_file = language_first_directory.path().join("store.pack");
+        assert!(language_first.pack(&packed_file).is_err(), "only phrase-first stores pack");
+    }
+
+    #[test]
+    fn sample_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        for phrase_id in 0..10 {
+            let entries: Vec<_> = (0..10)
+                .map(|i| GridEntry {
+                    id: phrase_id * 10 + i,
+                    x: i as u16,
+                    y: phrase_id as u16,
+                    relev: 1.,
+                    score: 1,
+                    source_phrase_hash: 0,
+                })
+                .collect();
+            builder.insert(&GridKey { phrase_id, lang_set: LangSet::from(1) }, entries).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+
+        let sample = reader.sample(5, 42).unwrap();
+        assert_eq!(sample.len(), 5);
+        assert_eq!(sample, reader.sample(5, 42).unwrap(), "the same seed gives the same sample");
+        assert_ne!(sample, reader.sample(5, 7).unwrap(), "a different seed gives another sample");
+        for (key, entry) in sample.iter() {
+            assert_eq!(entry.id / 10, key.phrase_id, "sampled entries keep their own key");
+        }
+
+        let all: Vec<_> = reader
+            .iter()
+            .flat_map(|item| {
+                let (key, entries) = item.unwrap();
+                entries.into_iter().map(move |entry| (key.clone(), entry))
+            })
+            .collect();
+        assert_eq!(reader.sample(1000, 42).unwrap(), all, "n larger than the store returns all");
+        assert_eq!(reader.sample(std::usize::MAX, 42).unwrap(), all, "huge n doesn't preallocate");
+        assert_eq!(reader.sample(0, 42).unwrap(), []);
+    }
+
+    #[test]
+    fn iter_matching_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        let entries_for = |id: u32, x: u16| {
+            vec![GridEntry { id, x, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }]
+        };
+        builder
+            .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, entries_for(1, 10))
+            .unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(2) }, entries_for(2, 11))
+            .unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 2, lang_set: LangSet::from(1) }, entries_for(3, 12))
+            .unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 3, lang_set: LangSet::from(1) }, entries_for(4, 50))
+            .unwrap();
+        builder.insert(&GridKey::all_languages(4), entries_for(5, 13)).unwrap();
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let match_opts = MatchOpts { bbox: Some([0, 0, 20, 2]), ..MatchOpts::default() };
+
+        let matched: Vec<_> = reader
+            .iter_matching(&match_opts, LangSet::from(1))
+            .map(|item| {
+                let (key, entry) = item.unwrap();
+                (key.phrase_id, entry.grid_entry.id)
+            })
+            .collect();
+        // phrase 1 in language 2 doesn't match the language, and phrase 3 is outside the bbox;
+        // the all-languages key matches any language
+        assert_eq!(matched, [(1, 1), (2, 3), (4, 5)]);
+
+        let matched: Vec<_> = reader
+            .iter_matching(&match_opts, LangSet::from(2))
+            .map(|item| item.unwrap().1.grid_entry.id)
+            .collect();
+        assert_eq!(matched, [2, 5]);
+
+        let matched: Vec<_> = reader
+            .iter_matching(&MatchOpts::default(), LangSet::from(1))
+            .map(|item| item.unwrap().1.grid_entry.id)
+            .collect();
+        assert_eq!(matched, [1, 3, 4, 5], "without a bbox every language match is returned");
+    }
+
+    #[test]
+    fn verify_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+
+        let entries = vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 0 },
+        ];
+        builder
+            .insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, entries.clone())
+            .unwrap();
+        builder
+            .insert(&GridKey { phrase_id: 2, lang_set: LangSet::from(1) }, entries.clone())
+            .unwrap();
+        builder.load_bin_boundaries(vec![0, 3]).unwrap();
+        builder.finish().unwrap();
+
+        {
+            let reader = GridStore::new(directory.path()).unwrap();
+            assert_eq!(reader.verify().unwrap(), [], "a freshly built store has no issues");
+            assert_eq!(reader.verify_parallel(4).unwrap(), []);
+        }
+
+        // write a malformed record and bin boundaries by hand
+        {
+            let mut writer = gridstore_format::Writer::new();
+            let ids_a = writer.write_fixed_vec(&[1u32 << 8, 2 << 8]);
+            let ids_b = writer.write_fixed_vec(&[3u32 << 8]);
+            let coords = writer.write_uniform_vec(&[
+                gridstore_format::Coord { coord: 1, ids: ids_a },
+                gridstore_format::Coord { coord: 5, ids: ids_b },
+            ]);
+            let relev_scores = writer.write_var_vec(&[
+                gridstore_format::RelevScore { relev_score: 0x49, coords },
+                gridstore_format::RelevScore { relev_score: 0x49, coords },
+            ]);
+            writer.write_fixed_scalar(gridstore_format::PhraseRecord { relev_scores });
+
+            let mut db_key = Vec::new();
+            let key = GridKey { phrase_id: 3, lang_set: LangSet::from(1) };
+            key.write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+
+            let mut bounds: Vec<u8> = Vec::new();
+            for boundary in [0u32, 3, 10].iter() {
+                bounds.extend_from_slice(&boundary.to_le_bytes());
+            }
+
+            let db = rocksdb::DB::open_default(directory.path()).unwrap();
+            db.put(&db_key, writer.finish()).unwrap();
+            db.put("~BOUNDS", bounds).unwrap();
+        }
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let key = GridKey { phrase_id: 3, lang_set: LangSet::from(1) };
+        let issues = reader.verify().unwrap();
+        assert_eq!(
+            issues,
+            [
+                VerifyIssue::RelevOutOfRange { key: key.clone(), relev_score: 0x49 },
+                VerifyIssue::ScoreOutOfRange { key: key.clone(), relev_score: 0x49 },
+                VerifyIssue::IdsNotDescending { key: key.clone(), relev_score: 0x49, coord: 1 },
+                VerifyIssue::CoordsNotDescending { key: key.clone(), relev_score: 0x49 },
+                VerifyIssue::RelevScoresNotDescending { key: key.clone() },
+                VerifyIssue::RelevOutOfRange { key: key.clone(), relev_score: 0x49 },
+                VerifyIssue::ScoreOutOfRange { key: key.clone(), relev_score: 0x49 },
+                VerifyIssue::IdsNotDescending { key: key.clone(), relev_score: 0x49, coord: 1 },
+                VerifyIssue::CoordsNotDescending { key: key.clone(), relev_score: 0x49 },
+                VerifyIssue::BinBoundaryOutOfRange { boundary: 10, max_phrase_id: Some(3) },
+            ]
+        );
+        for threads in 1..5 {
+            assert_eq!(
+                reader.verify_parallel(threads).unwrap(),
+                issues,
+                "verify_parallel with {} threads agrees with verify",
+                threads
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_key_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let entries =
+            vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }];
+        builder.insert(&GridKey { phrase_id: 1, lang_set: LangSet::from(1) }, entries).unwrap();
+        builder.finish().unwrap();
+
+        // a truncated key that sorts after phrase 1's
+        {
+            let db = rocksdb::DB::open_default(directory.path()).unwrap();
+            db.put(&[0u8, 0, 2], b"").unwrap();
+        }
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        let keys: Vec<_> = reader.keys().collect();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(
+            keys[0].as_ref().unwrap(),
+            &GridKey { phrase_id: 1, lang_set: LangSet::from(1) }
+        );
+        let err = keys[1].as_ref().unwrap_err();
+        assert!(err.to_string().contains("[0, 0, 2]"), "error names the bad key: {}", err);
+
+        let collected: Result<Vec<_>, _> = reader.keys().collect();
+        match collected {
+            Err(GridStoreError::InvalidKey { key, .. }) => assert_eq!(key, [0, 0, 2]),
+            other => panic!("expected an invalid key error, got {:?}", other),
+        }
+    }
+
+    static PREFIX_DATA: Lazy<(
+        GridStore,
+        GridStore,
+        Vec<String>,
+        tempfile::TempDir,
+        tempfile::TempDir,
+    )> = Lazy::new(|| {
+        let directory_with_boundaries: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let directory_without_boundaries: tempfile::TempDir = tempfile::tempdir().unwrap();
+
+        let mut builder_with_boundaries =
+            GridStoreBuilder::new(directory_with_boundaries.path()).unwrap();
+        let mut builder_without_boundaries =
+            GridStoreBuilder::new(directory_without_boundaries.path()).unwrap();
+
+        // this will produce 5000 phrases aaa, aab, aac, ...
+        let alphabet = "abcdefghijklmnopqrstuvwxyz";
+        let phrases: Vec<String> = alphabet
+            .bytes()
+            .flat_map(move |l1| {
+                alphabet.bytes().flat_map(move |l2| {
+                    alphabet.bytes().map(move |l3| String::from_utf8(vec![l1, l2, l3]).unwrap())
+                })
+            })
+            .take(5000)
+            .collect();
+
+        // insert phrases
+        for i in 0..=(phrases.len() as u32) {
+            let key = GridKey { phrase_id: i, lang_set: LangSet::from(1) };
+            let entries = vec![GridEntry {
+                id: i,
+                x: i as u16,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder_with_boundaries.insert(&key, entries.clone()).expect("Unable to insert record");
+            builder_without_boundaries
+                .insert(&key, entries.clone())
+                .expect("Unable to insert record");
+        }
+
+        // calculate bins
+        let mut bins: BTreeMap<u8, u32> = BTreeMap::new();
+        for (i, phrase) in phrases.iter().enumerate() {
+            // insert the first occurrence of every prefix
+            bins.entry(phrase.bytes().next().unwrap()).or_insert(i as u32);
+        }
+        let mut boundaries: Vec<_> = bins.values().cloned().collect();
+        boundaries.push(phrases.len() as u32);
+
+        builder_with_boundaries.load_bin_boundaries(boundaries).expect("Failed to load boundaries");
+
+        builder_with_boundaries.finish().unwrap();
+        builder_without_boundaries.finish().unwrap();
+
+        let reader_with_boundaries = GridStore::new(directory_with_boundaries.path()).unwrap();
+        let reader_without_boundaries =
+            GridStore::new(directory_without_boundaries.path()).unwrap();
+
+        (
+            reader_with_boundaries,
+            reader_without_boundaries,
+            phrases,
+            directory_with_boundaries,
+            directory_without_boundaries,
+        )
+    });
+
+    fn find_prefix_range(prefix: &str) -> (u32, u32) {
+        let phrases = &PREFIX_DATA.2;
+
+        let start =
+            phrases.iter().enumerate().find(|(_, phrase)| phrase.starts_with(prefix)).unwrap().0;
+        let end = phrases
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, phrase)| phrase.starts_with(prefix))
+            .unwrap()
+            .0
+            + 1;
+        (start as u32, end as u32)
+    }
+
+    #[test]
+    fn prefix_make_bins() {
+        Lazy::force(&PREFIX_DATA);
+    }
+
+    #[test]
+    fn prefix_range_test() {
+        let (reader_with_boundaries, reader_without_boundaries) = (&PREFIX_DATA.0, &PREFIX_DATA.1);
+
+        // 'a' is the first prefix the bins were built from, so 'b' gets the second bin
+        assert_eq!(reader_with_boundaries.prefix_range(0), Some(find_prefix_range("a")));
+        assert_eq!(reader_with_boundaries.prefix_range(1), Some(find_prefix_range("b")));
+        // 5000 phrases only get as far as 'h', and the last boundary closes that bin
+        assert_eq!(reader_with_boundaries.prefix_range(7), Some(find_prefix_range("h")));
+        assert_eq!(reader_with_boundaries.prefix_range(8), None);
+        assert_eq!(reader_without_boundaries.prefix_range(0), None);
+    }
+
+    #[test]
+    fn prefix_test_with_bins() {
+        let (reader_with_boundaries, reader_without_boundaries) = (&PREFIX_DATA.0, &PREFIX_DATA.1);
+        let starts_with_b = find_prefix_range("b");
 
         // query that we expect to use the pre-cached ranges
         let search_key = MatchKey {
             match_phrase: MatchPhrase::Range { start: starts_with_b.0, end: starts_with_b.1 },
-            lang_set: 1,
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
         };
         let mut records_with_boundaries: Vec<_> = reader_with_boundaries
             .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
@@ -578,8 +2771,11 @@ mod tests {
                 matches_language: true,
                 distance: 0.0,
                 scoredist: 1.0,
+                radius: 0.0,
+                within_bbox: true,
             })
         }
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
         assert_eq!(records_with_boundaries, expected);
         assert_eq!(records_without_boundaries, expected);
@@ -593,7 +2789,9 @@ mod tests {
         // query that we expect not to use the precached ranges
         let search_key = MatchKey {
             match_phrase: MatchPhrase::Range { start: starts_with_bc.0, end: starts_with_bc.1 },
-            lang_set: 1,
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
         };
         let mut records_with_boundaries: Vec<_> = reader_with_boundaries
             .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
@@ -621,8 +2819,12 @@ mod tests {
                 matches_language: true,
                 distance: 0.0,
                 scoredist: 1.0,
+                radius: 0.0,
+                within_bbox: true,
             })
         }
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
         assert_eq!(records_with_boundaries, expected);
         assert_eq!(records_without_boundaries, expected);
     }
@@ -647,11 +2849,14 @@ mod tests {
                 weight: 1.,
                 match_key: MatchKey {
                     match_phrase: MatchPhrase::Range { start: range.0, end: range.1 },
-                    lang_set: 1,
+                    lang_set: LangSet::from(1),
+                    lang_fallbacks: vec![],
+                    ignore_language: false,
                 },
                 idx: 1,
                 zoom: 14,
                 mask: 1 << 0,
+                radius_override: None,
             };
             let stack = vec![subquery];
             let match_opts = MatchOpts {
@@ -0,0 +1,219 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::gridstore::common::GridStoreError;
+
+/// Identifies a packed store file; the trailing digits are the format version
+const PACKED_MAGIC: &[u8; 8] = b"GSPACK01";
+/// Magic, then the record count as a little-endian u64
+const HEADER_LEN: usize = 16;
+/// The offset of the record index, as a little-endian u64
+const TRAILER_LEN: usize = 8;
+
+/// Writes `records`, which must already be in key order, to `out_file` as a packed store.
+///
+/// The file is laid out as:
+/// * a header: the magic bytes and the number of records
+/// * the records, each a u32 key length, the key, a u32 value length and the value
+/// * an index holding the u64 offset of each record from the start of the file
+/// * a trailer holding the u64 offset of the index
+///
+/// All integers are little-endian. The index lets a reader binary search for keys without
+/// walking the records.
+pub(crate) fn write_packed<I, K, V>(out_file: &Path, records: I) -> Result<(), GridStoreError>
+where
+    I: Iterator<Item = (K, V)>,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    let mut writer = BufWriter::new(File::create(out_file)?);
+    let mut offsets: Vec<u64> = Vec::new();
+    let mut position = HEADER_LEN as u64;
+
+    writer.write_all(PACKED_MAGIC)?;
+    // the count isn't known until the records have been written, so leave room for it
+    writer.write_all(&0u64.to_le_bytes())?;
+    for (key, value) in records {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        offsets.push(position);
+        writer.write_all(&(key.len() as u32).to_le_bytes())?;
+        writer.write_all(key)?;
+        writer.write_all(&(value.len() as u32).to_le_bytes())?;
+        writer.write_all(value)?;
+        position += (8 + key.len() + value.len()) as u64;
+    }
+    for offset in offsets.iter() {
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+    writer.write_all(&position.to_le_bytes())?;
+
+    let mut file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.seek(SeekFrom::Start(PACKED_MAGIC.len() as u64))?;
+    file.write_all(&(offsets.len() as u64).to_le_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// A read-only, memory-mapped view of a file written by `write_packed`. Opening only checks the
+/// header, trailer and index, so it doesn't touch the records; `verify` checks those. Until then
+/// a record that runs out of bounds reads as an empty key and value rather than panicking.
+#[derive(Debug)]
+pub(crate) struct PackedStore {
+    map: Mmap,
+    count: usize,
+    index_offset: usize,
+}
+
+fn malformed(reason: String) -> GridStoreError {
+    GridStoreError::Decode { what: "packed store", reason }
+}
+
+impl PackedStore {
+    pub(crate) fn open(path: &Path) -> Result<PackedStore, GridStoreError> {
+        let file = File::open(path)?;
+        // the file is only ever read, and packed stores aren't meant to be modified in place
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < HEADER_LEN + TRAILER_LEN || &map[..PACKED_MAGIC.len()] != PACKED_MAGIC {
+            return Err(malformed("missing packed store header".to_owned()));
+        }
+        let count = read_u64(&map, PACKED_MAGIC.len()) as usize;
+        let index_offset = read_u64(&map, map.len() - TRAILER_LEN) as usize;
+        if index_offset.checked_add(count.saturating_mul(8)) != Some(map.len() - TRAILER_LEN) {
+            return Err(malformed(format!(
+                "index of {} records at offset {} doesn't fit a {} byte file",
+                count,
+                index_offset,
+                map.len()
+            )));
+        }
+
+        Ok(PackedStore { map, count, index_offset })
+    }
+
+    /// Checks every record in the file, which reads the whole of it
+    pub(crate) fn verify(&self) -> Result<(), GridStoreError> {
+        for i in 0..self.count {
+            self.check_record(i)?;
+        }
+        Ok(())
+    }
+
+    /// Number of records in the file
+    pub(crate) fn len(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let i = self.lower_bound(key);
+        if i < self.count && self.key(i) == key {
+            Some(self.value(i))
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over the records from the first one whose key is at least `key`, in key order
+    pub(crate) fn iter_from<'a>(
+        &'a self,
+        key: &[u8],
+    ) -> impl Iterator<Item = (&'a [u8], &'a [u8])> {
+        (self.lower_bound(key)..self.count).map(move |i| (self.key(i), self.value(i)))
+    }
+
+    /// Iterates over the records in reverse key order, from the last one whose key is at most
+    /// `key`, or from the last record if `key` is `None`
+    pub(crate) fn iter_from_rev<'a>(
+        &'a self,
+        key: Option<&[u8]>,
+    ) -> impl Iterator<Item = (&'a [u8], &'a [u8])> {
+        let end = match key {
+            Some(key) => {
+                let end = self.lower_bound(key);
+                if end < self.count && self.key(end) == key {
+                    end + 1
+                } else {
+                    end
+                }
+            }
+            None => self.count,
+        };
+        (0..end).rev().map(move |i| (self.key(i), self.value(i)))
+    }
+
+    /// Index of the first record whose key isn't less than `key`
+    fn lower_bound(&self, key: &[u8]) -> usize {
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.key(mid).cmp(key) {
+                Ordering::Less => low = mid + 1,
+                _ => high = mid,
+            }
+        }
+        low
+    }
+
+    fn record_offset(&self, i: usize) -> usize {
+        read_u64(&self.map, self.index_offset + i * 8) as usize
+    }
+
+    fn key(&self, i: usize) -> &[u8] {
+        let offset = self.record_offset(i);
+        self.length_prefixed(offset).unwrap_or(&[])
+    }
+
+    fn value(&self, i: usize) -> &[u8] {
+        let offset = self.record_offset(i);
+        let value_offset = self.length_prefixed(offset).map(|key| offset + 4 + key.len());
+        value_offset.and_then(|value_offset| self.length_prefixed(value_offset)).unwrap_or(&[])
+    }
+
+    /// The u32 length-prefixed bytes at `offset`, if they lie within the record section
+    fn length_prefixed(&self, offset: usize) -> Option<&[u8]> {
+        let start = offset.checked_add(4)?;
+        if start > self.index_offset {
+            return None;
+        }
+        let end = start.checked_add(read_u32(&self.map, offset) as usize)?;
+        self.map[..self.index_offset].get(start..end)
+    }
+
+    /// Checks that the `i`th record lies within the record section and sorts after the one
+    /// before it
+    fn check_record(&self, i: usize) -> Result<(), GridStoreError> {
+        let offset = self.record_offset(i);
+        let fits = |start: usize, len: usize| {
+            start.checked_add(len).map_or(false, |end| end <= self.index_offset)
+        };
+        if offset < HEADER_LEN || !fits(offset, 4) {
+            return Err(malformed(format!("record {} starts out of bounds", i)));
+        }
+        let key_len = read_u32(&self.map, offset) as usize;
+        let value_offset = offset + 4 + key_len;
+        if !fits(offset + 4, key_len) || !fits(value_offset, 4) {
+            return Err(malformed(format!("record {} has an out of bounds key", i)));
+        }
+        let value_len = read_u32(&self.map, value_offset) as usize;
+        if !fits(value_offset + 4, value_len) {
+            return Err(malformed(format!("record {} has an out of bounds value", i)));
+        }
+        if i > 0 && self.key(i - 1) >= self.key(i) {
+            return Err(malformed(format!("record {} is out of key order", i)));
+        }
+        Ok(())
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
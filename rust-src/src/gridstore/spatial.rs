@@ -1,12 +1,81 @@
+use crate::gridstore::common::{LngLatError, MatchOpts};
 use crate::gridstore::gridstore_format::{Coord, UniformVec};
+use failure::Error;
 use itertools::Itertools;
 use morton::{deinterleave_morton, interleave_morton};
+use serde::{Deserialize, Serialize};
 
 #[cfg(test)]
 use crate::gridstore::common::relev_float_to_int;
 #[cfg(test)]
 use crate::gridstore::gridstore_format;
 
+/// Whether a bounding box covers every tile at the given zoom level
+pub fn bbox_covers_zoom(bbox: [u16; 4], zoom: u16) -> bool {
+    let max_coord = ((1u32 << zoom.min(16)) - 1) as u16;
+    bbox[0] == 0 && bbox[1] == 0 && bbox[2] >= max_coord && bbox[3] >= max_coord
+}
+
+#[test]
+fn bbox_covers_zoom_test() {
+    assert!(bbox_covers_zoom([0, 0, 3, 3], 2));
+    assert!(bbox_covers_zoom([0, 0, 65535, 65535], 16));
+    assert!(!bbox_covers_zoom([0, 0, 3, 2], 2));
+    assert!(!bbox_covers_zoom([1, 0, 3, 3], 2));
+    assert!(!bbox_covers_zoom([0, 0, 3, 3], 14));
+}
+
+/// Widens a bounding box by `margin` tiles on every side, without going past the edges of the
+/// given zoom level
+pub fn expand_bbox(bbox: [u16; 4], margin: u16, zoom: u16) -> [u16; 4] {
+    let max_coord = ((1u32 << zoom.min(16)) - 1) as u16;
+    [
+        bbox[0].saturating_sub(margin),
+        bbox[1].saturating_sub(margin),
+        bbox[2].saturating_add(margin).min(max_coord),
+        bbox[3].saturating_add(margin).min(max_coord),
+    ]
+}
+
+#[test]
+fn expand_bbox_test() {
+    assert_eq!(expand_bbox([5, 5, 10, 10], 2, 14), [3, 3, 12, 12]);
+    assert_eq!(expand_bbox([1, 0, 2, 3], 2, 2), [0, 0, 3, 3], "clamped to the zoom level");
+    assert_eq!(expand_bbox([0, 0, 65534, 65535], 5, 16), [0, 0, 65535, 65535]);
+    assert_eq!(expand_bbox([5, 5, 10, 10], 0, 14), [5, 5, 10, 10]);
+}
+
+/// Latitude beyond which web mercator tiles stop, north and south
+const MAX_MERCATOR_LAT: f64 = 85.0511287798066;
+
+/// Converts a longitude and latitude in degrees to the web mercator tile holding them at `zoom`.
+/// Points off the edge of the map, including latitudes past the poles' cutoff, are clamped to the
+/// nearest tile. Zooms past 16 don't fit tile coordinates in a `u16` and are rejected.
+pub fn lnglat_to_tile(lng: f64, lat: f64, zoom: u16) -> Result<[u16; 2], Error> {
+    if zoom > 16 {
+        return Err(LngLatError::ZoomOutOfRange { zoom }.into());
+    }
+    let tiles = f64::from(1u32 << zoom);
+    let lat = lat.max(-MAX_MERCATOR_LAT).min(MAX_MERCATOR_LAT).to_radians();
+    let x = (lng + 180.) / 360. * tiles;
+    let y = (1. - (lat.tan() + 1. / lat.cos()).ln() / std::f64::consts::PI) / 2. * tiles;
+    let clamp = |coord: f64| coord.floor().max(0.).min(tiles - 1.) as u16;
+    Ok([clamp(x), clamp(y)])
+}
+
+#[test]
+fn lnglat_to_tile_test() {
+    assert_eq!(lnglat_to_tile(0., 0., 1).unwrap(), [1, 1]);
+    assert_eq!(lnglat_to_tile(-122.52, 37.83, 14).unwrap(), [2615, 6329]);
+    assert_eq!(lnglat_to_tile(-180., 90., 2).unwrap(), [0, 0], "clamped to the top left tile");
+    assert_eq!(lnglat_to_tile(180., -90., 2).unwrap(), [3, 3], "clamped to the bottom right tile");
+    assert_eq!(lnglat_to_tile(180., -90., 16).unwrap(), [65535, 65535]);
+    match lnglat_to_tile(0., 0., 17).unwrap_err().downcast_ref::<LngLatError>() {
+        Some(LngLatError::ZoomOutOfRange { zoom }) => assert_eq!(*zoom, 17),
+        other => panic!("expected a zoom out of range error, got {:?}", other),
+    }
+}
+
 /// Generate a tuple of the (min, max) range of the Coord Vector that overlaps with the bounding box
 ///
 /// Returns (Some(min,max)) if the Coord Vector morton order range overlaps with the bounding box,
@@ -20,6 +89,11 @@ pub fn bbox_range<'a>(coords: UniformVec<'a, Coord>, bbox: [u16; 4]) -> Option<(
     if len == 0 {
         return None;
     }
+    if len == 1 {
+        // no need for the binary searches and their end-of-vector adjustment
+        let coord = coords.get(0).coord;
+        return if min <= coord && coord <= max { Some((0, 0)) } else { None };
+    }
 
     let range_start = coords.get(0).coord;
     if min > range_start {
@@ -295,6 +369,41 @@ mod test {
         assert_eq!(result.len(), 0, "result is on the z-order curve but not in the bbox");
     }
 
+    #[test]
+    fn filter_bbox_single() {
+        // 12 is (2, 2)
+        let buffer = encoded_val_generator(vec![12].into_iter());
+        let reader = gridstore_format::Reader::new(buffer.as_slice());
+        let coords = get_coords_from_reader(&reader);
+
+        assert_eq!(bbox_range(coords, [2, 2, 2, 2]), Some((0, 0)), "bbox is exactly the grid");
+        let result = bbox_filter(coords, [2, 2, 2, 2]).unwrap().collect::<Vec<Coord>>();
+        assert_eq!(result.len(), 1, "bbox is exactly the grid");
+
+        let result = bbox_filter(coords, [1, 1, 3, 3]).unwrap().collect::<Vec<Coord>>();
+        assert_eq!(result.len(), 1, "bbox surrounds the grid");
+
+        let result = bbox_filter(coords, [2, 0, 3, 3]).unwrap().collect::<Vec<Coord>>();
+        assert_eq!(result.len(), 1, "grid is on the bbox's corner");
+
+        let result = bbox_filter(coords, [0, 0, 7, 1]).map(|v| v.collect::<Vec<Coord>>());
+        assert_eq!(result.unwrap_or_default().len(), 0, "bbox spans the grid in z-order only");
+
+        assert!(bbox_filter(coords, [0, 0, 1, 1]).is_none(), "bbox ends before the grid");
+        assert!(bbox_filter(coords, [3, 3, 4, 4]).is_none(), "bbox starts after the grid");
+
+        let result = bbox_proximity_filter(coords, [2, 2, 2, 2], [0, 0])
+            .unwrap()
+            .map(|x| x.coord)
+            .collect::<Vec<u32>>();
+        assert_eq!(result, vec![12], "bbox is exactly the grid; proximity outside it");
+        let result = bbox_proximity_filter(coords, [2, 2, 2, 2], [2, 2])
+            .unwrap()
+            .map(|x| x.coord)
+            .collect::<Vec<u32>>();
+        assert_eq!(result, vec![12], "bbox is exactly the grid; proximity on it");
+    }
+
     #[test]
     fn proximity_search() {
         let buffer = encoded_val_generator((1..10).rev()); // [9,8,7,6,5,4,3,2,1]
@@ -576,7 +685,79 @@ const E_POW: [f64; 8] = [
     1096.6331584284585,
 ];
 
-pub fn scoredist(mut zoom: u16, mut distance: f64, mut score: u8, radius: f64) -> f64 {
+/// The shape of the falloff used to turn distance from the proximity point into scoredist
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ProximityCurve {
+    /// Scoredist is inversely proportional to the distance ratio, capped at the radius
+    Exponential,
+    /// Scoredist decays along a gaussian of the distance ratio, with about the same peak as
+    /// `Exponential` on the proximity tile and approaching the score-only value at the radius
+    Gaussian,
+}
+
+impl Default for ProximityCurve {
+    fn default() -> Self {
+        ProximityCurve::Exponential
+    }
+}
+
+/// How the scoredists against several weighted proximity points are combined into one
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ProximityCombine {
+    /// Use the largest weighted scoredist
+    Max,
+    /// Use the weighted mean of the scoredists
+    WeightedMean,
+}
+
+impl Default for ProximityCombine {
+    fn default() -> Self {
+        ProximityCombine::Max
+    }
+}
+
+/// The unit a proximity radius is given in
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum DistanceUnit {
+    Miles,
+    Kilometers,
+}
+
+impl Default for DistanceUnit {
+    fn default() -> Self {
+        DistanceUnit::Miles
+    }
+}
+
+const KILOMETERS_PER_MILE: f64 = 1.609344;
+
+impl DistanceUnit {
+    /// Converts a distance in this unit into miles, the unit `proximity_radius` works in
+    pub fn to_miles(self, distance: f64) -> f64 {
+        match self {
+            DistanceUnit::Miles => distance,
+            DistanceUnit::Kilometers => distance / KILOMETERS_PER_MILE,
+        }
+    }
+}
+
+#[test]
+fn distance_unit_test() {
+    assert_eq!(DistanceUnit::default(), DistanceUnit::Miles);
+    assert_eq!(DistanceUnit::Miles.to_miles(40.), 40.);
+    assert!((DistanceUnit::Kilometers.to_miles(64.37376) - 40.).abs() < 1e-9);
+}
+
+// Standard deviation of the gaussian falloff, as a fraction of the proximity radius
+const GAUSSIAN_SIGMA: f64 = 1. / 3.;
+
+pub fn scoredist(
+    mut zoom: u16,
+    mut distance: f64,
+    mut score: u8,
+    radius: f64,
+    curve: ProximityCurve,
+) -> f64 {
     if zoom < 6 {
         zoom = 6;
     }
@@ -589,17 +770,121 @@ pub fn scoredist(mut zoom: u16, mut distance: f64, mut score: u8, radius: f64) -
         distance = 0.8;
     }
 
-    let mut dist_ratio: f64 = distance / proximity_radius(zoom, radius);
+    let radius_tiles = proximity_radius(zoom, radius);
+    let mut dist_ratio: f64 = distance / radius_tiles;
+    let base = (6. * E_POW[score as usize] / E_POW[7]) + 1.;
+
+    match curve {
+        ProximityCurve::Exponential => {
+            // Beyond the proximity radius just let scoredist be driven by score.
+            if dist_ratio > 1.0 {
+                dist_ratio = 1.00;
+            }
+            base / dist_ratio
+        }
+        ProximityCurve::Gaussian => {
+            // Peak at about the value the exponential curve reaches on the proximity tile
+            let peak = (radius_tiles / 0.8).max(1.);
+            let falloff =
+                (-(dist_ratio * dist_ratio) / (2. * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+            base * (1. + (peak - 1.) * falloff)
+        }
+    }
+}
 
-    // Beyond the proximity radius just let scoredist be driven by score.
-    if dist_ratio > 1.0 {
-        dist_ratio = 1.00;
+/// Scores a grid at `x`,`y` against the weighted `proximity_points` in `match_opts`, returning
+/// the distance to the nearest point, whether the grid is within the radius of any point, and
+/// the combined scoredist. The radius, curve and zoom come from `match_opts` too.
+pub fn multi_point_scoredist(
+    match_opts: &MatchOpts,
+    x: u16,
+    y: u16,
+    score: u8,
+) -> (f64, bool, f64) {
+    let zoom = match_opts.zoom;
+    let radius = match_opts.proximity.as_ref().map_or(0., |proximity| proximity.radius);
+    let curve = match_opts.proximity_curve;
+    let points = match_opts.proximity_points.as_deref().unwrap_or(&[]);
+    let radius_tiles = proximity_radius(zoom, radius);
+    let mut min_distance = std::f64::MAX;
+    let mut max_scoredist: f64 = 0.;
+    let mut weighted_sum: f64 = 0.;
+    let mut total_weight: f64 = 0.;
+    for (point, weight) in points {
+        let distance = tile_dist(point[0], point[1], x, y);
+        let point_scoredist = weight * scoredist(zoom, distance, score, radius, curve);
+        min_distance = min_distance.min(distance);
+        max_scoredist = max_scoredist.max(point_scoredist);
+        weighted_sum += point_scoredist;
+        total_weight += weight;
     }
-    ((6. * E_POW[score as usize] / E_POW[7]) + 1.) / dist_ratio
+    let combined = match match_opts.proximity_combine {
+        ProximityCombine::Max => max_scoredist,
+        ProximityCombine::WeightedMean if total_weight > 0. => weighted_sum / total_weight,
+        ProximityCombine::WeightedMean => 0.,
+    };
+    (min_distance, min_distance <= radius_tiles, combined)
+}
+
+#[test]
+fn multi_point_scoredist_test() {
+    use crate::gridstore::common::Proximity;
+
+    let near = ([10, 10], 1.);
+    let far = ([100, 100], 1.);
+    let opts = |points: Vec<([u16; 2], f64)>, combine| MatchOpts {
+        zoom: 14,
+        proximity: Some(Proximity { point: [10, 10], radius: 40. }),
+        proximity_points: Some(points),
+        proximity_combine: combine,
+        ..MatchOpts::default()
+    };
+    let (_, _, near_only) =
+        multi_point_scoredist(&opts(vec![near], ProximityCombine::Max), 12, 12, 3);
+    let (_, _, far_only) =
+        multi_point_scoredist(&opts(vec![far], ProximityCombine::Max), 12, 12, 3);
+    assert!(near_only > far_only);
+
+    let (distance, within_radius, max) =
+        multi_point_scoredist(&opts(vec![near, far], ProximityCombine::Max), 12, 12, 3);
+    assert_eq!(max, near_only, "max picks the best point");
+    assert_eq!(distance, tile_dist(10, 10, 12, 12), "distance is to the nearest point");
+    assert!(within_radius);
+
+    let (_, _, mean) =
+        multi_point_scoredist(&opts(vec![near, far], ProximityCombine::WeightedMean), 12, 12, 3);
+    assert!(far_only < mean && mean < near_only, "weighted mean is between the single points");
 }
 
 #[test]
 fn scoredist_test() {
-    assert_eq!(scoredist(14, 1., 0, 400.), 321.7508133738646, "scoredist for a feature 1 tile away from proximity point with score 0 and radius 400 should be 321.7508133738646");
-    assert_eq!(scoredist(14, 0., 0, 400.), 402.1885167173308, "scoredist for a feature on the same tile as the proximity point with score 0 and radius 400 should be 402.1885167173308,");
+    assert_eq!(scoredist(14, 1., 0, 400., ProximityCurve::Exponential), 321.7508133738646, "scoredist for a feature 1 tile away from proximity point with score 0 and radius 400 should be 321.7508133738646");
+    assert_eq!(scoredist(14, 0., 0, 400., ProximityCurve::Exponential), 402.1885167173308, "scoredist for a feature on the same tile as the proximity point with score 0 and radius 400 should be 402.1885167173308,");
+}
+
+#[test]
+fn scoredist_gaussian_test() {
+    let gaussian_peak = scoredist(14, 0., 0, 400., ProximityCurve::Gaussian);
+    let exponential_peak = scoredist(14, 0., 0, 400., ProximityCurve::Exponential);
+    assert!(
+        (gaussian_peak - exponential_peak).abs() / exponential_peak < 0.001,
+        "Both curves peak at about the same value on the proximity tile"
+    );
+    assert_ne!(
+        scoredist(14, 100., 0, 400., ProximityCurve::Gaussian),
+        scoredist(14, 100., 0, 400., ProximityCurve::Exponential),
+        "The gaussian curve has a different falloff"
+    );
+
+    let mut previous = std::f64::MAX;
+    for distance in 0..500 {
+        let current = scoredist(14, distance as f64, 3, 400., ProximityCurve::Gaussian);
+        assert!(current <= previous, "Gaussian scoredist does not increase with distance");
+        previous = current;
+    }
+    assert!(
+        scoredist(14, 1000., 3, 400., ProximityCurve::Gaussian)
+            > scoredist(14, 1000., 2, 400., ProximityCurve::Gaussian),
+        "Beyond the radius, scoredist is still driven by score"
+    );
 }
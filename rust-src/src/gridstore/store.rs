@@ -1,97 +1,281 @@
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet, VecDeque};
 use std::convert::TryInto;
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
 
-use byteorder::{BigEndian, ReadBytesExt};
 use failure::Error;
 use itertools::Itertools;
 use min_max_heap::MinMaxHeap;
-use morton::deinterleave_morton;
+use morton::{deinterleave_morton, interleave_morton};
 use ordered_float::OrderedFloat;
-use rocksdb::{Direction, IteratorMode, Options, DB};
+use ouroboros::self_referencing;
+use rocksdb::{DBVector, Direction, IteratorMode, Options, DB};
+use serde::{Deserialize, Serialize};
 
+use crate::gridstore::builder::GridStoreBuilder;
 use crate::gridstore::common::*;
 use crate::gridstore::gridstore_format;
+use crate::gridstore::packed::{self, PackedStore};
 use crate::gridstore::spatial;
 
 #[derive(Debug)]
 pub struct GridStore {
-    db: DB,
+    db: Storage,
     bin_boundaries: HashSet<u32>,
+    /// The options the store was opened with, for reopening it; the defaults for packed stores
+    open_opts: GridStoreOpenOptions,
     pub path: PathBuf,
 }
 
+/// Where a store's records are read from
+#[derive(Debug)]
+enum Storage {
+    RocksDb(DB),
+    /// A single file written by `GridStore::pack`
+    Packed(PackedStore),
+}
+
+type RecordIter<'a> = Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+
+/// A value read by `Storage::get`. Rocksdb values are handed over in rocksdb's own buffer;
+/// packed values are copied out of the file so they can outlive the store like rocksdb's do.
+enum StoredValue {
+    RocksDb(DBVector),
+    Packed(Box<[u8]>),
+}
+
+impl Deref for StoredValue {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            StoredValue::RocksDb(value) => value,
+            StoredValue::Packed(value) => value,
+        }
+    }
+}
+
+impl AsRef<[u8]> for StoredValue {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Storage {
+    fn get(&self, key: &[u8]) -> Result<Option<StoredValue>, Error> {
+        Ok(match self {
+            Storage::RocksDb(db) => db.get(key)?.map(StoredValue::RocksDb),
+            Storage::Packed(store) => store.get(key).map(|value| StoredValue::Packed(value.into())),
+        })
+    }
+
+    /// Walks the records the way rocksdb's iterators do. Packed records are copied out of the
+    /// file as they're read, so they can outlive the iterator like rocksdb's do.
+    fn iterator<'a>(&'a self, mode: IteratorMode) -> RecordIter<'a> {
+        match self {
+            Storage::RocksDb(db) => Box::new(db.iterator(mode)),
+            Storage::Packed(store) => {
+                let to_owned = |(key, value): (&[u8], &[u8])| (Box::from(key), Box::from(value));
+                match mode {
+                    IteratorMode::Start => Box::new(store.iter_from(&[]).map(to_owned)),
+                    IteratorMode::End => Box::new(store.iter_from_rev(None).map(to_owned)),
+                    IteratorMode::From(key, Direction::Forward) => {
+                        Box::new(store.iter_from(key).map(to_owned))
+                    }
+                    IteratorMode::From(key, Direction::Reverse) => {
+                        Box::new(store.iter_from_rev(Some(key)).map(to_owned))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks the layout of a packed store's file, which `open_packed` leaves to this. Rocksdb
+    /// checks its own files as it reads them.
+    fn verify(&self) -> Result<(), GridStoreError> {
+        match self {
+            Storage::RocksDb(_) => Ok(()),
+            Storage::Packed(store) => store.verify(),
+        }
+    }
+
+    /// Reads a rocksdb integer property. Packed stores only know how many keys they hold.
+    fn property_int_value(&self, name: &str) -> Result<Option<u64>, Error> {
+        Ok(match self {
+            Storage::RocksDb(db) => db.property_int_value(name)?,
+            Storage::Packed(store) if name == "rocksdb.estimate-num-keys" => {
+                Some(store.len() as u64)
+            }
+            Storage::Packed(_) => None,
+        })
+    }
+}
+
+fn decode_bin_boundaries(encoded_boundaries: Option<&[u8]>) -> HashSet<u32> {
+    match encoded_boundaries {
+        Some(encoded_boundaries) => encoded_boundaries
+            .chunks(4)
+            .filter_map(|chunk| {
+                if chunk.len() == 4 {
+                    Some(u32::from_le_bytes(chunk.try_into().unwrap()))
+                } else {
+                    None
+                }
+            })
+            .collect(),
+        None => HashSet::new(),
+    }
+}
+
+/// Owns an encoded value along with an iterator that borrows from it, so the iterator can be
+/// handed back to callers after the value has been read out of rocksdb
+#[self_referencing]
+struct OwnedValueIter<T: AsRef<[u8]> + 'static, I: 'static> {
+    value: T,
+    #[borrows(value)]
+    #[covariant]
+    iter: Box<dyn Iterator<Item = I> + 'this>,
+}
+
+impl<T: AsRef<[u8]> + 'static, I: 'static> Iterator for OwnedValueIter<T, I> {
+    type Item = I;
+
+    #[inline]
+    fn next(&mut self) -> Option<I> {
+        self.with_iter_mut(|iter| iter.next())
+    }
+}
+
 #[inline]
-fn decode_value<T: AsRef<[u8]>>(value: T) -> impl Iterator<Item = GridEntry> {
-    let record_ref = {
-        let value_ref: &[u8] = value.as_ref();
-        // this is pretty sketch: we're opting out of compiler lifetime protection
-        // for this reference. This usage should be safe though, because we'll move the
-        // reference and the underlying owned object around together as a unit (the
-        // tuple below) so that when we pull the reference into the inner closures,
-        // we'll drag the owned object along, and won't drop it until the whole
-        // nest of closures is deleted
-        let static_ref: &'static [u8] = unsafe { std::mem::transmute(value_ref) };
-        (value, static_ref)
-    };
-    let reader = gridstore_format::Reader::new(record_ref.1);
-    let record = { gridstore_format::read_phrase_record_from(&reader) };
+fn decode_value<T: AsRef<[u8]> + 'static>(value: T) -> impl Iterator<Item = GridEntry> {
+    OwnedValueIterBuilder {
+        value,
+        iter_builder: |value: &T| {
+            Box::new(decode_value_slice(value.as_ref())) as Box<dyn Iterator<Item = GridEntry>>
+        },
+    }
+    .build()
+}
 
-    let iter = gridstore_format::read_var_vec_raw(record_ref.1, record.relev_scores)
-        .into_iter()
-        .flat_map(move |rs_obj| {
-            // grab a reference to the outer object to make sure it doesn't get freed
-            let _ref = &record_ref;
+#[inline]
+fn decode_value_slice<'a>(value: &'a [u8]) -> impl Iterator<Item = GridEntry> + 'a {
+    let reader = gridstore_format::Reader::new(value);
+    let record = { gridstore_format::read_phrase_record_from(&reader) };
 
+    let iter = gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter().flat_map(
+        move |rs_obj| {
             let relev_score = rs_obj.relev_score;
             let relev = relev_int_to_float(relev_score >> 4);
             // mask for the least significant four bits
             let score = relev_score & 15;
 
-            let nested_ref = record_ref.1;
-            gridstore_format::read_uniform_vec_raw(record_ref.1, rs_obj.coords)
-                .into_iter()
-                .flat_map(move |coords_obj| {
+            gridstore_format::read_uniform_vec_raw(value, rs_obj.coords).into_iter().flat_map(
+                move |coords_obj| {
                     let (x, y) = deinterleave_morton(coords_obj.coord);
 
-                    gridstore_format::read_fixed_vec_raw(nested_ref, coords_obj.ids)
-                        .into_iter()
-                        .map(move |id_comp| {
+                    gridstore_format::read_fixed_vec_raw(value, coords_obj.ids).into_iter().map(
+                        move |id_comp| {
                             let id = id_comp >> 8;
                             let source_phrase_hash = (id_comp & 255) as u8;
                             GridEntry { relev, score, x, y, id, source_phrase_hash }
-                        })
-                })
-        });
+                        },
+                    )
+                },
+            )
+        },
+    );
     iter
 }
 
 #[inline]
-fn decode_matching_value<T: AsRef<[u8]>>(
+fn decode_coords<T: AsRef<[u8]> + 'static>(value: T) -> impl Iterator<Item = (u16, u16)> {
+    OwnedValueIterBuilder {
+        value,
+        iter_builder: |value: &T| {
+            Box::new(decode_coords_slice(value.as_ref())) as Box<dyn Iterator<Item = (u16, u16)>>
+        },
+    }
+    .build()
+}
+
+#[inline]
+fn decode_coords_slice<'a>(value: &'a [u8]) -> impl Iterator<Item = (u16, u16)> + 'a {
+    let reader = gridstore_format::Reader::new(value);
+    let record = { gridstore_format::read_phrase_record_from(&reader) };
+
+    gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter().flat_map(
+        move |rs_obj| {
+            gridstore_format::read_uniform_vec_raw(value, rs_obj.coords)
+                .into_iter()
+                .map(|coords_obj| deinterleave_morton(coords_obj.coord))
+        },
+    )
+}
+
+/// A caller's test for which grids to keep, applied as records are decoded
+type GridPredicate = Rc<dyn Fn(&GridEntry) -> bool>;
+
+#[inline]
+fn decode_matching_value<T: AsRef<[u8]> + 'static>(
     value: T,
     match_opts: &MatchOpts,
     matches_language: bool,
+    language_multiplier: f64,
+    pred: Option<GridPredicate>,
 ) -> impl Iterator<Item = MatchEntry> {
-    let match_opts = match_opts.clone();
-
-    let record_ref = {
-        let value_ref: &[u8] = value.as_ref();
-        // this is pretty sketch: we're opting out of compiler lifetime protection
-        // for this reference. This usage should be safe though, because we'll move the
-        // reference and the underlying owned object around together as a unit (the
-        // tuple below) so that when we pull the reference into the inner closures,
-        // we'll drag the owned object along, and won't drop it until the whole
-        // nest of closures is deleted
-        let static_ref: &'static [u8] = unsafe { std::mem::transmute(value_ref) };
-        (value, static_ref)
+    OwnedValueIterBuilder {
+        value,
+        iter_builder: |value: &T| {
+            Box::new(decode_matching_value_slice(
+                value.as_ref(),
+                match_opts,
+                matches_language,
+                language_multiplier,
+                pred,
+            )) as Box<dyn Iterator<Item = MatchEntry>>
+        },
+    }
+    .build()
+}
+
+#[inline]
+fn decode_matching_value_slice<'a>(
+    value: &'a [u8],
+    match_opts: &MatchOpts,
+    matches_language: bool,
+    language_multiplier: f64,
+    pred: Option<GridPredicate>,
+) -> impl Iterator<Item = MatchEntry> + 'a {
+    let radius = match_opts.proximity.as_ref().map_or(0., |proximity| proximity.radius);
+    // shared between the nested closures below rather than cloned into each of them
+    let match_opts = Rc::new(match_opts.with_radius_in_miles());
+    let seek_point = match_opts.proximity_seek_point();
+    // candidates are picked from the bbox widened by the soft margin, if any, and then checked
+    // against the original to see whether they're inside it
+    let original_bbox = match_opts.bbox;
+    let bbox = match (match_opts.bbox, match_opts.bbox_soft_margin) {
+        (Some(bbox), Some(margin)) => Some(spatial::expand_bbox(bbox, margin, match_opts.zoom)),
+        (bbox, _) => bbox,
     };
-    let reader = gridstore_format::Reader::new(record_ref.1);
+    // a bbox covering the whole zoom level filters nothing, so skip the range search for it
+    let bbox = bbox.filter(|bbox| !spatial::bbox_covers_zoom(*bbox, match_opts.zoom));
+    // looked up once per grid, so laid out as a table indexed by source phrase hash
+    let boosts: Option<Rc<[f64; 256]>> = match_opts.source_phrase_boosts.as_ref().map(|boosts| {
+        let mut table = [1f64; 256];
+        for (hash, boost) in boosts {
+            table[*hash as usize] = *boost;
+        }
+        Rc::new(table)
+    });
+
+    let reader = gridstore_format::Reader::new(value);
     let record = { gridstore_format::read_phrase_record_from(&reader) };
 
-    let relevs = gridstore_format::read_var_vec_raw(record_ref.1, record.relev_scores)
-        .into_iter()
-        .map(|rs_obj| {
+    let relevs =
+        gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter().map(|rs_obj| {
             let relev_score = rs_obj.relev_score;
             let relev = relev_int_to_float(relev_score >> 4);
             // mask for the least significant four bits
@@ -102,41 +286,44 @@ fn decode_matching_value<T: AsRef<[u8]>>(
     let iter = somewhat_eager_groupby(relevs.into_iter(), |(relev, _, _)| *relev)
         .into_iter()
         .flat_map(move |(relev, score_groups)| {
-            // grab a reference to the outer object to make sure it doesn't get freed
-            let _ref = &record_ref;
-
+            let filter_opts = match_opts.clone();
             let match_opts = match_opts.clone();
-            let nested_ref = _ref.1;
-            let coords_per_score = score_groups.into_iter().map(move |(_, score, rs_obj)| {
-                let coords_vec = gridstore_format::read_uniform_vec_raw(nested_ref, rs_obj.coords);
-                let coords =
-                    match &match_opts {
-                        MatchOpts { bbox: None, proximity: None, .. } => {
-                            Some(Box::new(coords_vec.into_iter())
-                                as Box<dyn Iterator<Item = gridstore_format::Coord>>)
-                        }
-                        MatchOpts { bbox: Some(bbox), proximity: None, .. } => {
-                            match spatial::bbox_filter(coords_vec, *bbox) {
-                                Some(v) => Some(Box::new(v)
-                                    as Box<dyn Iterator<Item = gridstore_format::Coord>>),
-                                None => None,
-                            }
+            let boosts = boosts.clone();
+            let pred = pred.clone();
+            let min_score = match_opts.min_score;
+            // a record's relev/score levels are written in descending order, so within a relev
+            // group, everything after the first level below the minimum score is below it too
+            let score_groups = score_groups
+                .into_iter()
+                .take_while(move |(_, score, _)| min_score.map_or(true, |min| *score >= min));
+            let coords_per_score = score_groups.map(move |(_, score, rs_obj)| {
+                let coords_vec = gridstore_format::read_uniform_vec_raw(value, rs_obj.coords);
+                let coords = match (bbox, &match_opts.proximity) {
+                    (None, None) => Some(Box::new(coords_vec.into_iter())
+                        as Box<dyn Iterator<Item = gridstore_format::Coord>>),
+                    (Some(bbox), None) => match spatial::bbox_filter(coords_vec, bbox) {
+                        Some(v) => {
+                            Some(Box::new(v) as Box<dyn Iterator<Item = gridstore_format::Coord>>)
                         }
-                        MatchOpts { bbox: None, proximity: Some(prox_pt), .. } => {
-                            match spatial::proximity(coords_vec, prox_pt.point) {
-                                Some(v) => Some(Box::new(v)
-                                    as Box<dyn Iterator<Item = gridstore_format::Coord>>),
-                                None => None,
-                            }
+                        None => None,
+                    },
+                    (None, Some(_)) => match spatial::proximity(coords_vec, seek_point.unwrap()) {
+                        Some(v) => {
+                            Some(Box::new(v) as Box<dyn Iterator<Item = gridstore_format::Coord>>)
                         }
-                        MatchOpts { bbox: Some(bbox), proximity: Some(prox_pt), .. } => {
-                            match spatial::bbox_proximity_filter(coords_vec, *bbox, prox_pt.point) {
-                                Some(v) => Some(Box::new(v)
-                                    as Box<dyn Iterator<Item = gridstore_format::Coord>>),
-                                None => None,
+                        None => None,
+                    },
+                    (Some(bbox), Some(_)) => {
+                        match spatial::bbox_proximity_filter(coords_vec, bbox, seek_point.unwrap())
+                        {
+                            Some(v) => {
+                                Some(Box::new(v)
+                                    as Box<dyn Iterator<Item = gridstore_format::Coord>>)
                             }
+                            None => None,
                         }
-                    };
+                    }
+                };
 
                 let coords = coords.unwrap_or_else(|| {
                     Box::new((Option::<gridstore_format::Coord>::None).into_iter())
@@ -146,8 +333,13 @@ fn decode_matching_value<T: AsRef<[u8]>>(
                 coords.map(move |coords_obj| {
                     let (x, y) = deinterleave_morton(coords_obj.coord);
 
-                    let (distance, within_radius, scoredist) = match &match_opts {
-                        MatchOpts { proximity: Some(prox_pt), zoom, .. } => {
+                    let (distance, within_radius, scoredist) = match &*match_opts {
+                        MatchOpts {
+                            proximity: Some(_), proximity_points: Some(points), ..
+                        } if !points.is_empty() => {
+                            spatial::multi_point_scoredist(&match_opts, x, y, score)
+                        }
+                        MatchOpts { proximity: Some(prox_pt), zoom, proximity_curve, .. } => {
                             let distance =
                                 spatial::tile_dist(prox_pt.point[0], prox_pt.point[1], x, y);
                             (
@@ -155,7 +347,13 @@ fn decode_matching_value<T: AsRef<[u8]>>(
                                 // The proximity radius calculation is also done in scoredist
                                 // There could be an opportunity to optimize by doing it once
                                 distance <= spatial::proximity_radius(*zoom, prox_pt.radius),
-                                spatial::scoredist(*zoom, distance, score, prox_pt.radius),
+                                spatial::scoredist(
+                                    *zoom,
+                                    distance,
+                                    score,
+                                    prox_pt.radius,
+                                    *proximity_curve,
+                                ),
                             )
                         }
                         _ => (0f64, false, score as f64),
@@ -172,54 +370,300 @@ fn decode_matching_value<T: AsRef<[u8]>>(
                 scoredist1.partial_cmp(scoredist2).unwrap() == Ordering::Greater
             });
 
-            let nested_ref = record_ref.1;
             all_coords.flat_map(
                 move |(distance, within_radius, score, scoredist, x, y, coords_obj)| {
-                    let ids = gridstore_format::read_fixed_vec_raw(nested_ref, coords_obj.ids);
-
-                    ids.into_iter().map(move |id_comp| {
-                        let id = id_comp >> 8;
-                        let source_phrase_hash = (id_comp & 255) as u8;
-                        MatchEntry {
-                            grid_entry: GridEntry {
-                                relev: relev
-                                    * (if matches_language || within_radius {
-                                        1f64
-                                    } else {
-                                        0.96f64
-                                    }),
-                                score,
-                                x,
-                                y,
-                                id,
-                                source_phrase_hash,
-                            },
-                            matches_language,
-                            distance,
-                            scoredist,
-                        }
-                    })
+                    let ids = gridstore_format::read_fixed_vec_raw(value, coords_obj.ids);
+                    let filter_opts = filter_opts.clone();
+                    let within_bbox = original_bbox.map_or(true, |bbox| {
+                        x >= bbox[0] && y >= bbox[1] && x <= bbox[2] && y <= bbox[3]
+                    });
+                    let boosts = boosts.clone();
+                    let pred = pred.clone();
+                    let language_penalty_inside_radius = filter_opts.language_penalty_inside_radius;
+
+                    ids.into_iter()
+                        .filter(move |id_comp| match &filter_opts.id_filter {
+                            Some(id_filter) => id_filter.contains(&(id_comp >> 8)),
+                            None => true,
+                        })
+                        .map(move |id_comp| {
+                            let id = id_comp >> 8;
+                            let source_phrase_hash = (id_comp & 255) as u8;
+                            let boost = boosts
+                                .as_ref()
+                                .map_or(1f64, |boosts| boosts[source_phrase_hash as usize]);
+                            let language_penalty =
+                                if within_radius && !language_penalty_inside_radius {
+                                    1f64
+                                } else {
+                                    language_multiplier
+                                };
+                            MatchEntry {
+                                grid_entry: GridEntry {
+                                    relev: relev * language_penalty * boost,
+                                    score,
+                                    x,
+                                    y,
+                                    id,
+                                    source_phrase_hash,
+                                },
+                                matches_language,
+                                distance,
+                                scoredist,
+                                radius,
+                                within_bbox,
+                            }
+                        })
+                        .filter(move |entry| {
+                            pred.as_ref().map_or(true, |pred| pred(&entry.grid_entry))
+                        })
                 },
             )
         });
     iter
 }
 
+/// Where `GridStore::get_matching_page` left off: how many matches have been returned so far,
+/// which is the offset the next page starts at, and which grid was the last of them, so a
+/// cursor used with the wrong query can be caught
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Cursor {
+    offset: usize,
+    morton: u32,
+    id: u32,
+}
+
+impl Cursor {
+    fn after(offset: usize, entry: &MatchEntry) -> Cursor {
+        Cursor {
+            offset,
+            morton: interleave_morton(entry.grid_entry.x, entry.grid_entry.y),
+            id: entry.grid_entry.id,
+        }
+    }
+}
+
+/// A difference between two stores found by `GridStore::diff`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Diff {
+    /// The key is only in the first store
+    OnlyInFirst { key: GridKey },
+    /// The key is only in the second store
+    OnlyInSecond { key: GridKey },
+    /// Both stores have the key, but with different grids
+    EntriesDiffer { key: GridKey, only_in_first: Vec<GridEntry>, only_in_second: Vec<GridEntry> },
+}
+
+type RecordResult = Result<(GridKey, Vec<GridEntry>), GridStoreError>;
+
+/// The merge behind `GridStore::merge_iter`: holds the next record from each store, and a heap
+/// of their encoded keys so the smallest comes out first. Ties break by store index.
+struct MergeIter<'a> {
+    key_ordering: KeyOrdering,
+    iters: Vec<Box<dyn Iterator<Item = RecordResult> + 'a>>,
+    heads: Vec<Option<(GridKey, Vec<GridEntry>)>>,
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    errors: VecDeque<GridStoreError>,
+}
+
+impl<'a> MergeIter<'a> {
+    /// Pulls the next record from the `i`th store onto the heap, setting aside any errors on the
+    /// way to be yielded ahead of the next group
+    fn advance(&mut self, i: usize) {
+        loop {
+            match self.iters[i].next() {
+                Some(Ok((key, entries))) => {
+                    let mut db_key: Vec<u8> = Vec::new();
+                    match key.write_to(TypeMarker::SinglePhrase, &mut db_key) {
+                        Ok(()) => {
+                            let sort_key = self.key_ordering.sort_bytes(&db_key);
+                            self.heap.push(Reverse((sort_key, i)));
+                            self.heads[i] = Some((key, entries));
+                            return;
+                        }
+                        Err(err) => self.errors.push_back(err.into()),
+                    }
+                }
+                Some(Err(err)) => self.errors.push_back(err),
+                None => return,
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for MergeIter<'a> {
+    type Item = Result<(GridKey, Vec<(usize, Vec<GridEntry>)>), GridStoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.errors.pop_front() {
+            return Some(Err(err));
+        }
+        let Reverse((db_key, i)) = self.heap.pop()?;
+        let (key, entries) = self.heads[i].take().expect("every heap entry has a head");
+        let mut group = vec![(i, entries)];
+        self.advance(i);
+        while self.heap.peek().map_or(false, |Reverse((next_key, _))| next_key == &db_key) {
+            let Reverse((_, j)) = self.heap.pop().unwrap();
+            let (_, entries) = self.heads[j].take().expect("every heap entry has a head");
+            group.push((j, entries));
+            self.advance(j);
+        }
+        Some(Ok((key, group)))
+    }
+}
+
+/// Estimated memory held by a store's rocksdb instance, in bytes, as reported by
+/// `GridStore::memory_usage`
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct MemoryUsage {
+    /// Memory used by the readers of the store's table files, including their indexes and
+    /// filters
+    pub table_readers: u64,
+    /// Memory used by the block cache
+    pub block_cache: u64,
+    /// Memory used by the store's mem-tables
+    pub mem_tables: u64,
+}
+
+/// A structural problem found in a store by `GridStore::verify`
+#[derive(Debug, PartialEq, Clone)]
+pub enum VerifyIssue {
+    /// The relev/score groups of a record aren't in strictly descending order
+    RelevScoresNotDescending { key: GridKey },
+    /// The relev bits of a relev/score group don't map to one of the four relevance levels
+    RelevOutOfRange { key: GridKey, relev_score: u8 },
+    /// The score bits of a relev/score group are above 7
+    ScoreOutOfRange { key: GridKey, relev_score: u8 },
+    /// The coords in a relev/score group aren't in strictly descending morton order, which the
+    /// bbox and proximity searches rely on
+    CoordsNotDescending { key: GridKey, relev_score: u8 },
+    /// The ids at a coord aren't in strictly descending order
+    IdsNotDescending { key: GridKey, relev_score: u8, coord: u32 },
+    /// A prefix bin boundary is past the end of the phrases in the store
+    BinBoundaryOutOfRange { boundary: u32, max_phrase_id: Option<u32> },
+}
+
+/// Checks the ordering and value invariants of a single encoded record, appending any issues
+fn verify_value(key: &GridKey, value: &[u8], issues: &mut Vec<VerifyIssue>) {
+    let reader = gridstore_format::Reader::new(value);
+    let record = gridstore_format::read_phrase_record_from(&reader);
+
+    let mut prev_relev_score: Option<u8> = None;
+    for rs_obj in gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter() {
+        let relev_score = rs_obj.relev_score;
+        if prev_relev_score.map_or(false, |prev| prev <= relev_score) {
+            issues.push(VerifyIssue::RelevScoresNotDescending { key: key.clone() });
+        }
+        prev_relev_score = Some(relev_score);
+        if relev_score >> 4 > 3 {
+            issues.push(VerifyIssue::RelevOutOfRange { key: key.clone(), relev_score });
+        }
+        if relev_score & 15 > 7 {
+            issues.push(VerifyIssue::ScoreOutOfRange { key: key.clone(), relev_score });
+        }
+
+        let mut prev_coord: Option<u32> = None;
+        for coords_obj in gridstore_format::read_uniform_vec_raw(value, rs_obj.coords).into_iter() {
+            if prev_coord.map_or(false, |prev| prev <= coords_obj.coord) {
+                issues.push(VerifyIssue::CoordsNotDescending { key: key.clone(), relev_score });
+            }
+            prev_coord = Some(coords_obj.coord);
+
+            let ids = gridstore_format::read_fixed_vec_raw(value, coords_obj.ids);
+            if ids.into_iter().tuple_windows().any(|(a, b)| a <= b) {
+                issues.push(VerifyIssue::IdsNotDescending {
+                    key: key.clone(),
+                    relev_score,
+                    coord: coords_obj.coord,
+                });
+            }
+        }
+    }
+}
+
+/// A small seeded PRNG (splitmix64) -- we only need reproducible, reasonably-distributed numbers
+/// for sampling, not anything cryptographic
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
 struct QueueElement<T: Iterator<Item = MatchEntry>> {
     next_entry: MatchEntry,
     entry_iter: T,
+    /// The language the key's grids count toward for `max_per_language`, if it's being applied
+    language: Option<u32>,
+}
+
+/// The language a key's grids count toward for `MatchOpts::max_per_language`: the lowest of the
+/// key's languages that the query asked for, or failing that the lowest of the key's languages.
+/// Keys for all languages don't count toward any.
+fn capped_language(key_lang_set: LangSet, query_lang_set: LangSet) -> Option<u32> {
+    if key_lang_set.is_all() || key_lang_set.is_empty() {
+        return None;
+    }
+    let matched = key_lang_set.intersection(query_lang_set);
+    Some(if !matched.is_empty() { matched } else { key_lang_set }.bits().trailing_zeros())
+}
+
+/// The grids only in `a` and only in `b`, found with a merge walk over both sorted into read
+/// order. Like comparing them as sets, a grid that's in both is left out of both however many
+/// times each has it.
+fn diff_entries(mut a: Vec<GridEntry>, mut b: Vec<GridEntry>) -> (Vec<GridEntry>, Vec<GridEntry>) {
+    a.sort_by_key(GridEntry::read_order_key);
+    b.sort_by_key(GridEntry::read_order_key);
+    let (mut only_in_a, mut only_in_b) = (Vec::new(), Vec::new());
+    let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(a_entry), Some(b_entry)) => {
+                a_entry.read_order_key().cmp(&b_entry.read_order_key())
+            }
+        };
+        match ordering {
+            Ordering::Less => only_in_a.push(a.next().unwrap()),
+            Ordering::Greater => only_in_b.push(b.next().unwrap()),
+            Ordering::Equal => {
+                let key = a.next().unwrap().read_order_key();
+                while a.peek().map_or(false, |entry| entry.read_order_key() == key) {
+                    a.next();
+                }
+                while b.peek().map_or(false, |entry| entry.read_order_key() == key) {
+                    b.next();
+                }
+            }
+        }
+    }
+    (only_in_a, only_in_b)
+}
+
+/// The order matches come out of `GridStore::streaming_get_matching` in, best last
+fn match_sort_key(
+    entry: &MatchEntry,
+) -> (OrderedFloat<f64>, OrderedFloat<f64>, bool, u16, u16, u32) {
+    (
+        OrderedFloat(entry.grid_entry.relev),
+        OrderedFloat(entry.scoredist),
+        entry.matches_language,
+        entry.grid_entry.x,
+        entry.grid_entry.y,
+        entry.grid_entry.id,
+    )
 }
 
 impl<T: Iterator<Item = MatchEntry>> QueueElement<T> {
     fn sort_key(&self) -> (OrderedFloat<f64>, OrderedFloat<f64>, bool, u16, u16, u32) {
-        (
-            OrderedFloat(self.next_entry.grid_entry.relev),
-            OrderedFloat(self.next_entry.scoredist),
-            self.next_entry.matches_language,
-            self.next_entry.grid_entry.x,
-            self.next_entry.grid_entry.y,
-            self.next_entry.grid_entry.id,
-        )
+        match_sort_key(&self.next_entry)
     }
 }
 
@@ -243,36 +687,130 @@ impl<T: Iterator<Item = MatchEntry>> PartialEq for QueueElement<T> {
 
 impl<T: Iterator<Item = MatchEntry>> Eq for QueueElement<T> {}
 
+/// Settings for opening a `GridStore` with `GridStore::open`. Stores are always opened read-only;
+/// the defaults match `GridStore::new`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GridStoreOpenOptions {
+    /// Read the store's files through mmap rather than with reads into the block cache
+    pub allow_mmap_reads: bool,
+    /// Limit on how many of the store's files rocksdb keeps open at once, or `None` for no limit
+    pub max_open_files: Option<i32>,
+    /// The key ordering the store was built with (see `GridStoreBuilder::set_key_ordering`)
+    pub key_ordering: KeyOrdering,
+}
+
+impl Default for GridStoreOpenOptions {
+    fn default() -> Self {
+        GridStoreOpenOptions {
+            allow_mmap_reads: true,
+            max_open_files: None,
+            key_ordering: KeyOrdering::default(),
+        }
+    }
+}
+
 impl GridStore {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, GridStoreError> {
+        GridStore::open(path, GridStoreOpenOptions::default())
+    }
+
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        open_opts: GridStoreOpenOptions,
+    ) -> Result<Self, GridStoreError> {
         let path = path.as_ref().to_owned();
+        // a read-only open can't create the store, so report a missing one as a missing path
+        if !path.exists() {
+            let message = format!("no store at {}", path.display());
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, message).into());
+        }
         let mut opts = Options::default();
         opts.set_read_only(true);
-        opts.set_allow_mmap_reads(true);
+        opts.set_allow_mmap_reads(open_opts.allow_mmap_reads);
+        if let Some(max_open_files) = open_opts.max_open_files {
+            opts.set_max_open_files(max_open_files);
+        }
+        open_opts.key_ordering.configure(&mut opts);
+        let db = Storage::RocksDb(DB::open(&opts, &path)?);
+        let bin_boundaries = decode_bin_boundaries(db.get(b"~BOUNDS")?.as_deref());
+
+        Ok(GridStore { db, path, bin_boundaries, open_opts })
+    }
+
+    /// Opens a single file written by `pack`, memory-mapping it rather than going through
+    /// rocksdb. The result answers queries exactly as the store it was packed from does. Only the
+    /// file's header and index are checked here, so opening doesn't read every record; run
+    /// `verify` on files that may be damaged.
+    pub fn open_packed<P: AsRef<Path>>(file: P) -> Result<Self, GridStoreError> {
+        let path = file.as_ref().to_owned();
+        let db = Storage::Packed(PackedStore::open(&path)?);
+        let bin_boundaries = decode_bin_boundaries(db.get(b"~BOUNDS")?.as_deref());
+
+        Ok(GridStore { db, path, bin_boundaries, open_opts: GridStoreOpenOptions::default() })
+    }
+
+    /// Writes every record in the store, bin boundaries included, to `out_file` as a single
+    /// self-describing file that `open_packed` can read without rocksdb. Any existing file at
+    /// `out_file` is replaced. Packed files are searched in byte order, so only stores built with
+    /// `KeyOrdering::PhraseFirst` can be packed.
+    pub fn pack<P: AsRef<Path>>(&self, out_file: P) -> Result<(), GridStoreError> {
+        if self.key_ordering() != KeyOrdering::PhraseFirst {
+            let err = KeyOrderingError::Unsupported {
+                operation: "packing",
+                ordering: self.key_ordering(),
+            };
+            return Err(GridStoreError::Other(err.into()));
+        }
+        packed::write_packed(out_file.as_ref(), self.db.iterator(IteratorMode::Start))
+    }
+
+    /// Runs a full rocksdb compaction over the store, for stores whose files have been written
+    /// to since they were built, and hands back the store reopened with the same options.
+    /// Compaction replaces the store's table files, so the store is closed first rather than
+    /// left reading underneath it; nothing else may have the directory open meanwhile. A packed
+    /// store is already laid out for reading, so it's handed back as it is.
+    pub fn compact(self) -> Result<GridStore, GridStoreError> {
+        if let Storage::Packed(_) = self.db {
+            return Ok(self);
+        }
+        let path = self.path.clone();
+        let open_opts = self.open_opts.clone();
+        drop(self);
+
+        let mut opts = Options::default();
+        opts.set_disable_auto_compactions(true);
+        open_opts.key_ordering.configure(&mut opts);
         let db = DB::open(&opts, &path)?;
+        db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        drop(db);
 
-        let bin_boundaries: HashSet<u32> = match db.get("~BOUNDS")? {
-            Some(entry) => {
-                let encoded_boundaries: &[u8] = entry.as_ref();
-                encoded_boundaries
-                    .chunks(4)
-                    .filter_map(|chunk| {
-                        if chunk.len() == 4 {
-                            Some(u32::from_le_bytes(chunk.try_into().unwrap()))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
-            }
-            None => HashSet::new(),
-        };
+        GridStore::open(path, open_opts)
+    }
+
+    /// Opens several stores at once, each on its own thread, and hands them back in `Arc`s ready
+    /// to share between subqueries, in the same order as `paths`. If any store fails to open,
+    /// the first failure (in `paths` order) is returned.
+    pub fn open_many<P: AsRef<Path> + Sync>(
+        paths: &[P],
+        open_opts: &GridStoreOpenOptions,
+    ) -> Result<Vec<Arc<GridStore>>, GridStoreError> {
+        let opened: Vec<Result<GridStore, GridStoreError>> = crossbeam_utils::thread::scope(|s| {
+            let handles: Vec<_> = paths
+                .iter()
+                .map(|path| s.spawn(move |_| GridStore::open(path, open_opts.clone())))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().expect("open thread panicked")).collect()
+        })
+        .expect("open thread panicked");
 
-        Ok(GridStore { db, path, bin_boundaries })
+        opened.into_iter().map(|store| store.map(Arc::new)).collect()
     }
 
     #[inline(never)]
-    pub fn get(&self, key: &GridKey) -> Result<Option<impl Iterator<Item = GridEntry>>, Error> {
+    pub fn get(
+        &self,
+        key: &GridKey,
+    ) -> Result<Option<impl Iterator<Item = GridEntry>>, GridStoreError> {
         let mut db_key: Vec<u8> = Vec::new();
         key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
 
@@ -282,112 +820,942 @@ impl GridStore {
         })
     }
 
-    pub fn streaming_get_matching(
+    /// Like `get`, but only yields the position of each coord stored for `key`, without
+    /// decoding the ids stored at it. A coord is yielded once for each relev/score level it
+    /// appears in, so the same position can come up more than once.
+    pub fn get_coords(
         &self,
-        match_key: &MatchKey,
-        match_opts: &MatchOpts,
-        max_values: usize,
-    ) -> Result<impl Iterator<Item = MatchEntry>, Error> {
-        let (fetch_start, fetch_end, fetch_type_marker) = match match_key.match_phrase {
-            MatchPhrase::Exact(id) => (id, id + 1, TypeMarker::SinglePhrase),
-            MatchPhrase::Range { start, end } => {
-                if self.bin_boundaries.contains(&start) && self.bin_boundaries.contains(&end) {
-                    (start, end, TypeMarker::PrefixBin)
-                } else {
-                    (start, end, TypeMarker::SinglePhrase)
+        key: &GridKey,
+    ) -> Result<Option<impl Iterator<Item = (u16, u16)>>, GridStoreError> {
+        let mut db_key: Vec<u8> = Vec::new();
+        key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
+
+        Ok(match self.db.get(&db_key)? {
+            Some(value) => Some(decode_coords(value)),
+            None => None,
+        })
+    }
+
+    /// Returns the store's prefix bin boundaries in ascending order
+    pub fn bin_boundaries(&self) -> Vec<u32> {
+        let mut boundaries: Vec<u32> = self.bin_boundaries.iter().cloned().collect();
+        boundaries.sort();
+        boundaries
+    }
+
+    /// Returns the `[start, end)` phrase ID range covered by the `bin`th prefix bin, or `None` if
+    /// the store has no bin boundaries or not that many bins. The boundaries don't record which
+    /// prefix each bin was built for; bins are numbered in the order their prefixes sort in, so
+    /// callers map a prefix to its position among the prefixes the bins were built from.
+    pub fn prefix_range(&self, bin: usize) -> Option<(u32, u32)> {
+        let boundaries = self.bin_boundaries();
+        match (boundaries.get(bin), boundaries.get(bin + 1)) {
+            (Some(start), Some(end)) => Some((*start, *end)),
+            _ => None,
+        }
+    }
+
+    /// Returns the grids stored for `key` exactly as they're encoded, as (packed relev/score,
+    /// morton coord, packed id and source phrase hash) triples in storage order, without turning
+    /// them back into `GridEntry`s. Useful for debugging the stored structure of an index.
+    pub fn get_raw(&self, key: &GridKey) -> Result<Option<Vec<(u8, u32, u32)>>, GridStoreError> {
+        let mut db_key: Vec<u8> = Vec::new();
+        key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
+
+        let value = match self.db.get(&db_key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let value: &[u8] = value.as_ref();
+        let reader = gridstore_format::Reader::new(value);
+        let record = gridstore_format::read_phrase_record_from(&reader);
+
+        let mut out = Vec::new();
+        for rs_obj in gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter() {
+            for coords_obj in
+                gridstore_format::read_uniform_vec_raw(value, rs_obj.coords).into_iter()
+            {
+                for id_comp in
+                    gridstore_format::read_fixed_vec_raw(value, coords_obj.ids).into_iter()
+                {
+                    out.push((rs_obj.relev_score, coords_obj.coord, id_comp));
                 }
             }
+        }
+        Ok(Some(out))
+    }
+
+    /// Counts the grids stored for `key` from the lengths of its encoded lists, without decoding
+    /// the grids themselves. Returns 0 if the key isn't in the store.
+    pub fn phrase_entry_count(&self, key: &GridKey) -> Result<usize, GridStoreError> {
+        let mut db_key: Vec<u8> = Vec::new();
+        key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
+
+        let value = match self.db.get(&db_key)? {
+            Some(value) => value,
+            None => return Ok(0),
         };
+        let value: &[u8] = value.as_ref();
+        let reader = gridstore_format::Reader::new(value);
+        let record = gridstore_format::read_phrase_record_from(&reader);
 
-        let match_opts = match_opts.clone();
+        let mut count = 0;
+        for rs_obj in gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter() {
+            for coords_obj in
+                gridstore_format::read_uniform_vec_raw(value, rs_obj.coords).into_iter()
+            {
+                count += gridstore_format::read_fixed_vec_raw(value, coords_obj.ids).len();
+            }
+        }
+        Ok(count)
+    }
 
-        let mut range_key = match_key.clone();
-        range_key.match_phrase = MatchPhrase::Range { start: fetch_start, end: fetch_end };
+    /// The distinct source phrase hashes of the grids stored for `key`, in ascending order, read
+    /// straight from the stored ids without decoding the grids. Empty if the key isn't in the
+    /// store.
+    pub fn source_phrase_hashes(&self, key: &GridKey) -> Result<Vec<u8>, GridStoreError> {
         let mut db_key: Vec<u8> = Vec::new();
-        range_key.write_start_to(fetch_type_marker, &mut db_key)?;
+        key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
 
-        let db_iter = self
-            .db
-            .iterator(IteratorMode::From(&db_key, Direction::Forward))
-            .take_while(|(k, _)| range_key.matches_key(fetch_type_marker, k).unwrap());
+        let value = match self.db.get(&db_key)? {
+            Some(value) => value,
+            None => return Ok(Vec::new()),
+        };
+        let value: &[u8] = value.as_ref();
+        let reader = gridstore_format::Reader::new(value);
+        let record = gridstore_format::read_phrase_record_from(&reader);
+
+        let mut seen = [false; 256];
+        for rs_obj in gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter() {
+            for coords_obj in
+                gridstore_format::read_uniform_vec_raw(value, rs_obj.coords).into_iter()
+            {
+                for id_comp in
+                    gridstore_format::read_fixed_vec_raw(value, coords_obj.ids).into_iter()
+                {
+                    // the hash is the low byte, below the 24-bit feature id
+                    seen[(id_comp & 255) as usize] = true;
+                }
+            }
+        }
+        Ok((0..=255u8).filter(|hash| seen[*hash as usize]).collect())
+    }
+
+    /// The order this store's keys are sorted in
+    pub fn key_ordering(&self) -> KeyOrdering {
+        self.open_opts.key_ordering
+    }
+
+    /// The records under `type_marker` for phrase IDs in `[start, end)`, in store order. With
+    /// `KeyOrdering::PhraseFirst` they're all together, so this is one seek. With `LanguageFirst`
+    /// each language set's share of them is, so this seeks to the start of the range within each
+    /// language set in turn. Malformed keys are passed through for the caller to report.
+    fn phrase_range<'i>(&'i self, type_marker: TypeMarker, start: u32, end: u64) -> RecordIter<'i> {
+        let in_range = move |phrase_id: u32| start <= phrase_id && u64::from(phrase_id) < end;
+        match self.key_ordering() {
+            KeyOrdering::PhraseFirst => {
+                let mut db_key: Vec<u8> = vec![type_marker as u8];
+                db_key.extend_from_slice(&start.to_be_bytes());
+                Box::new(
+                    self.db.iterator(IteratorMode::From(&db_key, Direction::Forward)).take_while(
+                        move |(key, _)| {
+                            type_marker.marks(key)
+                                && GridKey::from_bytes(key)
+                                    .map_or(true, |grid_key| in_range(grid_key.phrase_id))
+                        },
+                    ),
+                )
+            }
+            KeyOrdering::LanguageFirst => {
+                let mut next_seek = Some(LangSet::default());
+                let mut records: Option<RecordIter<'i>> = None;
+                Box::new(std::iter::from_fn(move || loop {
+                    if records.is_none() {
+                        let lang_set = next_seek.take()?;
+                        let mut db_key: Vec<u8> = Vec::new();
+                        GridKey { phrase_id: start, lang_set }
+                            .write_to(type_marker, &mut db_key)
+                            .expect("writing to a Vec can't fail");
+                        records =
+                            Some(self.db.iterator(IteratorMode::From(&db_key, Direction::Forward)));
+                    }
+                    let (key, value) = records.as_mut().unwrap().next()?;
+                    if !type_marker.marks(&key) {
+                        return None;
+                    }
+                    let grid_key = match GridKey::from_bytes(&key) {
+                        Ok(grid_key) => grid_key,
+                        Err(_) => return Some((key, value)),
+                    };
+                    if in_range(grid_key.phrase_id) {
+                        return Some((key, value));
+                    }
+                    // either short of the range in this language set, or past it: seek to the
+                    // start of the range in this language set or the next one
+                    next_seek = if grid_key.phrase_id < start {
+                        Some(grid_key.lang_set)
+                    } else {
+                        grid_key.lang_set.bits().checked_add(1).map(LangSet::from)
+                    };
+                    records = None;
+                }))
+            }
+        }
+    }
+
+    /// The ranges of phrases to read for `match_key`, and which section of the store to read
+    /// each from: a range that lines up with prefix bin boundaries is read from the prefix bins
+    fn fetch_ranges(&self, match_key: &MatchKey) -> Vec<(u32, u32, TypeMarker)> {
+        match &match_key.match_phrase {
+            MatchPhrase::Exact(id) => vec![(*id, id + 1, TypeMarker::SinglePhrase)],
+            MatchPhrase::Range { start, end } => {
+                if self.bin_boundaries.contains(start) && self.bin_boundaries.contains(end) {
+                    vec![(*start, *end, TypeMarker::PrefixBin)]
+                } else {
+                    vec![(*start, *end, TypeMarker::SinglePhrase)]
+                }
+            }
+            // a set of phrases is fetched as one single-phrase range per member
+            MatchPhrase::Set(ids) => {
+                let mut ids = ids.clone();
+                ids.sort();
+                ids.dedup();
+                ids.into_iter().map(|id| (id, id + 1, TypeMarker::SinglePhrase)).collect()
+            }
+        }
+    }
+
+    /// Whether `streaming_get_matching` would find anything for `match_key`, found by seeking to
+    /// the first matching key without decoding any grids. As with matching, keys in languages
+    /// other than `match_key`'s count, since their grids are returned with a language penalty.
+    pub fn has_match(&self, match_key: &MatchKey) -> Result<bool, GridStoreError> {
+        for (fetch_start, fetch_end, fetch_type_marker) in self.fetch_ranges(match_key) {
+            let first = self.phrase_range(fetch_type_marker, fetch_start, fetch_end.into()).next();
+            if let Some((key, _)) = first {
+                // a malformed key is an error rather than a match
+                GridKey::from_bytes(&key)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn streaming_get_matching(
+        &self,
+        match_key: &MatchKey,
+        match_opts: &MatchOpts,
+        max_values: usize,
+    ) -> Result<impl Iterator<Item = MatchEntry>, GridStoreError> {
+        self.streaming_get_matching_with(match_key, match_opts, max_values, None)
+    }
+
+    /// `streaming_get_matching`, keeping only the grids `pred` accepts if there is one
+    fn streaming_get_matching_with(
+        &self,
+        match_key: &MatchKey,
+        match_opts: &MatchOpts,
+        max_values: usize,
+        pred: Option<GridPredicate>,
+    ) -> Result<impl Iterator<Item = MatchEntry>, GridStoreError> {
+        let match_opts = match_opts.clone();
 
         let mut pri_queue = MinMaxHeap::<QueueElement<_>>::new();
 
-        for (key, value) in db_iter {
-            let matches_language = match_key.matches_language(&key).unwrap();
-            let mut entry_iter = decode_matching_value(value, &match_opts, matches_language);
-            if let Some(next_entry) = entry_iter.next() {
-                let queue_element = QueueElement { next_entry, entry_iter };
-                if pri_queue.len() >= max_values {
-                    let worst_entry = pri_queue.peek_min().unwrap();
-                    if worst_entry >= &queue_element {
-                        continue;
+        for (fetch_start, fetch_end, fetch_type_marker) in self.fetch_ranges(match_key) {
+            let db_iter = self.phrase_range(fetch_type_marker, fetch_start, fetch_end.into());
+
+            for (key, value) in db_iter {
+                let (matches_language, language_multiplier) = match_key.language_match(&key)?;
+                let entry_iter = decode_matching_value(
+                    value,
+                    &match_opts,
+                    matches_language,
+                    language_multiplier,
+                    pred.clone(),
+                );
+                // boosts can lift grids above better-stored ones in the same record, so the
+                // record's grids have to be re-sorted before they can be merged with the others
+                let mut entry_iter: Box<dyn Iterator<Item = MatchEntry>> =
+                    if match_opts.source_phrase_boosts.is_some() {
+                        let mut entries: Vec<_> = entry_iter.collect();
+                        entries.sort_by(|a, b| match_sort_key(b).cmp(&match_sort_key(a)));
+                        Box::new(entries.into_iter())
                     } else {
-                        pri_queue.replace_min(queue_element);
+                        Box::new(entry_iter)
+                    };
+                if let Some(next_entry) = entry_iter.next() {
+                    let language = match match_opts.max_per_language {
+                        Some(_) => {
+                            capped_language(GridKey::from_bytes(&key)?.lang_set, match_key.lang_set)
+                        }
+                        None => None,
+                    };
+                    let queue_element = QueueElement { next_entry, entry_iter, language };
+                    if pri_queue.len() >= max_values {
+                        let worst_entry = pri_queue.peek_min().unwrap();
+                        if worst_entry >= &queue_element {
+                            continue;
+                        } else {
+                            pri_queue.replace_min(queue_element);
+                        }
+                    } else {
+                        pri_queue.push(queue_element);
                     }
-                } else {
-                    pri_queue.push(queue_element);
                 }
             }
         }
 
         let iter = std::iter::from_fn(move || {
             if let Some(mut best_entry) = pri_queue.peek_max_mut() {
+                let language = best_entry.language;
                 if let Some(mut next_entry) = best_entry.entry_iter.next() {
                     std::mem::swap(&mut next_entry, &mut (best_entry.next_entry));
-                    Some(next_entry)
+                    Some((next_entry, language))
                 } else {
                     let best_entry = best_entry.pop();
-                    Some(best_entry.next_entry)
+                    Some((best_entry.next_entry, language))
                 }
             } else {
                 None
             }
         });
-        Ok(iter)
+
+        let max_per_language = match_opts.max_per_language;
+        let mut language_counts = [0usize; 128];
+        let iter = iter.filter_map(move |(entry, language)| match (language, max_per_language) {
+            (Some(language), Some(max)) => {
+                let count = &mut language_counts[language as usize];
+                if *count >= max {
+                    None
+                } else {
+                    *count += 1;
+                    Some(entry)
+                }
+            }
+            _ => Some(entry),
+        });
+
+        // the same test for being inside the radius that scoredist uses
+        let radius_cap = match (
+            &match_opts.with_radius_in_miles().proximity,
+            match_opts.proximity_max_within_radius,
+        ) {
+            (Some(proximity), Some(max)) => {
+                Some((spatial::proximity_radius(match_opts.zoom, proximity.radius), max))
+            }
+            _ => None,
+        };
+        let mut within_radius_count = 0;
+        Ok(iter.filter(move |entry| match radius_cap {
+            Some((radius, max)) if entry.distance <= radius => {
+                within_radius_count += 1;
+                within_radius_count <= max
+            }
+            _ => true,
+        }))
+    }
+
+    /// Like `streaming_get_matching` with no limit on the number of keys read, but only yields
+    /// the grids `pred` accepts. The predicate runs as each record is decoded, next to the
+    /// `id_filter` check, so rejected grids are dropped before they're ranked against other
+    /// records or counted toward `max_per_language` and `proximity_max_within_radius`. It sees
+    /// each grid as it would be returned, so its `relev` already includes any language penalty
+    /// and boost. Scoredist is worked out per coordinate before a coordinate's grids are read,
+    /// so it's still computed for coordinates whose grids are all rejected.
+    pub fn get_matching_filtered<F: Fn(&GridEntry) -> bool + 'static>(
+        &self,
+        match_key: &MatchKey,
+        match_opts: &MatchOpts,
+        pred: F,
+    ) -> Result<impl Iterator<Item = MatchEntry>, GridStoreError> {
+        self.streaming_get_matching_with(
+            match_key,
+            match_opts,
+            std::usize::MAX,
+            Some(Rc::new(pred)),
+        )
+    }
+
+    /// Like `streaming_get_matching` with no limit on the number of keys read, but takes
+    /// `match_opts` at whatever zoom the caller has them at and adjusts them to `store_zoom`
+    /// (the zoom the store's grids were indexed at) first, the way `coalesce` does for each
+    /// subquery. Stores don't record their zoom, so the caller passes it in.
+    pub fn get_matching_at(
+        &self,
+        match_key: &MatchKey,
+        match_opts: &MatchOpts,
+        store_zoom: u16,
+    ) -> Result<impl Iterator<Item = MatchEntry>, GridStoreError> {
+        let match_opts = match_opts.adjust_to_zoom(store_zoom);
+        self.streaming_get_matching(match_key, &match_opts, std::usize::MAX)
+    }
+
+    /// Returns up to `page_size` of the grids `streaming_get_matching` would return for
+    /// `match_key`, starting after `cursor` (or from the start if it's `None`), along with a
+    /// cursor for the next page if there's anything left.
+    ///
+    /// This is offset-based paging: matches come out ranked across every key in the range, so
+    /// there's no position in the store to seek back to, and each page re-runs the match and
+    /// skips the `offset` grids before it. Paging through n grids therefore costs O(n²); callers
+    /// that want everything should stream it instead. Finished stores don't change, so the
+    /// skipped grids are the ones already returned, and the cursor's last grid is checked to
+    /// catch a cursor from a different key or set of options, which is reported as
+    /// `GridStoreError::InvalidCursor`.
+    pub fn get_matching_page(
+        &self,
+        match_key: &MatchKey,
+        match_opts: &MatchOpts,
+        cursor: Option<Cursor>,
+        page_size: usize,
+    ) -> Result<(Vec<MatchEntry>, Option<Cursor>), GridStoreError> {
+        if page_size == 0 {
+            return Ok((Vec::new(), cursor));
+        }
+        let mut matches =
+            self.streaming_get_matching(match_key, match_opts, std::usize::MAX)?.peekable();
+
+        let offset = match cursor {
+            Some(cursor) => {
+                let last = cursor.offset.checked_sub(1).and_then(|n| matches.nth(n));
+                if last.map(|entry| Cursor::after(cursor.offset, &entry)) != Some(cursor.clone()) {
+                    return Err(GridStoreError::InvalidCursor { offset: cursor.offset });
+                }
+                cursor.offset
+            }
+            None => 0,
+        };
+
+        let page: Vec<MatchEntry> = matches.by_ref().take(page_size).collect();
+        let next_cursor = match (page.last(), matches.peek()) {
+            (Some(last), Some(_)) => Some(Cursor::after(offset + page.len(), last)),
+            _ => None,
+        };
+        Ok((page, next_cursor))
     }
 
-    pub fn keys<'i>(&'i self) -> impl Iterator<Item = Result<GridKey, Error>> + 'i {
+    /// Returns all the grids for `match_key` that touch the tile at `zoom`/`x`/`y`. The tile is
+    /// converted into a bbox at `store_zoom` (the zoom the store's grids were indexed at), so a
+    /// lower-zoom tile covers all of its children and a higher-zoom tile matches its one parent.
+    pub fn grids_in_tile(
+        &self,
+        match_key: &MatchKey,
+        zoom: u16,
+        x: u16,
+        y: u16,
+        store_zoom: u16,
+    ) -> Result<Vec<MatchEntry>, GridStoreError> {
+        let match_opts = MatchOpts { bbox: Some([x, y, x, y]), zoom, ..MatchOpts::default() }
+            .adjust_to_zoom(store_zoom);
+        Ok(self.streaming_get_matching(match_key, &match_opts, std::usize::MAX)?.collect())
+    }
+
+    /// Returns the union of the language sets stored for `phrase_id` across all of its keys, or
+    /// an empty set if the phrase isn't in the store at all.
+    pub fn phrase_languages(&self, phrase_id: u32) -> Result<LangSet, GridStoreError> {
+        let mut lang_set = LangSet::default();
+        let end = u64::from(phrase_id) + 1;
+        for (key, _) in self.phrase_range(TypeMarker::SinglePhrase, phrase_id, end) {
+            lang_set = lang_set.union(GridKey::from_bytes(&key)?.lang_set);
+        }
+        Ok(lang_set)
+    }
+
+    /// Walks every single-phrase key in the store, in the order of its `KeyOrdering`
+    pub fn keys<'i>(&'i self) -> impl Iterator<Item = Result<GridKey, GridStoreError>> + 'i {
         let db_iter = self.db.iterator(IteratorMode::Start);
-        db_iter.take_while(|(key, _)| key[0] == 0).map(|(key, _)| {
-            let phrase_id = (&key[1..]).read_u32::<BigEndian>()?;
+        db_iter
+            .take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key))
+            .map(|(key, _)| Ok(GridKey::from_bytes(&key)?))
+    }
 
-            let key_lang_partial = &key[5..];
-            let lang_set: u128 = if key_lang_partial.len() == 0 {
-                // 0-length language array is the shorthand for "matches everything"
-                std::u128::MAX
-            } else {
-                let mut key_lang_full = [0u8; 16];
-                key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
+    /// Like `keys`, but only yields the keys for phrase IDs in `[start_phrase, end_phrase)`,
+    /// seeking straight to the first of them rather than walking the whole store.
+    pub fn keys_in_range<'i>(
+        &'i self,
+        start_phrase: u32,
+        end_phrase: u32,
+    ) -> impl Iterator<Item = Result<GridKey, GridStoreError>> + 'i {
+        self.phrase_range(TypeMarker::SinglePhrase, start_phrase, end_phrase.into())
+            .map(|(key, _)| Ok(GridKey::from_bytes(&key)?))
+    }
+
+    /// Reads every key and value for phrase IDs in `[start_phrase, end_phrase)` so the blocks
+    /// holding them are pulled into rocksdb's block cache, e.g. to warm up hot phrases before a
+    /// store starts serving queries.
+    pub fn warm(&self, start_phrase: u32, end_phrase: u32) -> Result<(), GridStoreError> {
+        // the iterator copies each value out of rocksdb, which is what loads its block
+        for (key, _) in self.phrase_range(TypeMarker::SinglePhrase, start_phrase, end_phrase.into())
+        {
+            GridKey::from_bytes(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Compares the single-phrase records of two stores, walking both in key order, and
+    /// reports keys found in only one of them and keys whose grids differ. Prefix bin records
+    /// are built from the single-phrase ones, so they aren't compared separately.
+    pub fn diff(a: &GridStore, b: &GridStore) -> Result<Vec<Diff>, GridStoreError> {
+        if a.key_ordering() != b.key_ordering() {
+            let err = KeyOrderingError::Mismatch { a: a.key_ordering(), b: b.key_ordering() };
+            return Err(GridStoreError::Other(err.into()));
+        }
+        let mut a_records =
+            a.db.iterator(IteratorMode::Start)
+                .take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key))
+                .peekable();
+        let mut b_records =
+            b.db.iterator(IteratorMode::Start)
+                .take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key))
+                .peekable();
 
-                (&key_lang_full[..]).read_u128::<BigEndian>()?
+        let mut diffs = Vec::new();
+        loop {
+            // keys are compared the way both stores order them, which is the order rocksdb
+            // yields them in
+            let ordering = match (a_records.peek(), b_records.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((a_key, _)), Some((b_key, _))) => a.key_ordering().compare(a_key, b_key),
             };
+            match ordering {
+                Ordering::Less => {
+                    let (key, _) = a_records.next().unwrap();
+                    diffs.push(Diff::OnlyInFirst { key: GridKey::from_bytes(&key)? });
+                }
+                Ordering::Greater => {
+                    let (key, _) = b_records.next().unwrap();
+                    diffs.push(Diff::OnlyInSecond { key: GridKey::from_bytes(&key)? });
+                }
+                Ordering::Equal => {
+                    let (key, a_value) = a_records.next().unwrap();
+                    let (_, b_value) = b_records.next().unwrap();
+                    if a_value == b_value {
+                        continue;
+                    }
+                    let (only_in_first, only_in_second) = diff_entries(
+                        decode_value(a_value).collect(),
+                        decode_value(b_value).collect(),
+                    );
+                    // the same grids can be encoded differently, e.g. if ids were deduplicated
+                    if !only_in_first.is_empty() || !only_in_second.is_empty() {
+                        diffs.push(Diff::EntriesDiffer {
+                            key: GridKey::from_bytes(&key)?,
+                            only_in_first,
+                            only_in_second,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(diffs)
+    }
 
-            Ok(GridKey { phrase_id, lang_set })
-        })
+    /// Walks several stores at once in key order, merging them so that each key comes out once,
+    /// with the grids each store has for it as `(store index, grids)` pairs in store order.
+    /// Keys are ordered as `iter` orders them, which every store has to share: stores with
+    /// different key orderings yield just an error.
+    pub fn merge_iter<'a>(
+        stores: &[&'a GridStore],
+    ) -> impl Iterator<Item = Result<(GridKey, Vec<(usize, Vec<GridEntry>)>), GridStoreError>> + 'a
+    {
+        let key_ordering =
+            stores.first().map_or(KeyOrdering::default(), |store| store.key_ordering());
+        if let Some(store) = stores.iter().find(|store| store.key_ordering() != key_ordering) {
+            let err = KeyOrderingError::Mismatch { a: key_ordering, b: store.key_ordering() };
+            return MergeIter {
+                key_ordering,
+                iters: Vec::new(),
+                heads: Vec::new(),
+                heap: BinaryHeap::new(),
+                errors: vec![GridStoreError::Other(err.into())].into(),
+            };
+        }
+        let mut merge = MergeIter {
+            key_ordering,
+            iters: stores
+                .iter()
+                .map(|store| Box::new(store.iter()) as Box<dyn Iterator<Item = RecordResult>>)
+                .collect(),
+            heads: stores.iter().map(|_| None).collect(),
+            heap: BinaryHeap::with_capacity(stores.len()),
+            errors: VecDeque::new(),
+        };
+        for i in 0..stores.len() {
+            merge.advance(i);
+        }
+        merge
     }
 
     pub fn iter<'i>(
         &'i self,
-    ) -> impl Iterator<Item = Result<(GridKey, Vec<GridEntry>), Error>> + 'i {
+    ) -> impl Iterator<Item = Result<(GridKey, Vec<GridEntry>), GridStoreError>> + 'i {
         let db_iter = self.db.iterator(IteratorMode::Start);
-        db_iter.take_while(|(key, _)| key[0] == 0).map(|(key, value)| {
-            let phrase_id = (&key[1..]).read_u32::<BigEndian>()?;
+        db_iter.take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key)).map(|(key, value)| {
+            let grid_key = GridKey::from_bytes(&key)?;
+            let entries: Vec<_> = decode_value(value).collect();
+            Ok((grid_key, entries))
+        })
+    }
 
-            let key_lang_partial = &key[5..];
-            let lang_set: u128 = if key_lang_partial.len() == 0 {
-                // 0-length language array is the shorthand for "matches everything"
-                std::u128::MAX
-            } else {
-                let mut key_lang_full = [0u8; 16];
-                key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
+    /// Like `iter`, but yields one row per grid instead of collecting each key's grids into a
+    /// `Vec`, for consumers that handle the store a grid at a time.
+    pub fn entries_stream<'i>(
+        &'i self,
+    ) -> impl Iterator<Item = Result<(GridKey, GridEntry), GridStoreError>> + 'i {
+        let db_iter = self.db.iterator(IteratorMode::Start);
+        db_iter.take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key)).flat_map(
+            |(key, value)| match GridKey::from_bytes(&key) {
+                Ok(grid_key) => {
+                    Box::new(decode_value(value).map(move |entry| Ok((grid_key.clone(), entry))))
+                        as Box<dyn Iterator<Item = Result<(GridKey, GridEntry), GridStoreError>>>
+                }
+                Err(err) => Box::new(std::iter::once(Err(err.into()))),
+            },
+        )
+    }
+
+    /// Like `iter`, but walks the store in exactly the opposite order, so phrase IDs come out
+    /// from highest to lowest. Within a phrase, keys are ordered by the bytes of their language
+    /// suffix rather than by `lang_set`'s numeric value, and that's reversed too: the
+    /// all-languages key (which has no suffix) comes last, and since leading zero bytes are
+    /// stripped, `lang_set` 2 comes before 256 (`[1, 0]`), which comes before 1.
+    pub fn iter_rev<'i>(
+        &'i self,
+    ) -> impl Iterator<Item = Result<(GridKey, Vec<GridEntry>), GridStoreError>> + 'i {
+        // every single-phrase key sorts before the first possible prefix bin key
+        let db_iter = self
+            .db
+            .iterator(IteratorMode::From(&[TypeMarker::PrefixBin as u8], Direction::Reverse));
+        db_iter.take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key)).map(|(key, value)| {
+            let grid_key = GridKey::from_bytes(&key)?;
+            let entries: Vec<_> = decode_value(value).collect();
+            Ok((grid_key, entries))
+        })
+    }
+
+    /// Walks every phrase in the store, yielding the grids whose key matches `lang_set` (keys
+    /// stored for all languages always match) and that pass the bbox filter in `match_opts`. If
+    /// `match_opts` has a proximity point, distance and scoredist are filled in as they are for
+    /// `streaming_get_matching`.
+    pub fn iter_matching<'i>(
+        &'i self,
+        match_opts: &MatchOpts,
+        lang_set: LangSet,
+    ) -> impl Iterator<Item = Result<(GridKey, MatchEntry), GridStoreError>> + 'i {
+        let match_opts = match_opts.clone();
+        let db_iter = self.db.iterator(IteratorMode::Start);
+        db_iter.take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key)).flat_map(
+            move |(key, value)| {
+                let grid_key = match GridKey::from_bytes(&key) {
+                    Ok(grid_key) => grid_key,
+                    Err(e) => {
+                        return Box::new(std::iter::once(Err(e.into())))
+                            as Box<
+                                dyn Iterator<Item = Result<(GridKey, MatchEntry), GridStoreError>>,
+                            >
+                    }
+                };
+                if !grid_key.lang_set.intersects(lang_set) {
+                    return Box::new(std::iter::empty());
+                }
+                Box::new(
+                    decode_matching_value(value, &match_opts, true, 1., None)
+                        .map(move |entry| Ok((grid_key.clone(), entry))),
+                )
+            },
+        )
+    }
 
-                (&key_lang_full[..]).read_u128::<BigEndian>()?
+    /// Consumes the store and loads everything in it, bin boundaries included, into a new
+    /// builder that will write to `out_path`, so an index can be patched with `insert`, `append`
+    /// or `renumber` and written out again without a round trip through JSON. `out_path` should
+    /// be a fresh directory: anything already stored there is kept alongside what's written.
+    pub fn into_builder<P: AsRef<Path>>(
+        self,
+        out_path: P,
+    ) -> Result<GridStoreBuilder, GridStoreError> {
+        let mut builder = GridStoreBuilder::new(out_path)?;
+        builder.set_key_ordering(self.key_ordering());
+        for item in self.iter() {
+            let (key, entries) = item?;
+            builder.insert(&key, entries)?;
+        }
+        builder.load_bin_boundaries(self.bin_boundaries())?;
+        Ok(builder)
+    }
+
+    /// Writes a new store to `out_path` holding only the grids `pred` accepts, in one pass over
+    /// this store. Keys left with no grids are dropped. The bin boundaries are carried over and
+    /// the prefix bin records rebuilt from what's kept.
+    pub fn retain<P: AsRef<Path>, F: Fn(&GridKey, &GridEntry) -> bool>(
+        &self,
+        out_path: P,
+        pred: F,
+    ) -> Result<(), GridStoreError> {
+        let mut builder = GridStoreBuilder::new(out_path)?;
+        builder.set_key_ordering(self.key_ordering());
+        for item in self.iter() {
+            let (key, entries) = item?;
+            let entries: Vec<GridEntry> =
+                entries.into_iter().filter(|entry| pred(&key, entry)).collect();
+            if !entries.is_empty() {
+                builder.insert(&key, entries)?;
+            }
+        }
+        builder.load_bin_boundaries(self.bin_boundaries())?;
+        builder.finish()
+    }
+
+    /// Writes a new store to `out_path` with every key's languages renumbered: language `i`
+    /// becomes `mapping[i]`, and languages past the end of `mapping` keep their IDs. Keys stored
+    /// for all languages are carried over as they are. Keys that end up with the same languages
+    /// have their grids merged. Fails without writing anything if `mapping` has an ID past 127.
+    pub fn rename_languages<P: AsRef<Path>>(
+        &self,
+        mapping: &[u8],
+        out_path: P,
+    ) -> Result<(), GridStoreError> {
+        let out_of_range: Vec<u32> =
+            mapping.iter().filter(|lang| **lang >= 128).map(|lang| u32::from(*lang)).collect();
+        if !out_of_range.is_empty() {
+            return Err(GridStoreError::Other(
+                LangSetError::OutOfRange { ids: out_of_range }.into(),
+            ));
+        }
+
+        let mut builder = GridStoreBuilder::new(out_path)?;
+        builder.set_key_ordering(self.key_ordering());
+        for item in self.iter() {
+            let (key, entries) = item?;
+            let lang_set = if key.lang_set.is_all() {
+                key.lang_set
+            } else {
+                let mut renamed = LangSet::default();
+                for lang in (0..128u8).filter(|lang| key.lang_set.contains(*lang)) {
+                    renamed.insert(mapping.get(lang as usize).cloned().unwrap_or(lang));
+                }
+                renamed
             };
+            builder.append(&GridKey { lang_set, ..key }, entries)?;
+        }
+        builder.load_bin_boundaries(self.bin_boundaries())?;
+        builder.finish()
+    }
 
-            let entries: Vec<_> = decode_value(value).collect();
+    /// Finds the grid nearest to `point` across every phrase in the store whose key matches
+    /// `lang_set`, for reverse geocoding without a phrase. The returned entry's distance is filled
+    /// in; ties go to the first grid in store order. Returns `None` if nothing matches.
+    pub fn closest_feature(
+        &self,
+        point: [u16; 2],
+        lang_set: LangSet,
+    ) -> Result<Option<MatchEntry>, GridStoreError> {
+        let mut closest: Option<MatchEntry> = None;
+        for item in self.iter_matching(&MatchOpts::default(), lang_set) {
+            let (_, mut entry) = item?;
+            entry.distance =
+                spatial::tile_dist(point[0], point[1], entry.grid_entry.x, entry.grid_entry.y);
+            if closest.as_ref().map_or(true, |closest| entry.distance < closest.distance) {
+                closest = Some(entry);
+            }
+        }
+        Ok(closest)
+    }
+
+    /// Reports rocksdb's estimates of how much memory this store is using. Properties rocksdb
+    /// doesn't report are counted as zero.
+    pub fn memory_usage(&self) -> Result<MemoryUsage, GridStoreError> {
+        let property = |name: &str| -> Result<u64, Error> {
+            Ok(self.db.property_int_value(name)?.unwrap_or(0))
+        };
+        Ok(MemoryUsage {
+            table_readers: property("rocksdb.estimate-table-readers-mem")?,
+            block_cache: property("rocksdb.block-cache-usage")?,
+            mem_tables: property("rocksdb.size-all-mem-tables")?,
+        })
+    }
+
+    /// Returns the smallest bbox, as `[min x, min y, max x, max y]`, that holds every grid in the
+    /// store, or `None` if the store has no grids. This walks the whole store.
+    pub fn bbox_coverage(&self) -> Result<Option<[u16; 4]>, GridStoreError> {
+        let mut coverage: Option<[u16; 4]> = None;
+        for item in self.iter() {
+            let (_, entries) = item?;
+            for entry in entries {
+                let (x, y) = (entry.x, entry.y);
+                coverage = Some(match coverage {
+                    Some([min_x, min_y, max_x, max_y]) => {
+                        [min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)]
+                    }
+                    None => [x, y, x, y],
+                });
+            }
+        }
+        Ok(coverage)
+    }
+
+    /// Counts the store's grids by their score (0 to 15) in one pass. Only the lengths of each
+    /// score level's lists are read, so the grids themselves aren't decoded.
+    pub fn histogram_scores(&self) -> Result<[u64; 16], GridStoreError> {
+        let mut histogram = [0u64; 16];
+        let db_iter = self.db.iterator(IteratorMode::Start);
+        for (_, value) in db_iter.take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key)) {
+            let value: &[u8] = value.as_ref();
+            let reader = gridstore_format::Reader::new(value);
+            let record = gridstore_format::read_phrase_record_from(&reader);
+            for rs_obj in gridstore_format::read_var_vec_raw(value, record.relev_scores).into_iter()
+            {
+                // the score is the low four bits of the packed relev/score byte
+                let score = (rs_obj.relev_score & 15) as usize;
+                for coords_obj in
+                    gridstore_format::read_uniform_vec_raw(value, rs_obj.coords).into_iter()
+                {
+                    histogram[score] +=
+                        gridstore_format::read_fixed_vec_raw(value, coords_obj.ids).len() as u64;
+                }
+            }
+        }
+        Ok(histogram)
+    }
+
+    /// Cheaply estimates how many keys the store holds, from rocksdb's `estimate-num-keys`
+    /// property rather than a scan. It's only approximate, and counts every rocksdb key: the
+    /// prefix bin records and the bin boundaries metadata key as well as the phrase keys `keys`
+    /// yields. Returns 0 if rocksdb doesn't report the property.
+    pub fn estimate_keys(&self) -> Result<u64, GridStoreError> {
+        Ok(self.db.property_int_value("rocksdb.estimate-num-keys")?.unwrap_or(0))
+    }
+
+    /// Scans the whole store and reports structural problems: records whose relev/score groups,
+    /// coords or ids are out of order, relev or score values out of range, and prefix bin
+    /// boundaries beyond the last phrase. Ids are stored in 24 bits alongside the source phrase
+    /// hash, so they can't be out of range in a well-framed record. For a packed store, the file's
+    /// layout is checked first, and a malformed file is an error.
+    pub fn verify(&self) -> Result<Vec<VerifyIssue>, GridStoreError> {
+        self.db.verify()?;
+        let mut issues = Vec::new();
+        let mut max_phrase_id: Option<u32> = None;
+
+        let db_iter = self.db.iterator(IteratorMode::Start);
+        for (key, value) in db_iter.take_while(|(key, _)| TypeMarker::SinglePhrase.marks(key)) {
+            let grid_key = GridKey::from_bytes(&key)?;
+            max_phrase_id = max_phrase_id.max(Some(grid_key.phrase_id));
+            verify_value(&grid_key, &value, &mut issues);
+        }
 
-            Ok((GridKey { phrase_id, lang_set }, entries))
+        self.verify_bin_boundaries(max_phrase_id, &mut issues);
+        Ok(issues)
+    }
+
+    /// Like `verify`, but splits the phrase ID space into `threads` contiguous ranges and scans
+    /// each on its own thread with its own rocksdb iterator. Issues are reported in the same
+    /// order `verify` reports them.
+    pub fn verify_parallel(&self, threads: usize) -> Result<Vec<VerifyIssue>, GridStoreError> {
+        self.db.verify()?;
+        let (first_phrase_id, last_phrase_id) = match self.key_ordering() {
+            KeyOrdering::PhraseFirst => {
+                let first = self.keys().next().transpose()?.map(|key| key.phrase_id);
+                let last = self.iter_rev().next().transpose()?.map(|(key, _)| key.phrase_id);
+                (first, last)
+            }
+            // each language set runs through its own phrases, so the bounds take a full scan
+            KeyOrdering::LanguageFirst => {
+                let (mut first, mut last) = (None, None);
+                for key in self.keys() {
+                    let phrase_id = key?.phrase_id;
+                    first = Some(first.map_or(phrase_id, |id: u32| id.min(phrase_id)));
+                    last = last.max(Some(phrase_id));
+                }
+                (first, last)
+            }
+        };
+        let (first_phrase_id, last_phrase_id) = match (first_phrase_id, last_phrase_id) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                let mut issues = Vec::new();
+                self.verify_bin_boundaries(None, &mut issues);
+                return Ok(issues);
+            }
+        };
+
+        // ranges are half-open, so do the arithmetic in u64 in case the last ID is u32::MAX
+        let span = u64::from(last_phrase_id) - u64::from(first_phrase_id) + 1;
+        let chunk = (span + threads.max(1) as u64 - 1) / threads.max(1) as u64;
+        let ranges: Vec<(u64, u64)> = (0..threads.max(1) as u64)
+            .map(|i| {
+                let start = u64::from(first_phrase_id) + i * chunk;
+                (start, (start + chunk).min(u64::from(last_phrase_id) + 1))
+            })
+            .filter(|(start, end)| start < end)
+            .collect();
+
+        let results: Vec<Result<Vec<VerifyIssue>, Error>> = crossbeam_utils::thread::scope(|s| {
+            let handles: Vec<_> = ranges
+                .iter()
+                .map(|&(start, end)| s.spawn(move |_| self.verify_phrase_range(start, end)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("verify thread panicked"))
+                .collect()
         })
+        .expect("verify thread panicked");
+
+        let mut issues = Vec::new();
+        for result in results {
+            issues.extend(result?);
+        }
+        self.verify_bin_boundaries(Some(last_phrase_id), &mut issues);
+        Ok(issues)
+    }
+
+    /// Runs the per-record checks of `verify` over the phrase IDs in `[start, end)`
+    fn verify_phrase_range(&self, start: u64, end: u64) -> Result<Vec<VerifyIssue>, Error> {
+        let mut issues = Vec::new();
+        for (key, value) in self.phrase_range(TypeMarker::SinglePhrase, start as u32, end) {
+            let grid_key = GridKey::from_bytes(&key)?;
+            verify_value(&grid_key, &value, &mut issues);
+        }
+        Ok(issues)
+    }
+
+    fn verify_bin_boundaries(&self, max_phrase_id: Option<u32>, issues: &mut Vec<VerifyIssue>) {
+        // the last boundary is the exclusive end of the last bin; compare in u64 so a store
+        // holding phrase u32::MAX doesn't overflow
+        let mut boundaries: Vec<u32> = self.bin_boundaries.iter().cloned().collect();
+        boundaries.sort();
+        for boundary in boundaries {
+            if max_phrase_id.map_or(true, |max_id| u64::from(boundary) > u64::from(max_id) + 1) {
+                issues.push(VerifyIssue::BinBoundaryOutOfRange { boundary, max_phrase_id });
+            }
+        }
+    }
+
+    /// Returns a uniform random sample of up to `n` grids from across the whole store, using
+    /// reservoir sampling so that only `n` entries are held in memory at a time. The same `seed`
+    /// always produces the same sample. If the store has fewer than `n` grids, all of them are
+    /// returned in store order.
+    pub fn sample(&self, n: usize, seed: u64) -> Result<Vec<(GridKey, GridEntry)>, GridStoreError> {
+        let mut rng = SplitMix64(seed);
+        // `n` can be far bigger than the store, so only pre-size for a modest sample and let
+        // the reservoir grow as it fills
+        let mut reservoir: Vec<(GridKey, GridEntry)> = Vec::with_capacity(n.min(1024));
+        let mut seen: u64 = 0;
+        for item in self.iter() {
+            let (key, entries) = item?;
+            for entry in entries {
+                if reservoir.len() < n {
+                    reservoir.push((key.clone(), entry));
+                } else {
+                    let slot = (rng.next_u64() % (seen + 1)) as usize;
+                    if slot < n {
+                        reservoir[slot] = (key.clone(), entry);
+                    }
+                }
+                seen += 1;
+            }
+        }
+        Ok(reservoir)
     }
 }
@@ -1 +1,2 @@
 pub mod gridstore;
+pub mod tile;
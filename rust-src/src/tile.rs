@@ -0,0 +1,37 @@
+// Conversions between tile x/y coordinates and the morton-order coords grids are stored by.
+
+use morton::{deinterleave_morton, interleave_morton};
+
+/// Interleaves a tile's x and y into a single morton-order coord
+#[inline]
+pub fn xy_to_coord(x: u16, y: u16) -> u32 {
+    interleave_morton(x, y)
+}
+
+/// Splits a morton-order coord back into the tile's x and y
+#[inline]
+pub fn coord_to_xy(coord: u32) -> (u16, u16) {
+    deinterleave_morton(coord)
+}
+
+#[test]
+fn xy_to_coord_test() {
+    assert_eq!(xy_to_coord(0, 0), 0);
+    assert_eq!(xy_to_coord(1, 0), 1, "x is in the even bits");
+    assert_eq!(xy_to_coord(0, 1), 2, "y is in the odd bits");
+    assert_eq!(xy_to_coord(1, 1), 3);
+    assert_eq!(xy_to_coord(std::u16::MAX, std::u16::MAX), std::u32::MAX);
+}
+
+#[test]
+fn coord_round_trip_test() {
+    for &(x, y) in
+        [(0, 0), (1, 2), (26, 1), (958, 1660), (65535, 0), (0, 65535), (65535, 65535)].iter()
+    {
+        assert_eq!(coord_to_xy(xy_to_coord(x, y)), (x, y), "{},{} round-trips", x, y);
+    }
+    for coord in (0..std::u32::MAX).step_by(65_537) {
+        let (x, y) = coord_to_xy(coord);
+        assert_eq!(xy_to_coord(x, y), coord, "{} round-trips", coord);
+    }
+}
@@ -5,7 +5,7 @@ extern crate serde_json;
 
 use carmen_core::gridstore::*;
 
-use failure::Error;
+use failure::{Error, Fail};
 use lz4::Decoder;
 use rusoto_core::Region;
 use rusoto_s3::{GetObjectRequest, S3Client, S3};
@@ -17,6 +17,7 @@ use std::fs::{self, File};
 use std::io::{self, BufRead, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // Util functions for tests and benchmarks
 
@@ -26,15 +27,6 @@ pub fn round(value: f64, digits: i32) -> f64 {
     (value * multiplier).round() / multiplier
 }
 
-/// Convert an array of language ids into the langfield to use for GridKey or MatchKey
-pub fn langarray_to_langfield(array: &[u32]) -> u128 {
-    let mut out = 0u128;
-    for lang in array {
-        out = out | (1 << *lang as usize);
-    }
-    out
-}
-
 /// Mapping of GridKey to all of the grid entries to insert into a store for that GridKey
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StoreEntryBuildingBlock {
@@ -81,34 +73,127 @@ pub fn load_db_from_json(json_path: &str, store_path: &str) {
 }
 
 fn load_db_from_json_reader<T: BufRead>(json_source: T, split_source: Option<T>, store_path: &str) {
+    load_db_from_json_reader_with(json_source, split_source, store_path, LoadOptions::default())
+        .expect("Error loading store");
+}
+
+/// What `load_db_from_json_reader_with` does with a grid line it can't deserialize
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MalformedLines {
+    /// Count the line as skipped and carry on
+    Skip,
+    /// Stop the load with a `LoadError::MalformedLine`
+    Error,
+}
+
+/// How far a load has got
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LoadProgress {
+    pub lines_read: usize,
+    pub records_loaded: usize,
+    pub lines_skipped: usize,
+}
+
+pub struct LoadOptions<'a> {
+    pub malformed_lines: MalformedLines,
+    /// Called every `progress_interval` lines, and once more when the load is done
+    pub progress: Option<&'a mut dyn FnMut(LoadProgress)>,
+    pub progress_interval: usize,
+    /// Checked before each line; once it's set the load stops with `LoadError::Cancelled`
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> Default for LoadOptions<'a> {
+    fn default() -> Self {
+        LoadOptions {
+            malformed_lines: MalformedLines::Error,
+            progress: None,
+            progress_interval: 10_000,
+            cancel: None,
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum LoadError {
+    #[fail(display = "malformed grid record on line {}: {}", line, reason)]
+    MalformedLine { line: usize, reason: String },
+    #[fail(display = "load cancelled after {} lines", lines_read)]
+    Cancelled { lines_read: usize },
+}
+
+/// Like `load_db_from_json`, but reads from any reader, and reports progress, handles malformed
+/// grid lines per `opts.malformed_lines` and can be cancelled, rather than panicking on the
+/// first problem. A cancelled or failed load leaves whatever it had written at `store_path`
+/// without finishing the store. Malformed splits are always an error.
+pub fn load_db_from_json_reader_with<T: BufRead>(
+    json_source: T,
+    split_source: Option<T>,
+    store_path: &str,
+    mut opts: LoadOptions,
+) -> Result<LoadProgress, Error> {
     // Set up new gridstore
     let directory = Path::new(store_path);
-    let mut builder = GridStoreBuilder::new(directory).unwrap();
-    json_source.lines().for_each(|l| {
-        let record = l.unwrap();
+    let mut builder = GridStoreBuilder::new(directory)?;
+    let mut progress = LoadProgress::default();
+    let progress_interval = opts.progress_interval.max(1);
+
+    for line in json_source.lines() {
+        if opts.cancel.map_or(false, |cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(LoadError::Cancelled { lines_read: progress.lines_read }.into());
+        }
+        let record = line?;
+        progress.lines_read += 1;
         if !record.is_empty() {
-            let deserialized: StoreEntryBuildingBlock =
-                serde_json::from_str(&record).expect("Error deserializing json from string");
-            builder.insert(&deserialized.grid_key, deserialized.entries).expect("Unable to insert");
+            match serde_json::from_str::<StoreEntryBuildingBlock>(&record) {
+                Ok(deserialized) => {
+                    builder.insert(&deserialized.grid_key, deserialized.entries)?;
+                    progress.records_loaded += 1;
+                }
+                Err(err) => match opts.malformed_lines {
+                    MalformedLines::Skip => progress.lines_skipped += 1,
+                    MalformedLines::Error => {
+                        return Err(LoadError::MalformedLine {
+                            line: progress.lines_read,
+                            reason: err.to_string(),
+                        }
+                        .into())
+                    }
+                },
+            }
         }
-    });
-
-    if let Some(splits) = split_source {
-        let boundary_records: Vec<PrefixBoundary> = splits
-            .lines()
-            .map(|l| {
-                serde_json::from_str(&l.unwrap()).expect("Error deserializing json from string")
-            })
-            .collect();
-
-        if boundary_records.len() > 0 {
-            let mut boundaries: Vec<u32> = boundary_records.iter().map(|r| r.first).collect();
-            boundaries.push(boundary_records.last().unwrap().last + 1);
-            builder.load_bin_boundaries(boundaries).unwrap();
+        if progress.lines_read % progress_interval == 0 {
+            if let Some(report) = opts.progress.as_mut() {
+                report(progress);
+            }
         }
     }
 
-    builder.finish().unwrap();
+    if let Some(mut splits) = split_source {
+        // binary splits start with a magic byte that can't begin a JSON line
+        if splits.fill_buf()?.first() == Some(&BINARY_BOUNDARIES_MAGIC) {
+            let mut data: Vec<u8> = Vec::new();
+            splits.read_to_end(&mut data)?;
+            builder.load_bin_boundaries_binary(&data)?;
+        } else {
+            let mut boundary_records: Vec<PrefixBoundary> = Vec::new();
+            for line in splits.lines() {
+                boundary_records.push(serde_json::from_str(&line?)?);
+            }
+
+            if boundary_records.len() > 0 {
+                let mut boundaries: Vec<u32> = boundary_records.iter().map(|r| r.first).collect();
+                boundaries.push(boundary_records.last().unwrap().last + 1);
+                builder.load_bin_boundaries(boundaries)?;
+            }
+        }
+    }
+
+    builder.finish()?;
+    if let Some(report) = opts.progress.as_mut() {
+        report(progress);
+    }
+    Ok(progress)
 }
 
 /// Takes an absolute path (in string form) to a rocksdb dir, and an absolute path for the output file,
@@ -127,6 +212,14 @@ pub fn dump_db_to_json(store_path: &str, json_path: &str) {
     }
 }
 
+/// Takes an absolute path (in string form) to a rocksdb dir, and an absolute path for the output
+/// file, and writes the store's prefix bin boundaries to the file in the compact binary format
+pub fn dump_bin_boundaries(store_path: &str, output_path: &str) {
+    let reader = GridStore::new(store_path).unwrap();
+    let encoded = encode_bin_boundaries(&reader.bin_boundaries()).unwrap();
+    File::create(output_path).unwrap().write_all(&encoded).unwrap();
+}
+
 pub fn ensure_downloaded(datafile: &str) -> PathBuf {
     let tmp = std::env::temp_dir().join("carmen_core_data/downloads");
     std::fs::create_dir_all(&tmp).unwrap();
@@ -222,6 +315,7 @@ pub fn prepare_coalesce_stacks(
                             idx: placeholder.idx,
                             zoom: placeholder.zoom,
                             mask: placeholder.mask,
+                            radius_override: None,
                         }
                     })
                     .collect();
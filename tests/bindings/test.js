@@ -22,6 +22,11 @@ tape('GridStoreBuilder insert()', (t) => {
     const builder = new addon.GridStoreBuilder(tmpDir.name);
     t.throws(() => builder.insert(), 'not enough arguments');
     t.throws(() => builder.insert({}), 'not enough arguments');
+    const entries = [{ id: 0, x: 0, y: 0, relev: 0.5, score: 2, source_phrase_hash: 0 }];
+    t.throws(() => builder.insert({ phrase_id: -1, lang_set: [0] }, entries), TypeError, 'negative phrase_id');
+    t.throws(() => builder.insert({ phrase_id: 1.5, lang_set: [0] }, entries), TypeError, 'fractional phrase_id');
+    t.throws(() => builder.insert({ phrase_id: 2 ** 32, lang_set: [0] }, entries), TypeError, 'phrase_id past u32 range');
+    t.throws(() => builder.insert({ phrase_id: 0, lang_set: [0.5] }, entries), TypeError, 'fractional language id');
     builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.5, score: 2, source_phrase_hash: 0 }]);
     builder.insert({ phrase_id: 1, lang_set: [0, 1, 2, 3] }, [{ id: 2, x: 2, y: 2, relev: 0.6, score: 3, source_phrase_hash: 0 }]);
     builder.finish();
@@ -53,6 +58,7 @@ tape('GridStoreBuilder compactAppend()', (t) => {
     const builder = new addon.GridStoreBuilder(tmpDir.name);
     builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 1, source_phrase_hash: 0 }]);
     builder.compactAppend({ phrase_id: 0, lang_set: [0] }, 1, 1, 0, 0, [[1, 1]]);
+    t.throws(() => builder.compactAppend({ phrase_id: 0, lang_set: [0] }, 1, 1, -3, 0, [[1, 1]]), TypeError, 'negative id');
     builder.finish();
 
     const reader = new addon.GridStore(tmpDir.name);
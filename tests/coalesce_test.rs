@@ -1,14 +1,37 @@
 use carmen_core::gridstore::*;
 use test_utils::*;
 
-const ALL_LANGUAGES: u128 = u128::max_value();
+/// A subquery matching `phrase_id` exactly in language set 1, with no radius override
+fn exact_subquery(
+    store: &GridStore,
+    phrase_id: u32,
+    weight: f64,
+    idx: u16,
+    zoom: u16,
+    mask: u32,
+) -> PhrasematchSubquery<&GridStore> {
+    PhrasematchSubquery {
+        store,
+        weight,
+        match_key: MatchKey {
+            match_phrase: MatchPhrase::Exact(phrase_id),
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        },
+        idx,
+        zoom,
+        mask,
+        radius_override: None,
+    }
+}
 
 #[test]
 fn coalesce_single_test_proximity_quadrants() {
     let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
     let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
     let entries = vec![
         GridEntry { id: 1, x: 200, y: 200, relev: 1., score: 1, source_phrase_hash: 0 }, // ne
@@ -24,10 +47,16 @@ fn coalesce_single_test_proximity_quadrants() {
     let subquery = PhrasematchSubquery {
         store: &store,
         weight: 1.,
-        match_key: MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 },
+        match_key: MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        },
         idx: 1,
         zoom: 14,
         mask: 1 << 0,
+        radius_override: None,
     };
     let stack = vec![subquery];
 
@@ -93,7 +122,7 @@ fn coalesce_single_test_proximity_basic() {
     let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
     let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
     let entries = vec![
         GridEntry { id: 1, x: 2, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -109,10 +138,16 @@ fn coalesce_single_test_proximity_basic() {
     let subquery = PhrasematchSubquery {
         store: &store,
         weight: 1.,
-        match_key: MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 },
+        match_key: MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        },
         idx: 1,
         zoom: 14,
         mask: 1 << 0,
+        radius_override: None,
     };
     let stack = vec![subquery];
     let match_opts = MatchOpts {
@@ -138,12 +173,54 @@ fn coalesce_single_test_proximity_basic() {
     );
 }
 
+#[test]
+fn coalesce_single_test_proximity_points() {
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 10, y: 10, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 100, y: 100, relev: 1., score: 1, source_phrase_hash: 0 },
+        ],
+    }]);
+    let subquery = exact_subquery(&store, 1, 1., 1, 14, 1 << 0);
+    let coalesce_with = |points: Vec<([u16; 2], f64)>, combine| {
+        let match_opts = MatchOpts {
+            zoom: 14,
+            proximity: Some(Proximity { point: [10, 10], radius: 40. }),
+            proximity_points: Some(points),
+            proximity_combine: combine,
+            ..MatchOpts::default()
+        };
+        coalesce(vec![subquery.clone()], &match_opts).unwrap()
+    };
+    let ids = |result: &[CoalesceContext]| -> Vec<u32> {
+        result.iter().map(|context| context.entries[0].grid_entry.id).collect()
+    };
+
+    let result = coalesce_with(vec![([10, 10], 1.), ([100, 100], 0.2)], ProximityCombine::Max);
+    assert_eq!(ids(&result), [1, 2], "the grid by the heavier point comes first");
+    assert_eq!(result[0].entries[0].distance, 0., "distance is to the nearest point");
+    assert_eq!(result[1].entries[0].distance, 0., "for each grid");
+
+    let result = coalesce_with(vec![([10, 10], 0.2), ([100, 100], 1.)], ProximityCombine::Max);
+    assert_eq!(ids(&result), [2, 1], "reweighting the points flips the order");
+    let max_scoredists: Vec<f64> =
+        result.iter().map(|context| context.entries[0].scoredist).collect();
+
+    let result =
+        coalesce_with(vec![([10, 10], 0.2), ([100, 100], 1.)], ProximityCombine::WeightedMean);
+    assert_eq!(ids(&result), [2, 1]);
+    for (context, max_scoredist) in result.iter().zip(max_scoredists) {
+        assert!(context.entries[0].scoredist < max_scoredist, "a mean is at most the max");
+    }
+}
+
 #[test]
 fn coalesce_single_test_language_penalty() {
     let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
     let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
 
-    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let key = GridKey { phrase_id: 1, lang_set: LangSet::from(1) };
 
     let entries = vec![
         GridEntry { id: 1, x: 2, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -158,10 +235,16 @@ fn coalesce_single_test_language_penalty() {
     let subquery = PhrasematchSubquery {
         store: &store,
         weight: 1.,
-        match_key: MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 2 },
+        match_key: MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(2),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        },
         idx: 1,
         zoom: 14,
         mask: 1 << 0,
+        radius_override: None,
     };
     let stack = vec![subquery.clone()];
     let match_opts = MatchOpts {
@@ -188,13 +271,27 @@ fn coalesce_single_test_language_penalty() {
         assert_eq!(result[0].entries[0].grid_entry.relev, 0.96, "With no proximity, cross language grids get a penalty");
         assert_eq!(result[0].entries[0].matches_language, false, "Matches language property is correctly set on CoalesceEntry");
     }
+    let match_opts = MatchOpts {
+        zoom: 14,
+        proximity: Some(Proximity { point: [2, 2], radius: 1. }),
+        language_penalty_inside_radius: true,
+        ..MatchOpts::default()
+    };
+    let result = coalesce(stack.clone(), &match_opts).unwrap();
+    #[cfg_attr(rustfmt, rustfmt::skip)]
+    {
+        assert_eq!(result[0].entries[0].grid_entry.id, 1, "The grid inside the proximity radius still comes first");
+        assert_eq!(result[0].relev, 0.96, "With language_penalty_inside_radius, contexts inside the proximity radius get a cross language penalty");
+        assert_eq!(result[0].entries[0].grid_entry.relev, 0.96, "With language_penalty_inside_radius, grids inside the proximity radius get a cross language penalty");
+        assert_eq!(result[1].relev, 0.96, "Contexts outside the proximity radius are penalized as before");
+    }
 }
 
 #[test]
 fn coalesce_multi_test_language_penalty() {
     // Add more specific layer into a store
     let store1 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
         entries: vec![
             GridEntry { id: 1, x: 2, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
             GridEntry { id: 2, x: 12800, y: 12800, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -203,7 +300,7 @@ fn coalesce_multi_test_language_penalty() {
 
     // Add less specific layer into a store
     let store2 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 2, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
         entries: vec![
             GridEntry { id: 3, x: 0, y: 0, relev: 1., score: 1, source_phrase_hash: 0 },
             GridEntry { id: 4, x: 50, y: 50, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -218,22 +315,28 @@ fn coalesce_multi_test_language_penalty() {
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 2,
+                lang_set: LangSet::from(2),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 14,
             mask: 1 << 0,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store2,
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 2,
+                lang_set: LangSet::from(2),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 2,
             zoom: 6,
             mask: 1 << 1,
+            radius_override: None,
         },
     ];
 
@@ -268,7 +371,7 @@ fn coalesce_multi_test_language_penalty() {
 #[test]
 fn coalesce_single_test() {
     let store = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
         entries: vec![
             GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 3, source_phrase_hash: 0 },
             GridEntry { id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 },
@@ -278,10 +381,16 @@ fn coalesce_single_test() {
     let subquery = PhrasematchSubquery {
         store: &store,
         weight: 1.,
-        match_key: MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 },
+        match_key: MatchKey {
+            match_phrase: MatchPhrase::Range { start: 1, end: 3 },
+            lang_set: LangSet::from(1),
+            lang_fallbacks: vec![],
+            ignore_language: false,
+        },
         idx: 1,
         zoom: 6,
         mask: 1 << 0,
+        radius_override: None,
     };
     let stack = vec![subquery];
 
@@ -463,6 +572,7 @@ fn coalesce_single_test() {
         zoom: 6,
         bbox: Some([1, 1, 1, 1]),
         proximity: Some(Proximity { point: [1, 1], radius: 40. }),
+        ..MatchOpts::default()
     };
     let result = coalesce(stack.clone(), &match_opts).unwrap();
     assert_eq!(result[0].entries.len(), 1, "Only one result is within the bbox");
@@ -500,7 +610,7 @@ fn coalesce_single_languages_test() {
     let lang_sets: [Vec<u32>; 4] = [vec![0], vec![1], vec![0, 1], vec![2]];
     // Load each grid_entry with a grid key for each language
     for (i, langs) in lang_sets.iter().enumerate() {
-        let lang_set = langarray_to_langfield(&langs[..]);
+        let lang_set = LangSet::from(&langs[..]);
         let key = GridKey { phrase_id: 1, lang_set };
         let grid_entry =
             GridEntry { id: i as u32, x: 1, y: 1, relev: 1., score: 0, source_phrase_hash: 0 };
@@ -517,10 +627,13 @@ fn coalesce_single_languages_test() {
         match_key: MatchKey {
             match_phrase: MatchPhrase::Range { start: 1, end: 3 },
             lang_set: ALL_LANGUAGES,
+            lang_fallbacks: vec![],
+            ignore_language: false,
         },
         idx: 0,
         zoom: 6,
         mask: 1 << 0,
+        radius_override: None,
     };
     let stack = vec![subquery];
     let match_opts = MatchOpts { zoom: 6, ..MatchOpts::default() };
@@ -554,11 +667,14 @@ fn coalesce_single_languages_test() {
         weight: 1.,
         match_key: MatchKey {
             match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-            lang_set: langarray_to_langfield(&[0]),
+            lang_set: LangSet::from(&[0][..]),
+            lang_fallbacks: vec![],
+            ignore_language: false,
         },
         idx: 0,
         zoom: 6,
         mask: 1 << 0,
+        radius_override: None,
     };
     let stack = vec![subquery];
     let match_opts = MatchOpts { zoom: 6, ..MatchOpts::default() };
@@ -592,11 +708,14 @@ fn coalesce_single_languages_test() {
         weight: 1.,
         match_key: MatchKey {
             match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-            lang_set: langarray_to_langfield(&[3]),
+            lang_set: LangSet::from(&[3][..]),
+            lang_fallbacks: vec![],
+            ignore_language: false,
         },
         idx: 0,
         zoom: 6,
         mask: 1 << 0,
+        radius_override: None,
     };
     let stack = vec![subquery];
     let match_opts = MatchOpts { zoom: 6, ..MatchOpts::default() };
@@ -628,7 +747,7 @@ fn coalesce_single_languages_test() {
 fn coalesce_multi_test() {
     // Add more specific layer into a store
     let store1 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
         entries: vec![
             GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
             // TODO: this isn't a real tile at zoom 1. Maybe pick more realistic test case?
@@ -637,7 +756,7 @@ fn coalesce_multi_test() {
     }]);
 
     let store2 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 2, lang_set: 1 },
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
         entries: vec![
             GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 3, source_phrase_hash: 0 },
             GridEntry { id: 2, x: 2, y: 2, relev: 1., score: 3, source_phrase_hash: 0 },
@@ -651,22 +770,28 @@ fn coalesce_multi_test() {
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 1,
+                lang_set: LangSet::from(1),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 0,
             zoom: 1,
             mask: 1 << 1,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store2,
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 1,
+                lang_set: LangSet::from(1),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 2,
             mask: 1 << 0,
+            radius_override: None,
         },
     ];
 
@@ -859,7 +984,7 @@ fn coalesce_multi_test() {
 fn coalesce_multi_languages_test() {
     // Store 1 with grids in all languages
     let store1 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: ALL_LANGUAGES },
+        grid_key: GridKey::all_languages(1),
         entries: vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
     }]);
 
@@ -867,7 +992,7 @@ fn coalesce_multi_languages_test() {
     let store2 = create_store(vec![
         // Insert grid with lang_set 1
         StoreEntryBuildingBlock {
-            grid_key: GridKey { phrase_id: 2, lang_set: langarray_to_langfield(&[1]) },
+            grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(&[1][..]) },
             entries: vec![GridEntry {
                 id: 2,
                 x: 1,
@@ -879,7 +1004,7 @@ fn coalesce_multi_languages_test() {
         },
         // Insert grid with lang_set 0
         StoreEntryBuildingBlock {
-            grid_key: GridKey { phrase_id: 2, lang_set: langarray_to_langfield(&[0]) },
+            grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(&[0][..]) },
             entries: vec![GridEntry {
                 id: 3,
                 x: 1,
@@ -900,10 +1025,13 @@ fn coalesce_multi_languages_test() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 0,
             zoom: 1,
             mask: 1 << 1,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store2,
@@ -911,11 +1039,14 @@ fn coalesce_multi_languages_test() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             // TODO: when would these have the same zoom?
             zoom: 1,
             mask: 1 << 0,
+            radius_override: None,
         },
     ];
     let match_opts = MatchOpts { zoom: 6, ..MatchOpts::default() };
@@ -950,22 +1081,28 @@ fn coalesce_multi_languages_test() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 0,
             zoom: 1,
             mask: 1 << 1,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store2,
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: langarray_to_langfield(&[0]),
+                lang_set: LangSet::from(&[0][..]),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             // TODO: when would these have the same zoom?
             zoom: 1,
             mask: 1 << 0,
+            radius_override: None,
         },
     ];
     let match_opts = MatchOpts { zoom: 6, ..MatchOpts::default() };
@@ -1000,22 +1137,28 @@ fn coalesce_multi_languages_test() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 0,
             zoom: 1,
             mask: 1 << 1,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store2,
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: langarray_to_langfield(&[3]),
+                lang_set: LangSet::from(&[3][..]),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             // TODO: when would these have the same zoom?
             zoom: 1,
             mask: 1 << 0,
+            radius_override: None,
         },
     ];
     let match_opts = MatchOpts { zoom: 6, ..MatchOpts::default() };
@@ -1046,13 +1189,13 @@ fn coalesce_multi_languages_test() {
 fn coalesce_multi_scoredist() {
     // Add more specific layer into a store
     let store1 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: 0 },
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(0) },
         entries: vec![GridEntry { id: 1, x: 0, y: 0, relev: 1., score: 1, source_phrase_hash: 0 }],
     }]);
 
     // Add less specific layer into a store
     let store2 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 2, lang_set: 0 },
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(0) },
         entries: vec![
             GridEntry { id: 2, x: 4800, y: 6200, relev: 1., score: 7, source_phrase_hash: 0 },
             GridEntry { id: 3, x: 4600, y: 6200, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -1065,22 +1208,28 @@ fn coalesce_multi_scoredist() {
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 0,
+                lang_set: LangSet::from(0),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 0,
             zoom: 0,
             mask: 1 << 1,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store2,
             weight: 0.5,
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
-                lang_set: 0,
+                lang_set: LangSet::from(0),
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 14,
             mask: 1 << 0,
+            radius_override: None,
         },
     ];
     // Closer proximity to one grid
@@ -1120,14 +1269,14 @@ fn coalesce_multi_scoredist() {
 #[test]
 fn coalesce_multi_test_bbox() {
     let store1 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 1, lang_set: ALL_LANGUAGES },
+        grid_key: GridKey::all_languages(1),
         entries: vec![
             GridEntry { id: 1, x: 0, y: 0, relev: 0.8, score: 1, source_phrase_hash: 0 },
             GridEntry { id: 2, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
         ],
     }]);
     let store2 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 2, lang_set: ALL_LANGUAGES },
+        grid_key: GridKey::all_languages(2),
         entries: vec![
             GridEntry { id: 3, x: 3, y: 0, relev: 1., score: 1, source_phrase_hash: 0 },
             GridEntry { id: 4, x: 0, y: 3, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -1135,7 +1284,7 @@ fn coalesce_multi_test_bbox() {
     }]);
 
     let store3 = create_store(vec![StoreEntryBuildingBlock {
-        grid_key: GridKey { phrase_id: 3, lang_set: ALL_LANGUAGES },
+        grid_key: GridKey::all_languages(3),
         entries: vec![
             GridEntry { id: 5, x: 21, y: 7, relev: 1., score: 1, source_phrase_hash: 0 },
             GridEntry { id: 6, x: 21, y: 18, relev: 1., score: 1, source_phrase_hash: 0 },
@@ -1149,10 +1298,13 @@ fn coalesce_multi_test_bbox() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 0,
             zoom: 1,
             mask: 1 << 1,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store2,
@@ -1160,10 +1312,13 @@ fn coalesce_multi_test_bbox() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 3 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 2,
             mask: 1 << 0,
+            radius_override: None,
         },
     ];
     // Test bbox at zoom 1 that should contain 2 grids
@@ -1222,10 +1377,13 @@ fn coalesce_multi_test_bbox() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 4 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 1,
             zoom: 2,
             mask: 1 << 1,
+            radius_override: None,
         },
         PhrasematchSubquery {
             store: &store3,
@@ -1233,10 +1391,13 @@ fn coalesce_multi_test_bbox() {
             match_key: MatchKey {
                 match_phrase: MatchPhrase::Range { start: 1, end: 4 },
                 lang_set: ALL_LANGUAGES,
+                lang_fallbacks: vec![],
+                ignore_language: false,
             },
             idx: 2,
             zoom: 5,
             mask: 1 << 0,
+            radius_override: None,
         },
     ];
     let match_opts = MatchOpts { zoom: 1, bbox: Some([0, 0, 1, 0]), ..MatchOpts::default() };
@@ -1256,3 +1417,725 @@ fn coalesce_multi_test_bbox() {
 
 // TODO: add proximity test with max score
 // TODO: add sort tests?
+
+#[test]
+fn coalesce_tiebreak_order_test() {
+    // Equal-relevance grids should come out in the same order from the single and multi paths
+    let entries = vec![
+        GridEntry { id: 1, x: 2, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
+        GridEntry { id: 2, x: 3, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
+        GridEntry { id: 3, x: 2, y: 3, relev: 1., score: 1, source_phrase_hash: 0 },
+        GridEntry { id: 4, x: 3, y: 3, relev: 1., score: 1, source_phrase_hash: 0 },
+    ];
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 10, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: entries.clone(),
+    }]);
+
+    let single_stack = vec![exact_subquery(&store2, 2, 1., 1, 2, 1 << 0)];
+    let multi_stack = vec![
+        exact_subquery(&store1, 1, 0.5, 0, 1, 1 << 1),
+        exact_subquery(&store2, 2, 0.5, 1, 2, 1 << 0),
+    ];
+    let match_opts = MatchOpts { zoom: 2, ..MatchOpts::default() };
+
+    let single_result = coalesce(single_stack, &match_opts).unwrap();
+    let multi_result = coalesce(multi_stack, &match_opts).unwrap();
+
+    for result in [&single_result, &multi_result].iter() {
+        let ids: Vec<u32> = result.iter().map(|context| context.entries[0].grid_entry.id).collect();
+        assert_eq!(ids, [4, 2, 3, 1], "Ties are broken by x, then y, then id, descending");
+        assert!(
+            result.windows(2).all(|pair| pair[0].sort_key() <= pair[1].sort_key()),
+            "Results are ordered by CoalesceContext::sort_key"
+        );
+    }
+    assert_eq!(single_result.len(), multi_result.len());
+    for (single, multi) in single_result.iter().zip(multi_result.iter()) {
+        assert_eq!(single.relev, 1.);
+        assert_eq!(multi.relev, 1.);
+    }
+}
+
+#[test]
+fn coalesce_tiebreak_seed_test() {
+    // two grids that tie on everything but their feature id
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 2, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 2, relev: 1., score: 1, source_phrase_hash: 0 },
+        ],
+    }]);
+    let stack = vec![exact_subquery(&store, 1, 1., 1, 2, 1 << 0)];
+    let ids = |tiebreak_seed| -> Vec<u32> {
+        let match_opts = MatchOpts { zoom: 2, tiebreak_seed, ..MatchOpts::default() };
+        coalesce(stack.clone(), &match_opts)
+            .unwrap()
+            .iter()
+            .map(|context| context.entries[0].grid_entry.id)
+            .collect()
+    };
+
+    assert_eq!(ids(None), [2, 1], "without a seed, ties are broken by id, descending");
+    assert_eq!(ids(Some(0)), [2, 1], "a zero seed changes nothing");
+    assert_eq!(ids(Some(3)), [1, 2], "a seed can swap tied results");
+    assert_eq!(ids(Some(3)), ids(Some(3)), "the same seed always gives the same order");
+}
+
+#[test]
+fn coalesce_scoredist_tiebreak_test() {
+    // both grids are well outside the proximity radius, where scoredist only depends on the score,
+    // and scores past 7 all give the same scoredist
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 500, y: 500, relev: 1., score: 12, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 501, y: 500, relev: 1., score: 7, source_phrase_hash: 0 },
+        ],
+    }]);
+    let stack = vec![exact_subquery(&store, 1, 1., 1, 14, 1 << 0)];
+    let coalesce_with = |scoredist_tiebreak| {
+        let match_opts = MatchOpts {
+            zoom: 14,
+            proximity: Some(Proximity { point: [1, 1], radius: 1. }),
+            scoredist_tiebreak,
+            ..MatchOpts::default()
+        };
+        coalesce(stack.clone(), &match_opts).unwrap()
+    };
+    let ids = |result: &Vec<CoalesceContext>| -> Vec<u32> {
+        result.iter().map(|context| context.entries[0].grid_entry.id).collect()
+    };
+
+    let by_position = coalesce_with(ScoreTiebreak::ByPosition);
+    assert_eq!(by_position[0].entries[0].scoredist, by_position[1].entries[0].scoredist);
+    assert_eq!(ids(&by_position), [2, 1], "by default, equal scoredists are ordered by x");
+    assert_eq!(ids(&by_position), ids(&coalesce_with(ScoreTiebreak::default())));
+
+    let by_score = coalesce_with(ScoreTiebreak::ByScore);
+    assert_eq!(ids(&by_score), [1, 2], "with ByScore, the higher score comes first");
+}
+
+#[test]
+fn coalesce_multi_same_zoom_test() {
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 2, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
+
+    // subqueries at the same zoom with different idx can stack
+    let stack = vec![
+        exact_subquery(&store1, 1, 0.5, 0, 14, 1 << 1),
+        exact_subquery(&store2, 2, 0.5, 1, 14, 1 << 0),
+    ];
+    let result = coalesce(stack, &match_opts).unwrap();
+    assert_eq!(result[0].entries.len(), 2, "Same-zoom subqueries stack");
+    assert_eq!(result[0].relev, 1.);
+
+    // but not if they share an idx
+    let stack = vec![
+        exact_subquery(&store1, 1, 0.5, 0, 14, 1 << 1),
+        exact_subquery(&store2, 2, 0.5, 0, 14, 1 << 0),
+    ];
+    let result = coalesce(stack, &match_opts).unwrap();
+    assert!(
+        result.iter().all(|context| context.entries.len() == 1),
+        "Subqueries with the same idx don't stack"
+    );
+}
+
+#[test]
+fn coalesce_multi_same_idx_across_zooms_test() {
+    let low_zoom_store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    // (256, 256) at z14 is inside (1, 1) at z6
+    let high_zoom_store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry {
+            id: 2,
+            x: 256,
+            y: 256,
+            relev: 1.,
+            score: 1,
+            source_phrase_hash: 0,
+        }],
+    }]);
+    let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
+
+    // a subquery can stack on a lower zoom with a different idx
+    let stack = vec![
+        exact_subquery(&low_zoom_store, 1, 0.5, 0, 6, 1 << 1),
+        exact_subquery(&high_zoom_store, 2, 0.5, 1, 14, 1 << 0),
+    ];
+    let result = coalesce(stack, &match_opts).unwrap();
+    assert_eq!(result[0].entries.len(), 2, "Different idx at a lower zoom stacks");
+
+    // but not on a lower zoom that only has its own idx
+    let stack = vec![
+        exact_subquery(&low_zoom_store, 1, 0.5, 0, 6, 1 << 1),
+        exact_subquery(&high_zoom_store, 2, 0.5, 0, 14, 1 << 0),
+    ];
+    let result = coalesce(stack, &match_opts).unwrap();
+    assert_eq!(result.len(), 2);
+    assert!(
+        result.iter().all(|context| context.entries.len() == 1),
+        "The same idx at a lower zoom doesn't stack"
+    );
+}
+
+#[test]
+fn coalesce_iter_test() {
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 0 },
+            GridEntry { id: 3, x: 5, y: 5, relev: 1., score: 5, source_phrase_hash: 0 },
+        ],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 4, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 5, x: 2, y: 1, relev: 1., score: 2, source_phrase_hash: 0 },
+        ],
+    }]);
+    let match_opts = MatchOpts {
+        zoom: 14,
+        proximity: Some(Proximity { point: [2, 2], radius: 40. }),
+        ..MatchOpts::default()
+    };
+
+    let stacks = vec![
+        vec![exact_subquery(&store1, 1, 0.5, 0, 14, 1 << 0)],
+        vec![
+            exact_subquery(&store1, 1, 0.5, 0, 14, 1 << 1),
+            exact_subquery(&store2, 2, 0.5, 1, 14, 1 << 0),
+        ],
+    ];
+    for stack in stacks {
+        let eager = coalesce(stack.clone(), &match_opts).unwrap();
+        let lazy: Vec<_> = coalesce_iter(stack, &match_opts).unwrap().collect();
+        assert!(!eager.is_empty());
+        assert_eq!(lazy, eager, "coalesce_iter yields the same contexts in the same order");
+    }
+}
+
+#[test]
+fn coalesce_grouped_test() {
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 0 },
+            GridEntry { id: 3, x: 5, y: 5, relev: 1., score: 5, source_phrase_hash: 0 },
+        ],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 4, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 5, x: 9, y: 9, relev: 1., score: 2, source_phrase_hash: 0 },
+        ],
+    }]);
+    // weighted so that store1's grids on their own stay within range of the stacked contexts
+    let stack = vec![
+        exact_subquery(&store1, 1, 0.8, 0, 14, 1 << 1),
+        exact_subquery(&store2, 2, 0.2, 1, 14, 1 << 0),
+    ];
+    let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
+
+    let all = coalesce(stack.clone(), &match_opts).unwrap();
+    let groups = coalesce_grouped(stack, &match_opts).unwrap();
+    assert!(groups.len() >= 2, "both stacked and unstacked interpretations are present");
+    assert!(groups.contains_key(&0b11));
+
+    assert_eq!(groups.values().map(|group| group.len()).sum::<usize>(), all.len());
+    for (mask, group) in groups.iter() {
+        let expected: Vec<_> = all.iter().filter(|c| c.mask == *mask).collect();
+        assert_eq!(
+            group.iter().collect::<Vec<_>>(),
+            expected,
+            "mask {} keeps the order of the full output",
+            mask
+        );
+    }
+}
+
+#[test]
+fn coalesce_relev_contributions_test() {
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 0 },
+        ],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(2) },
+        entries: vec![
+            GridEntry { id: 3, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 4, x: 2, y: 1, relev: 0.6, score: 2, source_phrase_hash: 0 },
+        ],
+    }]);
+    // store2's grids are in another language, so they come with a language penalty
+    let stack = vec![
+        exact_subquery(&store1, 1, 0.6, 0, 14, 1 << 1),
+        exact_subquery(&store2, 2, 0.4, 1, 14, 1 << 0),
+    ];
+
+    let unpenalized = MatchOpts { zoom: 14, apply_stack_penalties: false, ..MatchOpts::default() };
+    let contexts = coalesce(stack.clone(), &unpenalized).unwrap();
+    assert!(contexts.iter().any(|context| context.entries.len() > 1), "some results stacked");
+    for context in contexts.iter() {
+        let contributions = context.relev_contributions();
+        assert_eq!(contributions.len(), context.entries.len(), "one contribution per entry");
+        assert!(
+            (contributions.iter().sum::<f64>() - context.relev).abs() < 1e-9,
+            "contributions add up to the result's relevance"
+        );
+    }
+
+    let penalized = MatchOpts { zoom: 14, ..MatchOpts::default() };
+    for context in coalesce(stack, &penalized).unwrap().iter() {
+        let penalty = context.relev_contributions().iter().sum::<f64>() - context.relev;
+        assert!(
+            penalty.abs() < 1e-9 || (penalty - 0.01).abs() < 1e-9,
+            "contributions only differ from the relevance by the stacking penalty"
+        );
+    }
+}
+
+#[test]
+fn coalesce_serialize_contexts_test() {
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 2 },
+            GridEntry { id: 3, x: 5, y: 5, relev: 1., score: 5, source_phrase_hash: 1 },
+        ],
+    }]);
+    let stack = vec![exact_subquery(&store, 1, 1., 1, 14, 1 << 0)];
+    let match_opts = MatchOpts {
+        zoom: 14,
+        proximity: Some(Proximity { point: [2, 2], radius: 40. }),
+        ..MatchOpts::default()
+    };
+    let result = coalesce(stack, &match_opts).unwrap();
+    assert_eq!(result.len(), 3);
+
+    let encoded = serialize_contexts(&result);
+    let decoded = deserialize_contexts(&encoded).unwrap();
+    assert_eq!(decoded, result, "Contexts survive a bincode round trip");
+
+    assert!(deserialize_contexts(&encoded[..encoded.len() - 1]).is_err(), "Truncated data fails");
+}
+
+#[test]
+fn coalesce_radius_override_test() {
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry {
+            id: 1,
+            x: 10,
+            y: 10,
+            relev: 1.,
+            score: 1,
+            source_phrase_hash: 0,
+        }],
+    }]);
+    let subquery = |radius_override| PhrasematchSubquery {
+        radius_override,
+        ..exact_subquery(&store, 1, 1., 1, 14, 1 << 0)
+    };
+    let match_opts = MatchOpts {
+        zoom: 14,
+        proximity: Some(Proximity { point: [2, 2], radius: 40. }),
+        ..MatchOpts::default()
+    };
+
+    let default_radius = coalesce(vec![subquery(None)], &match_opts).unwrap();
+    let same_radius = coalesce(vec![subquery(Some(40.))], &match_opts).unwrap();
+    let overridden = coalesce(vec![subquery(Some(400.))], &match_opts).unwrap();
+    assert_eq!(default_radius, same_radius, "Overriding with the same radius changes nothing");
+    assert!(
+        overridden[0].entries[0].scoredist != default_radius[0].entries[0].scoredist,
+        "The override radius is used for scoredist"
+    );
+    assert_eq!(overridden[0].entries[0].distance, default_radius[0].entries[0].distance);
+
+    // with a second subquery, the override only applies to the one that sets it
+    let multi = |radius_override| {
+        let mut first = subquery(radius_override);
+        first.mask = 1 << 1;
+        first.idx = 0;
+        coalesce(vec![first, subquery(None)], &match_opts).unwrap()
+    };
+    let scoredist_for_idx = |contexts: &Vec<CoalesceContext>, idx| {
+        contexts[0].entries.iter().find(|entry| entry.idx == idx).unwrap().scoredist
+    };
+    let default_multi = multi(None);
+    let overridden_multi = multi(Some(400.));
+    assert!(scoredist_for_idx(&default_multi, 0) != scoredist_for_idx(&overridden_multi, 0));
+    assert_eq!(scoredist_for_idx(&default_multi, 1), scoredist_for_idx(&overridden_multi, 1));
+}
+
+#[test]
+fn coalesce_dedup_by_feature_test() {
+    // the same feature, id 7, is in two indexes; id 8 is only in the second
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 7, x: 1, y: 1, relev: 1., score: 3, source_phrase_hash: 0 }],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 7, x: 1, y: 1, relev: 0.8, score: 3, source_phrase_hash: 0 },
+            GridEntry { id: 8, x: 5, y: 5, relev: 0.8, score: 1, source_phrase_hash: 0 },
+        ],
+    }]);
+    let stack = vec![
+        exact_subquery(&store1, 1, 1., 0, 14, 1 << 0),
+        exact_subquery(&store2, 1, 1., 1, 14, 1 << 0),
+    ];
+
+    let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
+    let result = coalesce(stack.clone(), &match_opts).unwrap();
+    let ids: Vec<_> = result.iter().map(|context| context.entries[0].grid_entry.id).collect();
+    assert_eq!(ids, vec![7, 7, 8], "Without the flag each index's copy is a separate result");
+
+    let match_opts = MatchOpts { zoom: 14, dedup_by_feature: true, ..MatchOpts::default() };
+    let result = coalesce(stack, &match_opts).unwrap();
+    let ids: Vec<_> = result.iter().map(|context| context.entries[0].grid_entry.id).collect();
+    assert_eq!(ids, vec![7, 8], "With the flag the feature only comes back once");
+    assert_eq!(result[0].entries[0].idx, 0, "The most relevant copy is the one kept");
+    assert_eq!(result[0].relev, 1.);
+}
+
+#[test]
+fn coalesce_max_per_feature_test() {
+    // features 1 and 2 have many grids in both indexes; feature 3 is only in the second
+    let stores: Vec<_> = (0..2)
+        .map(|store_num| {
+            let mut entries = Vec::new();
+            for id in 1..=2 {
+                for i in 0..5 {
+                    entries.push(GridEntry {
+                        id,
+                        x: i,
+                        y: id as u16,
+                        relev: 1.,
+                        score: 3,
+                        source_phrase_hash: 0,
+                    });
+                }
+            }
+            if store_num == 1 {
+                entries.push(GridEntry {
+                    id: 3,
+                    x: 9,
+                    y: 9,
+                    relev: 1.,
+                    score: 1,
+                    source_phrase_hash: 0,
+                });
+            }
+            create_store(vec![StoreEntryBuildingBlock {
+                grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+                entries,
+            }])
+        })
+        .collect();
+    let stack: Vec<_> = stores
+        .iter()
+        .enumerate()
+        .map(|(idx, store)| exact_subquery(store, 1, 1., idx as u16, 14, 1 << 0))
+        .collect();
+    let feature_counts = |max_per_feature| {
+        let match_opts = MatchOpts { zoom: 14, max_per_feature, ..MatchOpts::default() };
+        let mut counts = std::collections::BTreeMap::new();
+        for context in coalesce(stack.clone(), &match_opts).unwrap() {
+            *counts.entry(context.entries[0].grid_entry.id).or_insert(0) += 1;
+        }
+        counts.into_iter().collect::<Vec<_>>()
+    };
+
+    assert_eq!(feature_counts(None), vec![(1, 2), (2, 2), (3, 1)], "one result per index");
+    assert_eq!(feature_counts(Some(2)), feature_counts(None));
+    assert_eq!(feature_counts(Some(1)), vec![(1, 1), (2, 1), (3, 1)], "every feature once");
+}
+
+#[test]
+fn coalesce_min_relev_test() {
+    let entries = [(1, 1.), (2, 0.8), (3, 1.), (4, 0.8)]
+        .iter()
+        .map(|&(id, relev)| GridEntry {
+            id,
+            x: id as u16,
+            y: 1,
+            relev,
+            score: 1,
+            source_phrase_hash: 0,
+        })
+        .collect();
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries,
+    }]);
+    let subquery = exact_subquery(&store, 1, 1., 0, 14, 1 << 0);
+    let relevs = |min_relev| -> Vec<(u32, f64)> {
+        let match_opts = MatchOpts { zoom: 14, min_relev, ..MatchOpts::default() };
+        coalesce(vec![subquery.clone()], &match_opts)
+            .unwrap()
+            .iter()
+            .map(|context| (context.entries[0].grid_entry.id, context.relev))
+            .collect()
+    };
+
+    assert_eq!(relevs(None), vec![(3, 1.), (1, 1.), (4, 0.8), (2, 0.8)]);
+    assert_eq!(relevs(Some(0.9)), vec![(3, 1.), (1, 1.)], "results below the floor are dropped");
+    assert_eq!(relevs(Some(1.)), relevs(Some(0.9)), "results at the floor are kept");
+    assert_eq!(relevs(Some(1.1)), vec![], "a floor above the best result drops everything");
+}
+
+#[test]
+fn coalesce_dedup_by_source_phrase_test() {
+    // the same feature, matched through two different source phrases
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1.0, score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 1, x: 1, y: 1, relev: 0.8, score: 1, source_phrase_hash: 2 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.8, score: 1, source_phrase_hash: 0 },
+        ],
+    }]);
+    let subquery = exact_subquery(&store, 1, 1., 0, 14, 1 << 0);
+    let results = |dedup_by_source_phrase| -> Vec<(u32, u8, f64)> {
+        let match_opts = MatchOpts { zoom: 14, dedup_by_source_phrase, ..MatchOpts::default() };
+        coalesce(vec![subquery.clone()], &match_opts)
+            .unwrap()
+            .iter()
+            .map(|context| {
+                let grid = &context.entries[0].grid_entry;
+                (grid.id, grid.source_phrase_hash, context.relev)
+            })
+            .collect()
+    };
+
+    assert_eq!(results(false), vec![(1, 0, 1.), (2, 0, 0.8)], "one result per feature");
+    assert_eq!(
+        results(true),
+        vec![(1, 0, 1.), (2, 0, 0.8), (1, 2, 0.8)],
+        "both source phrases of feature 1 survive"
+    );
+
+    // with more than one subquery the flag is ignored
+    let other_store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 3, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let stack = vec![
+        exact_subquery(&other_store, 2, 0.5, 0, 14, 1 << 0),
+        exact_subquery(&store, 1, 0.5, 1, 14, 1 << 1),
+    ];
+    let multi_results = |dedup_by_source_phrase| -> Vec<(u32, u8)> {
+        let match_opts = MatchOpts { zoom: 14, dedup_by_source_phrase, ..MatchOpts::default() };
+        coalesce(stack.clone(), &match_opts)
+            .unwrap()
+            .iter()
+            .map(|context| {
+                let grid = &context.entries[0].grid_entry;
+                (grid.id, grid.source_phrase_hash)
+            })
+            .collect()
+    };
+    assert_eq!(multi_results(true), multi_results(false));
+    assert_eq!(
+        multi_results(true).iter().filter(|(id, _)| *id == 1).count(),
+        1,
+        "one result for feature 1"
+    );
+}
+
+#[test]
+fn coalesce_stack_penalties_test() {
+    // the second subquery's grid has nothing to stack on
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 2, x: 5, y: 5, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let stack: Vec<_> = [(&store1, 1), (&store2, 2)]
+        .iter()
+        .enumerate()
+        .map(|(idx, &(store, phrase_id))| {
+            exact_subquery(store, phrase_id, 0.5, idx as u16, 14, 1 << idx)
+        })
+        .collect();
+    let relev_of_feature_2 = |apply_stack_penalties| {
+        let match_opts = MatchOpts { zoom: 14, apply_stack_penalties, ..MatchOpts::default() };
+        let contexts = coalesce(stack.clone(), &match_opts).unwrap();
+        let context = contexts.iter().find(|c| c.entries[0].grid_entry.id == 2).unwrap();
+        assert_eq!(context.entries.len(), 1);
+        context.relev
+    };
+
+    assert_eq!(MatchOpts::default().apply_stack_penalties, true, "penalties apply by default");
+    assert_eq!(relev_of_feature_2(false), 0.5);
+    assert!((relev_of_feature_2(true) - 0.49).abs() < 1e-9, "unstacked contexts lose 0.01");
+}
+
+#[test]
+fn coalesce_wide_id_layout_test() {
+    let store1 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 7, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let store2 = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 9, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    // the second index is past the 127 the default layout can hold
+    let stack: Vec<_> = [(&store1, 1, 3u16), (&store2, 2, 200u16)]
+        .iter()
+        .enumerate()
+        .map(|(i, &(store, phrase_id, idx))| exact_subquery(store, phrase_id, 0.5, idx, 14, 1 << i))
+        .collect();
+    let layout = IdLayout::new(8);
+    let match_opts = MatchOpts { zoom: 14, id_layout: layout, ..MatchOpts::default() };
+    let contexts = coalesce(stack, &match_opts).unwrap();
+
+    let context = &contexts[0];
+    assert_eq!(context.entries.len(), 2, "the two subqueries stack");
+    let decoded: Vec<_> = context.entries.iter().map(|e| layout.decode(e.tmp_id)).collect();
+    assert!(decoded.contains(&(200, 9)), "idx 200 round trips through tmp_id");
+    assert!(decoded.contains(&(3, 7)));
+    for entry in context.entries.iter() {
+        assert_eq!(layout.decode(entry.tmp_id).0, entry.idx);
+    }
+}
+
+#[test]
+fn coalesce_truncation_test() {
+    let dense_entries: Vec<_> = (0..(MAX_GRIDS_PER_PHRASE as u32 + 1))
+        .map(|id| GridEntry {
+            id,
+            x: (id % 1000) as u16,
+            y: (id / 1000) as u16,
+            relev: 1.,
+            score: 1,
+            source_phrase_hash: 0,
+        })
+        .collect();
+    let dense_store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: dense_entries,
+    }]);
+    let sparse_store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 1, x: 0, y: 0, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
+
+    let (contexts, stats) = coalesce_with_stats(
+        vec![
+            exact_subquery(&dense_store, 1, 0.5, 0, 14, 1 << 0),
+            exact_subquery(&sparse_store, 1, 0.5, 1, 14, 1 << 1),
+        ],
+        &match_opts,
+    )
+    .unwrap();
+    assert!(!contexts.is_empty());
+    assert!(stats.truncated, "more than MAX_GRIDS_PER_PHRASE grids matched");
+
+    let (_, stats) = coalesce_with_stats(
+        vec![
+            exact_subquery(&sparse_store, 1, 0.5, 0, 14, 1 << 0),
+            exact_subquery(&sparse_store, 1, 0.5, 1, 14, 1 << 1),
+        ],
+        &match_opts,
+    )
+    .unwrap();
+    assert!(!stats.truncated);
+}
+
+#[test]
+fn coalesce_histogram_test() {
+    // six features: three at relevance 1, two at 0.8 and one at 0.6, more than 0.25 below the best
+    let relevs = [1., 1., 1., 0.8, 0.8, 0.6];
+    let entries: Vec<_> = relevs
+        .iter()
+        .enumerate()
+        .map(|(i, relev)| GridEntry {
+            id: i as u32 + 1,
+            x: i as u16,
+            y: 1,
+            relev: *relev,
+            score: 1,
+            source_phrase_hash: 0,
+        })
+        .collect();
+    let store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries,
+    }]);
+    let subquery = exact_subquery(&store, 1, 1., 1, 14, 1 << 0);
+    let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
+
+    let (contexts, histogram) =
+        coalesce_with_histogram(vec![subquery.clone()], &match_opts).unwrap();
+    assert_eq!(contexts, coalesce(vec![subquery], &match_opts).unwrap());
+    assert_eq!(contexts.len(), 5, "the 0.6 grid is cut");
+    let counts: Vec<_> = histogram.iter().map(|(_, count)| *count).collect();
+    assert_eq!(counts, vec![1, 2, 3], "every candidate is counted, cut or not");
+    assert_eq!(histogram.iter().map(|(_, count)| count).sum::<usize>(), relevs.len());
+    assert!(histogram.windows(2).all(|pair| pair[0].0 < pair[1].0), "buckets ascend");
+    assert!((histogram[2].0 - 1.).abs() < HISTOGRAM_BUCKET_SIZE);
+}
+
+#[test]
+fn coalesce_multi_histogram_test() {
+    let region_store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 1, lang_set: LangSet::from(1) },
+        entries: vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }],
+    }]);
+    // (256, 256) at z14 is inside (1, 1) at z6, (0, 0) isn't
+    let street_store = create_store(vec![StoreEntryBuildingBlock {
+        grid_key: GridKey { phrase_id: 2, lang_set: LangSet::from(1) },
+        entries: vec![
+            GridEntry { id: 2, x: 256, y: 256, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 3, x: 0, y: 0, relev: 1., score: 1, source_phrase_hash: 0 },
+        ],
+    }]);
+    let stack = vec![
+        exact_subquery(&region_store, 1, 0.5, 0, 6, 1 << 1),
+        exact_subquery(&street_store, 2, 0.5, 1, 14, 1 << 0),
+    ];
+    let match_opts = MatchOpts { zoom: 14, ..MatchOpts::default() };
+
+    let (contexts, histogram) = coalesce_with_histogram(stack.clone(), &match_opts).unwrap();
+    assert_eq!(contexts, coalesce(stack, &match_opts).unwrap());
+    assert_eq!(contexts.len(), 1, "only the stacked street is close enough to the best");
+    assert_eq!(
+        histogram.iter().map(|(_, count)| count).sum::<usize>(),
+        3,
+        "candidates are the stacked street, the lone street and the region on its own"
+    );
+    assert!((histogram.last().unwrap().0 - 1.).abs() < HISTOGRAM_BUCKET_SIZE);
+}
@@ -0,0 +1,72 @@
+use std::io::Cursor;
+use std::sync::atomic::AtomicBool;
+
+use carmen_core::gridstore::*;
+use test_utils::*;
+
+const GRID_LINES: &str = r#"{"grid_key":{"phrase_id":1,"lang_set":1},"entries":[{"relev":1.0,"score":1,"x":1,"y":1,"id":1,"source_phrase_hash":0}]}
+{"grid_key":{"phrase_id":2,"lang_set":1},"entries":[{"relev":1.0,"score":
+{"grid_key":{"phrase_id":3,"lang_set":1},"entries":[{"relev":1.0,"score":3,"x":3,"y":3,"id":3,"source_phrase_hash":0}]}
+"#;
+
+#[test]
+fn load_skipping_malformed_lines_test() {
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let store_path = directory.path().to_str().unwrap();
+    let mut reports = Vec::new();
+    let mut report = |progress| reports.push(progress);
+    let opts = LoadOptions {
+        malformed_lines: MalformedLines::Skip,
+        progress: Some(&mut report),
+        progress_interval: 2,
+        ..LoadOptions::default()
+    };
+
+    let progress =
+        load_db_from_json_reader_with(Cursor::new(GRID_LINES), None, store_path, opts).unwrap();
+    assert_eq!(progress, LoadProgress { lines_read: 3, records_loaded: 2, lines_skipped: 1 });
+    assert_eq!(reports.len(), 2, "a report after two lines, and one at the end");
+    assert_eq!(reports[0].lines_read, 2);
+    assert_eq!(reports[1], progress);
+
+    let reader = GridStore::new(store_path).unwrap();
+    let phrase_ids: Vec<u32> = reader.keys().map(|key| key.unwrap().phrase_id).collect();
+    assert_eq!(phrase_ids, [1, 3], "everything but the malformed line is loaded");
+}
+
+#[test]
+fn load_malformed_line_error_test() {
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let store_path = directory.path().to_str().unwrap();
+
+    let err = load_db_from_json_reader_with(
+        Cursor::new(GRID_LINES),
+        None,
+        store_path,
+        LoadOptions::default(),
+    )
+    .unwrap_err();
+    match err.downcast_ref::<LoadError>() {
+        Some(LoadError::MalformedLine { line, .. }) => assert_eq!(*line, 2),
+        other => panic!("expected a malformed line error, got {:?}", other),
+    }
+}
+
+#[test]
+fn load_cancelled_test() {
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let cancel = AtomicBool::new(true);
+    let opts = LoadOptions { cancel: Some(&cancel), ..LoadOptions::default() };
+
+    let err = load_db_from_json_reader_with(
+        Cursor::new(GRID_LINES),
+        None,
+        directory.path().to_str().unwrap(),
+        opts,
+    )
+    .unwrap_err();
+    match err.downcast_ref::<LoadError>() {
+        Some(LoadError::Cancelled { lines_read }) => assert_eq!(*lines_read, 0),
+        other => panic!("expected a cancellation, got {:?}", other),
+    }
+}